@@ -0,0 +1,336 @@
+use crate::audit::{Audit, Outcome};
+use crate::commands::{split_word, string_option, Command, Invocation, Reply};
+use crate::config::Config;
+use crate::locale::Locale;
+use serenity::all::{CommandOptionType, ResolvedOption, ResolvedValue, UserId};
+use serenity::async_trait;
+use serenity::builder::{CreateCommandOption, CreateEmbed};
+use serenity::prelude::TypeMapKey;
+use std::collections::BTreeSet;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// Users who may run anything anywhere, from `discord.owners` in the config.
+#[derive(Clone, Default)]
+pub struct Owners {
+    ids: Arc<RwLock<BTreeSet<UserId>>>,
+}
+
+impl TypeMapKey for Owners {
+    type Value = Owners;
+}
+
+impl Owners {
+    pub fn new(ids: &[u64]) -> Self {
+        let owners = Owners::default();
+        owners.replace(ids);
+        owners
+    }
+
+    pub fn contains(&self, user: UserId) -> bool {
+        self.ids.read().unwrap().contains(&user)
+    }
+
+    fn replace(&self, ids: &[u64]) {
+        let ids = ids.iter().filter(|&&id| id != 0).map(|&id| UserId::new(id));
+        *self.ids.write().unwrap() = ids.collect();
+    }
+
+    fn len(&self) -> usize {
+        self.ids.read().unwrap().len()
+    }
+}
+
+/// Maintenance commands for bot owners and server admins.
+pub struct AdminCommand {
+    started: Instant,
+}
+
+/// Counts uptime from when the command is created, i.e. startup.
+impl Default for AdminCommand {
+    fn default() -> Self {
+        AdminCommand {
+            started: Instant::now(),
+        }
+    }
+}
+
+pub enum AdminArgs {
+    Status,
+    ReloadReasons,
+    ReloadConfig,
+    ClearChess,
+    /// The user's latest commands in this server.
+    Audit(UserId),
+}
+
+/// How many audit log entries `puppy admin audit` shows.
+const AUDIT_ENTRIES: usize = 15;
+
+/// Parses `<@123>`, `<@!123>` or a bare user id.
+fn parse_user(s: &str) -> Option<UserId> {
+    let id = s
+        .strip_prefix("<@")
+        .and_then(|s| s.strip_suffix('>'))
+        .map(|s| s.trim_start_matches('!'))
+        .unwrap_or(s);
+    id.parse().ok().filter(|&id| id != 0).map(UserId::new)
+}
+
+/// Formats `uptime` as e.g. `2d 3h 4m`.
+fn format_uptime(uptime: Duration) -> String {
+    let minutes = uptime.as_secs() / 60;
+    let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+    if days > 0 {
+        format!("{days}d {hours}h {minutes}m")
+    } else if hours > 0 {
+        format!("{hours}h {minutes}m")
+    } else {
+        format!("{minutes}m")
+    }
+}
+
+#[async_trait]
+impl Command for AdminCommand {
+    type Args = AdminArgs;
+
+    fn name(&self) -> &'static str {
+        "admin"
+    }
+
+    fn description(&self, locale: Locale) -> String {
+        locale.text("description-admin", &[])
+    }
+
+    fn usage(&self) -> &'static str {
+        "[status|reload reasons|reload config|clear chess|audit @user]"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &[
+            "",
+            "reload reasons",
+            "clear chess",
+            "audit <@123456789012345678>",
+        ]
+    }
+
+    fn parse(&self, args: &str) -> Option<AdminArgs> {
+        let (action, target) = split_word(args);
+        match (
+            action.to_lowercase().as_str(),
+            target.to_lowercase().as_str(),
+        ) {
+            ("" | "status", "") => Some(AdminArgs::Status),
+            ("reload", "reasons") => Some(AdminArgs::ReloadReasons),
+            ("reload", "config") => Some(AdminArgs::ReloadConfig),
+            ("clear", "chess") => Some(AdminArgs::ClearChess),
+            ("audit", user) => parse_user(user).map(AdminArgs::Audit),
+            _ => None,
+        }
+    }
+
+    fn options(&self) -> Vec<CreateCommandOption> {
+        let mut action = CreateCommandOption::new(
+            CommandOptionType::String,
+            "action",
+            Locale::En.text("option-admin-action", &[]),
+        );
+        for choice in ["status", "reload", "clear", "audit"] {
+            action = action.add_string_choice(choice, choice);
+        }
+        let mut target = CreateCommandOption::new(
+            CommandOptionType::String,
+            "target",
+            Locale::En.text("option-admin-target", &[]),
+        );
+        for choice in ["reasons", "config", "chess"] {
+            target = target.add_string_choice(choice, choice);
+        }
+        let user = CreateCommandOption::new(
+            CommandOptionType::User,
+            "user",
+            Locale::En.text("option-admin-user", &[]),
+        );
+        vec![action, target, user]
+    }
+
+    fn parse_options(&self, options: &[ResolvedOption]) -> Option<AdminArgs> {
+        let action = string_option(options, "action").unwrap_or_default();
+        let user = options.iter().find_map(|option| match option.value {
+            ResolvedValue::User(user, _) => Some(user.id),
+            _ => None,
+        });
+        match user {
+            Some(user) if action == "audit" => Some(AdminArgs::Audit(user)),
+            _ => {
+                let target = string_option(options, "target").unwrap_or_default();
+                self.parse(&format!("{action} {target}"))
+            }
+        }
+    }
+
+    async fn run(&self, inv: &Invocation<'_>, args: AdminArgs) -> Vec<Reply> {
+        // Reloads affect every server, so only owners may do them.
+        let allowed = match args {
+            AdminArgs::ReloadReasons | AdminArgs::ReloadConfig => inv.is_owner().await,
+            AdminArgs::Status | AdminArgs::ClearChess | AdminArgs::Audit(_) => inv.is_admin().await,
+        };
+        if !allowed {
+            return vec![Reply::text(inv.locale.error("not-allowed", &[]))];
+        }
+
+        let reply = match args {
+            AdminArgs::Status => self.status(inv.locale),
+            AdminArgs::ReloadReasons => reload_reasons(inv).await,
+            AdminArgs::ReloadConfig => match reload_config(inv).await {
+                Ok(owners) => inv
+                    .locale
+                    .text("admin-config-reloaded", &[("owners", &owners)]),
+                Err(why) => {
+                    tracing::error!("Error reloading config: {why:?}");
+                    inv.locale.error("admin-config-broken", &[])
+                }
+            },
+            AdminArgs::ClearChess => clear_chess(inv).await,
+            AdminArgs::Audit(user) => return vec![audit(inv, user).await],
+        };
+        vec![Reply::text(reply)]
+    }
+}
+
+impl AdminCommand {
+    fn status(&self, locale: Locale) -> String {
+        #[cfg_attr(not(feature = "gpt"), allow(unused_mut))]
+        let mut status = locale.text(
+            "admin-status",
+            &[
+                ("version", &env!("CARGO_PKG_VERSION")),
+                ("uptime", &format_uptime(self.started.elapsed())),
+            ],
+        );
+        #[cfg(feature = "gpt")]
+        {
+            let count = crate::puppygpt::available_emoji_count();
+            status.push('\n');
+            status.push_str(&locale.text("admin-emojis", &[("count", &count)]));
+        }
+        status
+    }
+}
+
+/// The latest commands `user` ran in this server, in an embed so that the
+/// mentions don't ping anyone.
+async fn audit(inv: &Invocation<'_>, user: UserId) -> Reply {
+    let audit = inv.shared::<Audit>().await;
+    let entries = match audit.recent(user, inv.guild_id, AUDIT_ENTRIES).await {
+        Ok(entries) => entries,
+        Err(why) => {
+            tracing::error!("Error reading audit log: {why:?}");
+            return Reply::text(inv.locale.error("admin-audit-failed", &[]));
+        }
+    };
+    let mention = format!("<@{user}>");
+    if entries.is_empty() {
+        let none = inv.locale.text("admin-audit-none", &[("user", &mention)]);
+        return Reply::embed(CreateEmbed::new().description(none));
+    }
+    let mut description = inv.locale.text("admin-audit", &[("user", &mention)]);
+    for entry in entries {
+        let outcome = match entry.outcome {
+            Outcome::Ok => "✅",
+            Outcome::Failed => "❌",
+            Outcome::RateLimited => "⏳",
+            Outcome::Denied => "🚫",
+        };
+        // Backticks would end the code span early.
+        let mut command = format!("{} {}", entry.command, entry.args).replace('`', "'");
+        if command.chars().count() > 80 {
+            command = command.chars().take(79).chain(['…']).collect();
+        }
+        description.push_str(&format!(
+            "\n{outcome} <t:{}:f> <#{}> `{}`",
+            entry.time.timestamp(),
+            entry.channel,
+            command.trim_end(),
+        ));
+    }
+    Reply::embed(CreateEmbed::new().description(description))
+}
+
+/// Applies the owners, `[audit]` and `[http]` from a fresh read of the
+/// config, returning how many owners there are now. Nothing changes if any
+/// of them is invalid. Everything else is only read at startup.
+async fn reload_config(inv: &Invocation<'_>) -> anyhow::Result<usize> {
+    let config = Config::load()?;
+    let audit = config.audit.open()?;
+    let upstream = config.http.open()?;
+
+    let owners = inv.shared::<Owners>().await;
+    owners.replace(&config.discord.owners);
+    let mut data = inv.data.write().await;
+    data.insert::<Audit>(audit);
+    data.insert::<crate::upstream::Upstream>(upstream);
+    Ok(owners.len())
+}
+
+#[cfg(feature = "why")]
+async fn reload_reasons(inv: &Invocation<'_>) -> String {
+    let reasons = inv.shared::<crate::puppywhy::NoReasons>().await;
+    match reasons.reload() {
+        Ok(count) => inv
+            .locale
+            .text("admin-reasons-reloaded", &[("count", &count)]),
+        Err(why) => {
+            tracing::error!("Error reloading reasons: {why:?}");
+            inv.locale.error("admin-reasons-broken", &[])
+        }
+    }
+}
+
+#[cfg(not(feature = "why"))]
+async fn reload_reasons(inv: &Invocation<'_>) -> String {
+    inv.locale.error("admin-not-built", &[("command", &"no")])
+}
+
+#[cfg(feature = "chess")]
+async fn clear_chess(inv: &Invocation<'_>) -> String {
+    match crate::puppychess::clear(inv.data, inv.channel_id).await {
+        Ok(true) => inv.locale.text("admin-chess-cleared", &[]),
+        Ok(false) => inv.locale.text("admin-chess-none", &[]),
+        Err(why) => {
+            tracing::error!("Error clearing chess game: {why:?}");
+            inv.locale.error("admin-chess-failed", &[])
+        }
+    }
+}
+
+#[cfg(not(feature = "chess"))]
+async fn clear_chess(inv: &Invocation<'_>) -> String {
+    inv.locale
+        .error("admin-not-built", &[("command", &"chess")])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_users() {
+        assert_eq!(parse_user("<@42>"), Some(UserId::new(42)));
+        assert_eq!(parse_user("<@!42>"), Some(UserId::new(42)));
+        assert_eq!(parse_user("42"), Some(UserId::new(42)));
+        assert_eq!(parse_user("<#42>"), None);
+        assert_eq!(parse_user("0"), None);
+    }
+
+    #[test]
+    fn formats_uptime() {
+        assert_eq!(format_uptime(Duration::from_secs(59)), "0m");
+        assert_eq!(format_uptime(Duration::from_secs(3 * 3600 + 120)), "3h 2m");
+        assert_eq!(
+            format_uptime(Duration::from_secs(2 * 86400 + 3600 + 60)),
+            "2d 1h 1m"
+        );
+    }
+}
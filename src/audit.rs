@@ -0,0 +1,422 @@
+use crate::scheduler::{Job, JobContext, JobHandler};
+use chrono::{DateTime, NaiveDate, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serenity::all::{GuildId, UserId};
+use serenity::async_trait;
+use serenity::prelude::TypeMapKey;
+use std::fs::{File, OpenOptions};
+use std::io::{Read as _, Write as _};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+/// Name of the file being written. Older ones are renamed with the time
+/// they were rotated, e.g. `audit-20261018T120000123456789.jsonl`.
+const CURRENT: &str = "audit.jsonl";
+
+const REDACTED: &str = "[redacted]";
+
+/// Kind of the daily scheduler job that deletes expired files, which
+/// otherwise only happens when the log rotates.
+pub const PRUNE_JOB: &str = "audit-prune";
+
+/// One command someone ran, as a line of the audit log.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Entry {
+    pub time: DateTime<Utc>,
+    pub guild: Option<u64>,
+    pub channel: u64,
+    pub user: u64,
+    pub command: String,
+    pub args: String,
+    /// `message`, `slash` or `component`.
+    pub source: String,
+    pub outcome: Outcome,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Ok,
+    /// The command ran but replied with an error.
+    Failed,
+    RateLimited,
+    /// Disabled on the server or in the channel.
+    Denied,
+}
+
+/// What to leave out of the log.
+#[derive(Default)]
+pub struct Redaction {
+    /// Commands whose arguments are never written.
+    pub commands: Vec<String>,
+    /// Replaced wherever they match in arguments.
+    pub patterns: Vec<Regex>,
+}
+
+impl Redaction {
+    fn apply(&self, command: &str, args: &str) -> String {
+        if self.commands.iter().any(|c| c == command) {
+            return REDACTED.to_string();
+        }
+        let mut args = args.to_string();
+        for pattern in &self.patterns {
+            args = pattern.replace_all(&args, REDACTED).into_owned();
+        }
+        args
+    }
+}
+
+/// An append-only record of who ran which command, where and how it went,
+/// for settling moderation disputes.
+#[derive(Clone)]
+pub struct Audit {
+    inner: Arc<Inner>,
+}
+
+impl TypeMapKey for Audit {
+    type Value = Audit;
+}
+
+struct Inner {
+    sink: Sink,
+    redaction: Redaction,
+}
+
+enum Sink {
+    /// Nothing is recorded.
+    Off,
+    #[cfg(test)]
+    Memory(Mutex<Vec<Entry>>),
+    Files(Arc<Mutex<Files>>),
+}
+
+/// JSON lines under `dir`, in a file that's rotated daily or once it
+/// reaches `max_bytes`. Rotated files are deleted after `retention`, or
+/// kept forever without one.
+struct Files {
+    dir: PathBuf,
+    max_bytes: u64,
+    retention: Option<Duration>,
+    current: Option<Current>,
+}
+
+struct Current {
+    file: File,
+    bytes: u64,
+    /// The day it was started, in UTC.
+    day: NaiveDate,
+}
+
+impl Audit {
+    fn new(sink: Sink, redaction: Redaction) -> Self {
+        Audit {
+            inner: Arc::new(Inner { sink, redaction }),
+        }
+    }
+
+    pub fn off() -> Self {
+        Audit::new(Sink::Off, Redaction::default())
+    }
+
+    /// Entries that only last as long as the process.
+    #[cfg(test)]
+    pub fn memory(redaction: Redaction) -> Self {
+        Audit::new(Sink::Memory(Mutex::default()), redaction)
+    }
+
+    pub fn files(
+        dir: impl Into<PathBuf>,
+        max_bytes: u64,
+        retention: Option<Duration>,
+        redaction: Redaction,
+    ) -> Self {
+        let files = Files {
+            dir: dir.into(),
+            max_bytes,
+            retention,
+            current: None,
+        };
+        Audit::new(Sink::Files(Arc::new(Mutex::new(files))), redaction)
+    }
+
+    /// Appends `entry` after redacting its arguments. Failing to write is
+    /// logged, and never stops the command.
+    pub fn record(&self, mut entry: Entry) {
+        entry.args = self.inner.redaction.apply(&entry.command, &entry.args);
+        match &self.inner.sink {
+            Sink::Off => {}
+            #[cfg(test)]
+            Sink::Memory(entries) => entries.lock().unwrap().push(entry),
+            Sink::Files(files) => {
+                if let Err(why) = files.lock().unwrap().append(&entry) {
+                    tracing::error!("Error writing audit log: {why:?}");
+                }
+            }
+        }
+    }
+
+    /// Deletes rotated files older than the retention.
+    pub fn delete_expired(&self) -> anyhow::Result<()> {
+        match &self.inner.sink {
+            Sink::Files(files) => files.lock().unwrap().delete_expired(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Up to `limit` of the latest entries by `user` in `guild`, newest
+    /// first. `None` means direct messages.
+    pub async fn recent(
+        &self,
+        user: UserId,
+        guild: Option<GuildId>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Entry>> {
+        let matches =
+            move |entry: &Entry| entry.user == user.get() && entry.guild == guild.map(|g| g.get());
+        match &self.inner.sink {
+            Sink::Off => Ok(Vec::new()),
+            #[cfg(test)]
+            Sink::Memory(entries) => {
+                let entries = entries.lock().unwrap();
+                Ok(entries
+                    .iter()
+                    .rev()
+                    .filter(|e| matches(e))
+                    .take(limit)
+                    .cloned()
+                    .collect())
+            }
+            Sink::Files(files) => {
+                let files = files.clone();
+                tokio::task::spawn_blocking(move || {
+                    // Only long enough to open the files, so that commands
+                    // can be recorded while they're read.
+                    let opened = files.lock().unwrap().open_newest_first()?;
+                    read_recent(opened, limit, matches)
+                })
+                .await?
+            }
+        }
+    }
+}
+
+/// Up to `limit` entries that `matches` from `files`, newest first. Files
+/// after the one that fills the limit are never read.
+fn read_recent(
+    files: Vec<(PathBuf, File)>,
+    limit: usize,
+    matches: impl Fn(&Entry) -> bool,
+) -> anyhow::Result<Vec<Entry>> {
+    let mut found = Vec::new();
+    for (path, mut file) in files {
+        if found.len() >= limit {
+            break;
+        }
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        for line in contents.lines().rev() {
+            match serde_json::from_str::<Entry>(line) {
+                Ok(entry) if matches(&entry) => found.push(entry),
+                Ok(_) => {}
+                // A line cut short by a crash.
+                Err(why) => tracing::warn!(?path, "Skipping audit line: {why}"),
+            }
+            if found.len() >= limit {
+                break;
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Deletes the expired files of the [`Audit`] in use, for [`PRUNE_JOB`].
+pub struct Prune;
+
+#[async_trait]
+impl JobHandler for Prune {
+    async fn run(&self, ctx: &JobContext, _job: &Job) -> anyhow::Result<()> {
+        let audit = ctx.data.read().await.get::<Audit>().cloned();
+        match audit {
+            Some(audit) => tokio::task::spawn_blocking(move || audit.delete_expired()).await?,
+            None => Ok(()),
+        }
+    }
+}
+
+impl Files {
+    fn append(&mut self, entry: &Entry) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        let today = entry.time.date_naive();
+        let full = |current: &Current| {
+            current.day != today || current.bytes + line.len() as u64 > self.max_bytes
+        };
+        if self.current.as_ref().is_none_or(full) {
+            self.current = None;
+            self.open(today, line.len() as u64)?;
+        }
+        let current = self.current.as_mut().expect("opened above");
+        current.file.write_all(&line)?;
+        current.bytes += line.len() as u64;
+        Ok(())
+    }
+
+    /// Opens the current file for appending, first rotating it if it's
+    /// from an earlier day or has no room for `needed` more bytes.
+    fn open(&mut self, today: NaiveDate, needed: u64) -> anyhow::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let path = self.dir.join(CURRENT);
+        if let Ok(metadata) = std::fs::metadata(&path) {
+            let day = DateTime::<Utc>::from(metadata.modified()?).date_naive();
+            // Empty files don't need rotating, e.g. after a failed write.
+            if metadata.len() > 0 && (day != today || metadata.len() + needed > self.max_bytes) {
+                let rotated = format!("audit-{}.jsonl", Utc::now().format("%Y%m%dT%H%M%S%9f"));
+                std::fs::rename(&path, self.dir.join(rotated))?;
+            }
+        }
+        // Old files piling up mustn't stop new entries from being written.
+        if let Err(why) = self.delete_expired() {
+            tracing::error!("Error deleting expired audit logs: {why:?}");
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        self.current = Some(Current {
+            bytes: file.metadata()?.len(),
+            file,
+            day: today,
+        });
+        Ok(())
+    }
+
+    fn delete_expired(&self) -> anyhow::Result<()> {
+        let cutoff = self
+            .retention
+            .and_then(|retention| SystemTime::now().checked_sub(retention));
+        // Without a retention, or one reaching back before the epoch.
+        let cutoff = match cutoff {
+            Some(cutoff) => cutoff,
+            None => return Ok(()),
+        };
+        for path in self.rotated()? {
+            if std::fs::metadata(&path)?.modified()? < cutoff {
+                tracing::info!(?path, "Deleting expired audit log");
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rotated files, oldest first. Their names sort by when they were
+    /// rotated.
+    fn rotated(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => return Ok(paths),
+            Err(why) => return Err(why.into()),
+        };
+        for entry in entries {
+            let name = entry?.file_name();
+            let name = name.to_string_lossy();
+            if name.starts_with("audit-") && name.ends_with(".jsonl") {
+                paths.push(self.dir.join(name.as_ref()));
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// All files, newest first. Open files can still be read after they
+    /// are rotated or deleted.
+    fn open_newest_first(&self) -> anyhow::Result<Vec<(PathBuf, File)>> {
+        let mut paths = self.rotated()?;
+        paths.push(self.dir.join(CURRENT));
+        let mut files = Vec::new();
+        for path in paths.into_iter().rev() {
+            match File::open(&path) {
+                Ok(file) => files.push((path, file)),
+                // Nothing has been written yet.
+                Err(why) if why.kind() == std::io::ErrorKind::NotFound => {}
+                Err(why) => return Err(why.into()),
+            }
+        }
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(user: u64, command: &str, args: &str) -> Entry {
+        Entry {
+            time: Utc::now(),
+            guild: Some(1000),
+            channel: 100,
+            user,
+            command: command.to_string(),
+            args: args.to_string(),
+            source: "message".to_string(),
+            outcome: Outcome::Ok,
+        }
+    }
+
+    #[tokio::test]
+    async fn rotates_and_redacts() {
+        let dir = std::env::temp_dir().join(format!("woofer-audit-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let redaction = Redaction {
+            commands: vec!["gpt".to_string()],
+            patterns: vec![Regex::new(r"[\w.+-]+@[\w-]+\.\w+").unwrap()],
+        };
+        let audit = Audit::files(&dir, 400, Some(Duration::from_secs(3600)), redaction);
+
+        audit.record(entry(2, "gpt", "what is my password"));
+        audit.record(entry(3, "remind", "tomorrow mail pup@example.com"));
+        for i in 0..4 {
+            audit.record(entry(2, "chess", &format!("e{i}")));
+        }
+        let rotated = std::fs::read_dir(&dir).unwrap().count() - 1;
+        assert!(rotated >= 1, "{}", rotated);
+
+        let recent = audit
+            .recent(UserId::new(2), Some(GuildId::new(1000)), 3)
+            .await
+            .unwrap();
+        let args: Vec<&str> = recent.iter().map(|e| e.args.as_str()).collect();
+        assert_eq!(args, ["e3", "e2", "e1"]);
+        let all = audit
+            .recent(UserId::new(2), Some(GuildId::new(1000)), 10)
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 5);
+        assert_eq!(all[4].args, REDACTED);
+        let other = audit
+            .recent(UserId::new(3), Some(GuildId::new(1000)), 10)
+            .await
+            .unwrap();
+        assert_eq!(other[0].args, "tomorrow mail [redacted]");
+        assert!(audit
+            .recent(UserId::new(2), None, 10)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // Everything rotated is older than no retention at all.
+        let files = match &audit.inner.sink {
+            Sink::Files(files) => files,
+            _ => unreachable!(),
+        };
+        let mut files = files.lock().unwrap();
+        // Too long to reach back is the same as none.
+        files.retention = Some(Duration::MAX);
+        files.delete_expired().unwrap();
+        assert!(!files.rotated().unwrap().is_empty());
+        files.retention = Some(Duration::ZERO);
+        files.delete_expired().unwrap();
+        assert!(files.rotated().unwrap().is_empty());
+        drop(files);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+
+/// Remembers values for a while, and makes concurrent lookups of the same
+/// key wait for a single fetch instead of each doing their own.
+pub struct Cache<V> {
+    capacity: usize,
+    entries: Mutex<HashMap<String, Arc<Entry<V>>>>,
+}
+
+struct Entry<V> {
+    ttl: Duration,
+    /// The value and when it was fetched, once it has been.
+    value: OnceCell<(Instant, V)>,
+}
+
+impl<V> Entry<V> {
+    fn expires(&self) -> Option<Instant> {
+        self.value.get().map(|(fetched, _)| *fetched + self.ttl)
+    }
+
+    /// Entries that are still being fetched count as fresh, so that others
+    /// wait for them.
+    fn is_fresh(&self, now: Instant) -> bool {
+        self.expires().is_none_or(|expires| expires > now)
+    }
+}
+
+impl<V: Clone> Cache<V> {
+    /// A cache of at most `capacity` values.
+    pub fn new(capacity: usize) -> Self {
+        Cache {
+            capacity,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The value for `key` if it was fetched less than `ttl` ago, or else
+    /// the one `fetch` returns. Values that `keep` rejects, and errors, are
+    /// only shared with lookups that were already waiting. If `fetch` fails,
+    /// the next of those tries again itself.
+    pub async fn get_or_fetch<F, Fut, E>(
+        &self,
+        key: &str,
+        ttl: Duration,
+        fetch: F,
+        keep: impl FnOnce(&V) -> bool,
+    ) -> Result<V, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+    {
+        let entry = self.entry(key, ttl);
+        let value = entry
+            .value
+            .get_or_try_init(|| async { Ok((Instant::now(), fetch().await?)) })
+            .await
+            .map(|(_, value)| value.clone());
+        if !value.as_ref().is_ok_and(keep) {
+            self.remove(key, &entry);
+        }
+        value
+    }
+
+    fn entry(&self, key: &str, ttl: Duration) -> Arc<Entry<V>> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get(key).filter(|entry| entry.is_fresh(now)) {
+            return entry.clone();
+        }
+        let entry = Arc::new(Entry {
+            ttl,
+            value: OnceCell::new(),
+        });
+        entries.insert(key.to_string(), entry.clone());
+        if entries.len() > self.capacity {
+            entries.retain(|_, entry| entry.is_fresh(now));
+        }
+        // Still full of fresh values, so drop the ones closest to expiring.
+        while entries.len() > self.capacity {
+            let soonest = entries
+                .iter()
+                .filter_map(|(key, entry)| Some((entry.expires()?, key)))
+                .min()
+                .map(|(_, key)| key.clone());
+            match soonest {
+                Some(key) => entries.remove(&key),
+                // Everything is being fetched right now.
+                None => break,
+            };
+        }
+        entry
+    }
+
+    /// Forgets `entry`, unless it has already been replaced.
+    fn remove(&self, key: &str, entry: &Arc<Entry<V>>) {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.get(key).is_some_and(|e| Arc::ptr_eq(e, entry)) {
+            entries.remove(key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn fetches_once_until_expired() {
+        let cache = Cache::new(2);
+        let fetches = AtomicUsize::new(0);
+        let get = |key: &'static str, ttl: u64| {
+            let fetches = &fetches;
+            let cache = &cache;
+            async move {
+                let fetch = || async move {
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    Ok::<_, ()>(fetches.fetch_add(1, Ordering::SeqCst))
+                };
+                cache
+                    .get_or_fetch(key, Duration::from_millis(ttl), fetch, |_| true)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // Concurrent lookups share one fetch.
+        let (a, b) = tokio::join!(get("tsla", 1000), get("tsla", 1000));
+        assert_eq!((a, b), (0, 0));
+        assert_eq!(get("tsla", 1000).await, 0);
+
+        assert_eq!(get("ksea", 0).await, 1);
+        assert_eq!(get("ksea", 0).await, 2);
+
+        // Room for two, so the expired one goes first, then the oldest.
+        assert_eq!(get("tokyo", 1000).await, 3);
+        assert_eq!(get("paris", 1000).await, 4);
+        assert_eq!(get("tokyo", 1000).await, 3);
+        assert_eq!(get("tsla", 1000).await, 5);
+        assert_eq!(fetches.load(Ordering::SeqCst), 6);
+    }
+}
@@ -0,0 +1,1078 @@
+use crate::admin::Owners;
+use crate::audit::{self, Audit, Outcome};
+use crate::components::{self, Click, Component};
+use crate::cooldown::{Caller, Cooldowns, Limit};
+use crate::edits::Replies;
+use crate::locale::Locale;
+use crate::metrics::METRICS;
+use crate::prefs::Prefs;
+use crate::settings::{Denied, GuildSettings, Settings};
+use crate::transport::Transport;
+use crate::utils;
+use serenity::all::{
+    ChannelId, CommandInteraction, CommandOptionType, ComponentInteraction,
+    ComponentInteractionDataKind, GuildId, ResolvedOption, ResolvedValue, RoleId, User,
+};
+use serenity::async_trait;
+use serenity::builder::{
+    CreateAllowedMentions, CreateAttachment, CreateCommand, CreateCommandOption, CreateEmbed,
+    CreateInteractionResponseFollowup, CreateMessage, EditInteractionResponse, EditMessage,
+};
+use serenity::model::channel::{Message, MessageReference};
+use serenity::prelude::*;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::Instrument;
+
+/// Name of the slash command that every text command is mirrored under, so
+/// that `puppy stonk tsla` is also `/puppy stonk ticker:tsla`.
+pub const SLASH_COMMAND: &str = "puppy";
+
+/// A single message sent back in response to a command.
+#[derive(Debug, Default)]
+pub struct Reply {
+    pub content: Option<String>,
+    pub embed: Option<CreateEmbed>,
+    pub attachments: Vec<CreateAttachment>,
+    /// Inline reply to the triggering message. `Some(true)` also pings its author.
+    pub reply_to: Option<bool>,
+    pub components: Vec<Component>,
+    /// When answering a component, only whoever used it sees the reply,
+    /// and the message the component is on stays as it was. Other replies
+    /// ignore this.
+    pub ephemeral: bool,
+    /// Reports an error, so the audit log counts the command as failed.
+    pub failed: bool,
+}
+
+impl Reply {
+    /// Plain text inline reply, like `Message::reply`.
+    pub fn text(content: impl Into<String>) -> Self {
+        Reply {
+            content: Some(content.into()),
+            reply_to: Some(false),
+            ..Default::default()
+        }
+    }
+
+    /// Embed posted to the channel without quoting the triggering message.
+    pub fn embed(embed: CreateEmbed) -> Self {
+        Reply {
+            embed: Some(embed),
+            ..Default::default()
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn attachment(mut self, attachment: CreateAttachment) -> Self {
+        self.attachments.push(attachment);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn component(mut self, component: Component) -> Self {
+        self.components.push(component);
+        self
+    }
+
+    pub fn ephemeral(mut self) -> Self {
+        self.ephemeral = true;
+        self
+    }
+
+    /// A new message that quotes `reference` if this is an inline reply.
+    pub(crate) fn into_message(self, reference: Option<MessageReference>) -> CreateMessage {
+        let mut builder = CreateMessage::new();
+        if let Some(content) = self.content {
+            builder = builder.content(content);
+        }
+        if let Some(embed) = self.embed {
+            builder = builder.embed(embed);
+        }
+        if let Some(ping) = self.reply_to {
+            let allowed_mentions = CreateAllowedMentions::new()
+                .replied_user(ping)
+                .everyone(true)
+                .all_users(true)
+                .all_roles(true);
+            builder = builder.allowed_mentions(allowed_mentions);
+            if let Some(reference) = reference {
+                builder = builder.reference_message(reference);
+            }
+        }
+        if !self.components.is_empty() {
+            builder = builder.components(components::action_rows(self.components));
+        }
+        builder.add_files(self.attachments)
+    }
+
+    /// Replaces everything in the interaction's message, which is either
+    /// the deferred response to a slash command or the message a component
+    /// is on.
+    pub(crate) fn into_edit(self) -> EditInteractionResponse {
+        let mut builder = EditInteractionResponse::new()
+            .content(self.content.unwrap_or_default())
+            .embeds(self.embed.into_iter().collect())
+            .components(components::action_rows(self.components))
+            .clear_attachments();
+        for attachment in self.attachments {
+            builder = builder.new_attachment(attachment);
+        }
+        builder
+    }
+
+    /// Replaces everything in the message, so that nothing of the earlier
+    /// reply is left over.
+    pub(crate) fn into_edit_message(self) -> EditMessage {
+        let mut builder = EditMessage::new()
+            .content(self.content.unwrap_or_default())
+            .embeds(self.embed.into_iter().collect())
+            .components(components::action_rows(self.components))
+            .remove_all_attachments();
+        for attachment in self.attachments {
+            builder = builder.new_attachment(attachment);
+        }
+        builder
+    }
+
+    pub(crate) fn into_followup(self) -> CreateInteractionResponseFollowup {
+        let mut builder = CreateInteractionResponseFollowup::new();
+        if let Some(content) = self.content {
+            builder = builder.content(content);
+        }
+        if let Some(embed) = self.embed {
+            builder = builder.embed(embed);
+        }
+        if !self.components.is_empty() {
+            builder = builder.components(components::action_rows(self.components));
+        }
+        builder.add_files(self.attachments)
+    }
+}
+
+/// Where a command invocation came from.
+pub enum Source<'a> {
+    Message(&'a Message),
+    Slash(&'a CommandInteraction),
+    /// A button or select menu on one of the bot's replies.
+    Component(&'a ComponentInteraction),
+}
+
+/// Everything a command needs to know about who invoked it and where,
+/// independent of whether it was typed as text or used as a slash command.
+pub struct Invocation<'a> {
+    pub transport: &'a dyn Transport,
+    pub data: &'a Arc<RwLock<TypeMap>>,
+    pub registry: &'a Registry,
+    pub source: Source<'a>,
+    /// The language to reply in, once the dispatcher has looked it up.
+    pub locale: Locale,
+    pub guild_id: Option<GuildId>,
+    pub channel_id: ChannelId,
+    pub author: &'a User,
+}
+
+impl<'a> Invocation<'a> {
+    pub fn from_message(
+        transport: &'a dyn Transport,
+        data: &'a Arc<RwLock<TypeMap>>,
+        registry: &'a Registry,
+        msg: &'a Message,
+    ) -> Self {
+        Invocation {
+            transport,
+            data,
+            registry,
+            source: Source::Message(msg),
+            locale: Locale::default(),
+            guild_id: msg.guild_id,
+            channel_id: msg.channel_id,
+            author: &msg.author,
+        }
+    }
+
+    pub fn from_interaction(
+        transport: &'a dyn Transport,
+        data: &'a Arc<RwLock<TypeMap>>,
+        registry: &'a Registry,
+        command: &'a CommandInteraction,
+    ) -> Self {
+        Invocation {
+            transport,
+            data,
+            registry,
+            source: Source::Slash(command),
+            locale: Locale::default(),
+            guild_id: command.guild_id,
+            channel_id: command.channel_id,
+            author: &command.user,
+        }
+    }
+
+    pub fn from_component(
+        transport: &'a dyn Transport,
+        data: &'a Arc<RwLock<TypeMap>>,
+        registry: &'a Registry,
+        component: &'a ComponentInteraction,
+    ) -> Self {
+        Invocation {
+            transport,
+            data,
+            registry,
+            source: Source::Component(component),
+            locale: Locale::default(),
+            guild_id: component.guild_id,
+            channel_id: component.channel_id,
+            author: &component.user,
+        }
+    }
+
+    /// What to call the author: their server nickname, or else their
+    /// display name.
+    #[allow(dead_code)]
+    pub fn author_name(&self) -> String {
+        match self.source {
+            Source::Message(msg) => utils::author_name_from_msg(msg),
+            Source::Slash(command) => {
+                let nick = command.member.as_ref().and_then(|m| m.nick.as_deref());
+                utils::author_name(&command.user, nick)
+            }
+            Source::Component(component) => {
+                let nick = component.member.as_ref().and_then(|m| m.nick.as_deref());
+                utils::author_name(&component.user, nick)
+            }
+        }
+    }
+
+    /// The triggering message, if this was a text command.
+    #[allow(dead_code)]
+    pub fn message(&self) -> Option<&'a Message> {
+        match self.source {
+            Source::Message(msg) => Some(msg),
+            Source::Slash(_) | Source::Component(_) => None,
+        }
+    }
+
+    /// Whether the author is one of the bot's [`Owners`].
+    pub async fn is_owner(&self) -> bool {
+        self.shared::<Owners>().await.contains(self.author.id)
+    }
+
+    /// The author's roles in this guild, as sent along with the event.
+    fn roles(&self) -> &'a [RoleId] {
+        let roles = match self.source {
+            Source::Slash(command) => command.member.as_ref().map(|m| &m.roles),
+            Source::Component(component) => component.member.as_ref().map(|m| &m.roles),
+            Source::Message(msg) => msg.member.as_ref().map(|m| &m.roles),
+        };
+        roles.map_or(&[], Vec::as_slice)
+    }
+
+    /// Whether the author may manage the bot in this guild: owners, members
+    /// with one of the guild's admin roles, and anyone who can manage the
+    /// guild itself.
+    pub async fn is_admin(&self) -> bool {
+        if self.is_owner().await {
+            return true;
+        }
+        let guild = match self.guild_id {
+            Some(guild) => guild,
+            None => return false,
+        };
+        let settings = self.shared::<Settings>().await.get(Some(guild)).await;
+        if self
+            .roles()
+            .iter()
+            .any(|role| settings.admin_roles.contains(role))
+        {
+            return true;
+        }
+        let permissions = match self.source {
+            Source::Slash(command) => command.member.as_ref().and_then(|m| m.permissions),
+            Source::Component(component) => component.member.as_ref().and_then(|m| m.permissions),
+            Source::Message(_) => self.transport.permissions(guild, self.author.id).await,
+        };
+        permissions.is_some_and(|p| p.administrator() || p.manage_guild())
+    }
+
+    /// A shared service stored in the client's TypeMap, such as the store.
+    pub async fn shared<K>(&self) -> K::Value
+    where
+        K: TypeMapKey,
+        K::Value: Clone,
+    {
+        self.data
+            .read()
+            .await
+            .get::<K>()
+            .unwrap_or_else(|| panic!("Expected {}", std::any::type_name::<K>()))
+            .clone()
+    }
+}
+
+/// Arguments as they arrive from Discord, before a command parses them.
+pub enum Input<'a> {
+    Text(&'a str),
+    Options(&'a [ResolvedOption<'a>]),
+}
+
+impl Input<'_> {
+    /// The arguments as text, with options written as `name:value` the
+    /// way Discord shows them.
+    fn describe(&self) -> String {
+        let options = match self {
+            Input::Text(args) => return args.to_string(),
+            Input::Options(options) => options,
+        };
+        let options: Vec<String> = options
+            .iter()
+            .filter_map(|option| {
+                let value = match &option.value {
+                    ResolvedValue::String(s) => s.to_string(),
+                    ResolvedValue::Integer(i) => i.to_string(),
+                    ResolvedValue::Number(n) => n.to_string(),
+                    ResolvedValue::Boolean(b) => b.to_string(),
+                    ResolvedValue::User(user, _) => format!("<@{}>", user.id),
+                    ResolvedValue::Role(role) => format!("<@&{}>", role.id),
+                    ResolvedValue::Channel(channel) => format!("<#{}>", channel.id),
+                    _ => return None,
+                };
+                Some(format!("{}:{value}", option.name))
+            })
+            .collect();
+        options.join(" ")
+    }
+}
+
+/// A command such as `puppy stonk tsla`, also available as `/puppy stonk`.
+#[async_trait]
+pub trait Command: Send + Sync {
+    type Args: Send;
+
+    /// The word following `puppy`, e.g. `stonk`.
+    fn name(&self) -> &'static str;
+
+    /// One-line summary shown by `puppy help`, and in English in the slash
+    /// command picker.
+    fn description(&self, locale: Locale) -> String;
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Arguments in the text syntax, e.g. `[ticker] [range]`.
+    fn usage(&self) -> &'static str {
+        ""
+    }
+
+    /// Example arguments shown by `puppy help`. Each must be accepted by
+    /// [`Command::parse`].
+    fn examples(&self) -> &'static [&'static str] {
+        &[]
+    }
+
+    /// How often the command may be used. Uses beyond these get a reply
+    /// asking the user to slow down.
+    fn limits(&self) -> &'static [Limit] {
+        &[]
+    }
+
+    /// Whether to show the typing indicator while the command runs.
+    fn typing(&self) -> bool {
+        false
+    }
+
+    /// Whether editing the triggering message runs the command again and
+    /// edits the earlier reply. Commands with side effects, like chess
+    /// moves, must not opt in.
+    fn rerun_on_edit(&self) -> bool {
+        false
+    }
+
+    /// Parses the text after the command name. Returning `None` means the
+    /// message is not an invocation of this command.
+    fn parse(&self, args: &str) -> Option<Self::Args>;
+
+    /// Options of the `/puppy <name>` subcommand.
+    fn options(&self) -> Vec<CreateCommandOption> {
+        Vec::new()
+    }
+
+    /// Builds arguments from slash command options. By default the string
+    /// options are joined in order and handed to [`Command::parse`], which
+    /// works whenever the options mirror the text syntax.
+    fn parse_options(&self, options: &[ResolvedOption]) -> Option<Self::Args> {
+        let words: Vec<&str> = options
+            .iter()
+            .filter_map(|option| match option.value {
+                ResolvedValue::String(s) => Some(s),
+                _ => None,
+            })
+            .collect();
+        self.parse(&words.join(" "))
+    }
+
+    async fn run(&self, inv: &Invocation<'_>, args: Self::Args) -> Vec<Reply>;
+}
+
+/// Stand-in for a command whose integration has no credentials configured.
+struct Unconfigured {
+    name: &'static str,
+}
+
+#[async_trait]
+impl Command for Unconfigured {
+    type Args = ();
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn description(&self, locale: Locale) -> String {
+        locale.text("description-not-configured", &[])
+    }
+
+    fn parse(&self, _args: &str) -> Option<()> {
+        Some(())
+    }
+
+    async fn run(&self, inv: &Invocation<'_>, _args: ()) -> Vec<Reply> {
+        vec![Reply::text(
+            inv.locale
+                .error("not-configured", &[("command", &self.name)]),
+        )]
+    }
+}
+
+/// Looks up a string option by name.
+pub fn string_option<'a>(options: &[ResolvedOption<'a>], name: &str) -> Option<&'a str> {
+    options
+        .iter()
+        .find(|option| option.name == name)
+        .and_then(|option| match option.value {
+            ResolvedValue::String(s) => Some(s),
+            _ => None,
+        })
+}
+
+/// Object-safe view of a [`Command`] so that commands with different `Args`
+/// can live in the same registry.
+#[async_trait]
+trait Dispatch: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn aliases(&self) -> &'static [&'static str];
+    fn rerun_on_edit(&self) -> bool;
+    fn subcommand(&self) -> CreateCommandOption;
+    fn help(&self, locale: Locale) -> Help;
+    #[cfg(test)]
+    fn parses(&self, args: &str) -> bool;
+    async fn dispatch(&self, inv: &Invocation<'_>, input: Input<'_>) -> bool;
+}
+
+#[async_trait]
+impl<C: Command> Dispatch for C {
+    fn name(&self) -> &'static str {
+        Command::name(self)
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        Command::aliases(self)
+    }
+
+    fn rerun_on_edit(&self) -> bool {
+        Command::rerun_on_edit(self)
+    }
+
+    fn subcommand(&self) -> CreateCommandOption {
+        let name = Command::name(self);
+        let subcommand = CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            name,
+            self.description(Locale::En),
+        );
+        let mut subcommand = localize(subcommand, |locale| self.description(locale));
+        for option in self.options() {
+            let key = format!("option-{name}-{}", option_name(&option));
+            subcommand =
+                subcommand.add_sub_option(localize(option, |locale| locale.text(&key, &[])));
+        }
+        subcommand
+    }
+
+    fn help(&self, locale: Locale) -> Help {
+        let name = Command::name(self);
+        Help {
+            name: Command::name(self),
+            aliases: Command::aliases(self)
+                .iter()
+                .map(|a| a.to_string())
+                .collect(),
+            description: self.description(locale),
+            usage: self.usage(),
+            examples: self.examples(),
+            options: self
+                .options()
+                .iter()
+                .map(|option| OptionHelp::new(locale, name, option))
+                .collect(),
+        }
+    }
+
+    #[cfg(test)]
+    fn parses(&self, args: &str) -> bool {
+        self.parse(args).is_some()
+    }
+
+    async fn dispatch(&self, inv: &Invocation<'_>, input: Input<'_>) -> bool {
+        let args = match input {
+            Input::Text(args) => self.parse(args),
+            Input::Options(options) => self.parse_options(options),
+        };
+        let args = match args {
+            Some(args) => args,
+            None => return false,
+        };
+
+        let span = tracing::info_span!(
+            "command",
+            command = Command::name(self),
+            guild = inv.guild_id.map(|g| g.get()),
+            channel = inv.channel_id.get(),
+            user = inv.author.id.get(),
+            slash = matches!(inv.source, Source::Slash(_)),
+            component = matches!(inv.source, Source::Component(_)),
+        );
+        let outcome = handle(self, inv, args).instrument(span).await;
+        record(inv, Command::name(self), &input, outcome).await;
+        true
+    }
+}
+
+/// Runs `command` once its arguments have parsed, and logs how long it took.
+async fn handle<C: Command>(command: &C, inv: &Invocation<'_>, args: C::Args) -> Outcome {
+    let start = Instant::now();
+    let caller = Caller {
+        user: inv.author.id.get(),
+        channel: inv.channel_id.get(),
+        guild: inv.guild_id.map_or(inv.channel_id.get(), |g| g.get()),
+    };
+    let cooldowns = inv.shared::<Cooldowns>().await;
+    if let Err(wait) = cooldowns.check(command.name(), command.limits(), &caller) {
+        tracing::info!(wait_ms = wait.as_millis() as u64, "rate limited");
+        METRICS.command_rate_limited(command.name());
+        let reply = Reply::text(inv.locale.text(
+            "slow-down",
+            &[
+                ("command", &command.name()),
+                ("seconds", &wait.as_secs_f64().ceil().max(1.0)),
+            ],
+        ))
+        .ephemeral();
+        inv.transport.send(&inv.source, vec![reply]).await;
+        return Outcome::RateLimited;
+    }
+
+    // Slash commands were already deferred, which shows its own indicator.
+    let typing = match inv.source {
+        Source::Message(_) if command.typing() => inv.transport.start_typing(inv.channel_id),
+        _ => None,
+    };
+    let replies = command.run(inv, args).await;
+    if let Some(typing) = typing {
+        typing.stop();
+    }
+    let outcome = if replies.iter().any(|reply| reply.failed) {
+        Outcome::Failed
+    } else {
+        Outcome::Ok
+    };
+    let sent = inv.transport.send(&inv.source, replies).await;
+    if let Source::Message(msg) = inv.source {
+        if command.rerun_on_edit() {
+            inv.shared::<Replies>().await.record(msg, sent);
+        }
+    }
+    let elapsed = start.elapsed();
+    METRICS.command_handled(command.name(), elapsed);
+    tracing::info!(latency_ms = elapsed.as_millis() as u64, "handled command");
+    outcome
+}
+
+/// Adds a command that was run, or refused, to the audit log.
+async fn record(inv: &Invocation<'_>, command: &str, input: &Input<'_>, outcome: Outcome) {
+    let source = match inv.source {
+        Source::Message(_) => "message",
+        Source::Slash(_) => "slash",
+        Source::Component(_) => "component",
+    };
+    inv.shared::<Audit>().await.record(audit::Entry {
+        time: chrono::Utc::now(),
+        guild: inv.guild_id.map(|g| g.get()),
+        channel: inv.channel_id.get(),
+        user: inv.author.id.get(),
+        command: command.to_string(),
+        args: input.describe(),
+        source: source.to_string(),
+        outcome,
+    });
+}
+
+/// What `puppy help` shows about a command, taken from its [`Command`] impl
+/// so that it can't drift from what the dispatcher accepts.
+pub struct Help {
+    pub name: &'static str,
+    /// Built-in aliases followed by those from the config.
+    pub aliases: Vec<String>,
+    pub description: String,
+    pub usage: &'static str,
+    pub examples: &'static [&'static str],
+    pub options: Vec<OptionHelp>,
+}
+
+/// A slash command option, as listed by `puppy help <command>`.
+pub struct OptionHelp {
+    pub name: String,
+    pub description: String,
+    pub required: bool,
+    pub choices: Vec<String>,
+}
+
+/// Adds the description in every other language Discord users may have
+/// picked.
+fn localize(
+    mut option: CreateCommandOption,
+    describe: impl Fn(Locale) -> String,
+) -> CreateCommandOption {
+    for locale in Locale::ALL {
+        for &code in locale.discord_codes() {
+            option = option.description_localized(code, describe(locale));
+        }
+    }
+    option
+}
+
+fn option_name(option: &CreateCommandOption) -> String {
+    // The builder's fields are private, but it serializes to the
+    // documented Discord option object.
+    let option = serde_json::to_value(option).unwrap_or_default();
+    option["name"].as_str().unwrap_or_default().to_string()
+}
+
+impl OptionHelp {
+    /// Describes `option` of `command` from the `option-<command>-<name>`
+    /// message, since the builder only has the English one.
+    fn new(locale: Locale, command: &str, option: &CreateCommandOption) -> Self {
+        let name = option_name(option);
+        let option = serde_json::to_value(option).unwrap_or_default();
+        let string = |v: &serde_json::Value| v.as_str().unwrap_or_default().to_string();
+        OptionHelp {
+            description: locale.text(&format!("option-{command}-{name}"), &[]),
+            name,
+            required: option["required"].as_bool().unwrap_or(false),
+            choices: option["choices"]
+                .as_array()
+                .map(|choices| choices.iter().map(|c| string(&c["name"])).collect())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Registry {
+    commands: Vec<Box<dyn Dispatch>>,
+    triggers: Vec<Box<dyn Dispatch>>,
+    /// Aliases from the config, mapped to the command's name.
+    aliases: BTreeMap<String, &'static str>,
+}
+
+impl Registry {
+    /// Adds a command invoked as `puppy <name> [args]` or `/puppy <name>`.
+    pub fn register(&mut self, command: impl Command + 'static) -> &mut Self {
+        self.commands.push(Box::new(command));
+        self
+    }
+
+    /// Adds `command` if its integration is configured, and otherwise a
+    /// placeholder that tells users so.
+    // Only integrations with credentials need this.
+    #[allow(dead_code)]
+    pub fn register_configured(
+        &mut self,
+        name: &'static str,
+        command: Option<impl Command + 'static>,
+    ) -> &mut Self {
+        match command {
+            Some(command) => self.register(command),
+            None => {
+                tracing::warn!("puppy {name} is not configured, disabling it");
+                self.register(Unconfigured { name })
+            }
+        }
+    }
+
+    /// Adds a command that is offered the whole message instead of the text
+    /// after `puppy <name>`, e.g. woofs. Triggers have no slash command.
+    pub fn trigger(&mut self, command: impl Command + 'static) -> &mut Self {
+        self.triggers.push(Box::new(command));
+        self
+    }
+
+    /// Adds another name for `command`, on top of its built-in aliases.
+    /// Aliases that would shadow a command, or that point at a command this
+    /// build doesn't have, are ignored.
+    pub fn alias(&mut self, alias: &str, command: &str) -> &mut Self {
+        let alias = alias.to_lowercase();
+        if let Some(existing) = self.find(&alias) {
+            tracing::warn!(
+                "Alias {alias} is already taken by puppy {}",
+                existing.name()
+            );
+            return self;
+        }
+        match self.find(&command.to_lowercase()) {
+            Some(command) => {
+                self.aliases.insert(alias, command.name());
+            }
+            None => tracing::warn!("Alias {alias} points at unknown command {command}"),
+        }
+        self
+    }
+
+    fn find(&self, name: &str) -> Option<&dyn Dispatch> {
+        let name = self.aliases.get(name).copied().unwrap_or(name);
+        self.commands
+            .iter()
+            .find(|c| c.name() == name || c.aliases().contains(&name))
+            .map(|c| c.as_ref())
+    }
+
+    fn with_aliases(&self, mut help: Help) -> Help {
+        let command = help.name;
+        let configured = self.aliases.iter().filter(|(_, &name)| name == command);
+        help.aliases
+            .extend(configured.map(|(alias, _)| alias.clone()));
+        help
+    }
+
+    /// The name of the command that `name` is most likely a typo of, if any
+    /// command or alias is close enough.
+    fn suggest(&self, name: &str) -> Option<&'static str> {
+        let builtin = self.commands.iter().flat_map(|c| {
+            let aliases = c.aliases().iter().map(move |&alias| (alias, c.name()));
+            std::iter::once((c.name(), c.name())).chain(aliases)
+        });
+        let configured = self
+            .aliases
+            .iter()
+            .map(|(alias, &name)| (alias.as_str(), name));
+        let max_distance = name.chars().count() / 3;
+        builtin
+            .chain(configured)
+            .map(|(candidate, command)| (edit_distance(name, candidate), command))
+            .filter(|&(distance, _)| distance <= max_distance)
+            .min_by_key(|&(distance, _)| distance)
+            .map(|(_, command)| command)
+    }
+
+    /// Resolves a command or trigger name or alias to the command's name.
+    pub fn canonical_name(&self, name: &str) -> Option<&'static str> {
+        let trigger = self.triggers.iter().find(|t| t.name() == name);
+        trigger
+            .map(|t| t.as_ref())
+            .or_else(|| self.find(name))
+            .map(|c| c.name())
+    }
+
+    /// Help for every trigger and command, in registration order.
+    pub fn help(&self, locale: Locale) -> (Vec<Help>, Vec<Help>) {
+        (
+            self.triggers.iter().map(|t| t.help(locale)).collect(),
+            self.commands
+                .iter()
+                .map(|c| self.with_aliases(c.help(locale)))
+                .collect(),
+        )
+    }
+
+    /// Help for the command called `name`, or one of its aliases.
+    pub fn help_for(&self, name: &str, locale: Locale) -> Option<Help> {
+        self.find(name).map(|c| self.with_aliases(c.help(locale)))
+    }
+
+    /// Whether `name` accepts `args`, without running it.
+    #[cfg(test)]
+    pub fn parses(&self, name: &str, args: &str) -> bool {
+        let trigger = self.triggers.iter().find(|t| t.name() == name);
+        trigger
+            .map(|t| t.as_ref())
+            .or_else(|| self.find(name))
+            .is_some_and(|c| c.parses(args))
+    }
+
+    /// The `/puppy` application command with one subcommand per registered
+    /// command.
+    pub fn slash_command(&self) -> CreateCommand {
+        let subcommands = self.commands.iter().map(|c| c.subcommand()).collect();
+        CreateCommand::new(SLASH_COMMAND)
+            .description("woof")
+            .set_options(subcommands)
+    }
+
+    /// Runs the first command matching `msg`, returning whether one ran.
+    pub async fn dispatch(
+        &self,
+        transport: &dyn Transport,
+        data: &Arc<RwLock<TypeMap>>,
+        msg: &Message,
+    ) -> bool {
+        self.dispatch_text(transport, data, msg, false).await
+    }
+
+    /// Runs the command in an edited message again, if it's one that
+    /// [`Command::rerun_on_edit`]. Triggers aren't re-run.
+    pub async fn dispatch_edit(
+        &self,
+        transport: &dyn Transport,
+        data: &Arc<RwLock<TypeMap>>,
+        msg: &Message,
+    ) -> bool {
+        self.dispatch_text(transport, data, msg, true).await
+    }
+
+    async fn dispatch_text(
+        &self,
+        transport: &dyn Transport,
+        data: &Arc<RwLock<TypeMap>>,
+        msg: &Message,
+        edited: bool,
+    ) -> bool {
+        let mut inv = Invocation::from_message(transport, data, self, msg);
+        let settings = inv.shared::<Settings>().await.get(inv.guild_id).await;
+        inv.locale = locale(&inv, &settings).await;
+        let content = msg.content.trim();
+        let triggers = if edited { &[][..] } else { &self.triggers[..] };
+        for trigger in triggers {
+            if settings.allows(trigger.name(), inv.channel_id).is_ok()
+                && trigger.dispatch(&inv, Input::Text(content)).await
+            {
+                return true;
+            }
+        }
+
+        let rest = match strip_prefix(content, settings.prefix()) {
+            Some(rest) => rest,
+            None => return false,
+        };
+        let (name, args) = split_word(rest);
+        let command = match self.find(&name.to_lowercase()) {
+            Some(command) if !edited || command.rerun_on_edit() => command,
+            Some(_) => return false,
+            None if edited => return false,
+            None => return did_you_mean(&inv, &settings, name, args).await,
+        };
+        match settings.allows(command.name(), inv.channel_id) {
+            Ok(()) => command.dispatch(&inv, Input::Text(args)).await,
+            Err(denied) => {
+                deny(&inv, command.name(), Input::Text(args), denied).await;
+                true
+            }
+        }
+    }
+
+    /// Runs the subcommand of a `/puppy` interaction.
+    pub async fn dispatch_slash(
+        &self,
+        transport: &dyn Transport,
+        data: &Arc<RwLock<TypeMap>>,
+        command: &CommandInteraction,
+    ) {
+        let resolved = command.data.options();
+        let (subcommand, options) = match resolved.first() {
+            Some(option) => match &option.value {
+                ResolvedValue::SubCommand(options) => (option.name, options.as_slice()),
+                _ => return,
+            },
+            None => return,
+        };
+
+        // Discord wants an answer within three seconds, which gpt and stonk
+        // can't promise.
+        transport.defer(command).await;
+        let mut inv = Invocation::from_interaction(transport, data, self, command);
+        let settings = inv.shared::<Settings>().await.get(inv.guild_id).await;
+        inv.locale = locale(&inv, &settings).await;
+        let ran = match self.find(subcommand) {
+            Some(c) => match settings.allows(c.name(), inv.channel_id) {
+                Ok(()) => c.dispatch(&inv, Input::Options(options)).await,
+                Err(denied) => {
+                    deny(&inv, c.name(), Input::Options(options), denied).await;
+                    true
+                }
+            },
+            None => false,
+        };
+        if !ran {
+            let reply = Reply::text(inv.locale.error("not-understood", &[]));
+            transport.send(&inv.source, vec![reply]).await;
+        }
+    }
+
+    /// Runs the command behind a button or select menu on one of the bot's
+    /// replies, with the arguments stored in it and the chosen options.
+    pub async fn dispatch_component(
+        &self,
+        transport: &dyn Transport,
+        data: &Arc<RwLock<TypeMap>>,
+        component: &ComponentInteraction,
+    ) {
+        transport.acknowledge(component).await;
+        let mut inv = Invocation::from_component(transport, data, self, component);
+        let settings = inv.shared::<Settings>().await.get(inv.guild_id).await;
+        inv.locale = locale(&inv, &settings).await;
+        let click = match Click::parse(&component.data.custom_id) {
+            Some(click) if !click.is_expired() => click,
+            _ => return refuse(&inv, inv.locale.error("component-expired", &[])).await,
+        };
+        if let Some(owner) = click.owner.filter(|&owner| owner != inv.author.id) {
+            let owner = format!("<@{owner}>");
+            let reason = inv.locale.error("component-not-yours", &[("user", &owner)]);
+            return refuse(&inv, reason).await;
+        }
+        let command = match self.find(&click.command) {
+            Some(command) => command,
+            None => return refuse(&inv, inv.locale.error("component-expired", &[])).await,
+        };
+        if let Err(denied) = settings.allows(command.name(), inv.channel_id) {
+            return deny(&inv, command.name(), Input::Text(&click.args), denied).await;
+        }
+        let mut args = click.args;
+        if let ComponentInteractionDataKind::StringSelect { values } = &component.data.kind {
+            for value in values {
+                args.push(' ');
+                args.push_str(value);
+            }
+        }
+        if !command.dispatch(&inv, Input::Text(args.trim())).await {
+            refuse(&inv, inv.locale.error("not-understood", &[])).await;
+        }
+    }
+}
+
+/// Tells only the user of a component why it didn't work, e.g. because it
+/// expired.
+async fn refuse(inv: &Invocation<'_>, reason: String) {
+    let reply = Reply::text(reason).ephemeral();
+    inv.transport.send(&inv.source, vec![reply]).await;
+}
+
+/// The language to reply to the author in: their own choice, then their
+/// guild's.
+async fn locale(inv: &Invocation<'_>, settings: &GuildSettings) -> Locale {
+    let prefs = inv.shared::<Prefs>().await.get(inv.author.id).await;
+    prefs.locale.or(settings.locale).unwrap_or_default()
+}
+
+/// Tells the author why `name` can't run here.
+async fn deny(inv: &Invocation<'_>, name: &str, input: Input<'_>, denied: Denied) {
+    let reason = match denied {
+        Denied::Disabled => inv.locale.error("disabled", &[("command", &name)]),
+        Denied::OnlyIn(channels) => {
+            let mentions: Vec<String> = channels.iter().map(|c| format!("<#{c}>")).collect();
+            let channels = mentions.join(", ");
+            inv.locale
+                .error("only-in", &[("command", &name), ("channels", &channels)])
+        }
+    };
+    let reply = Reply::text(reason).ephemeral();
+    inv.transport.send(&inv.source, vec![reply]).await;
+    record(inv, name, &input, Outcome::Denied).await;
+}
+
+/// Points out the command that an unknown `puppy <name>` was probably meant
+/// to be, returning whether there was one.
+async fn did_you_mean(
+    inv: &Invocation<'_>,
+    settings: &GuildSettings,
+    name: &str,
+    args: &str,
+) -> bool {
+    let suggestion = match inv.registry.suggest(&name.to_lowercase()) {
+        Some(suggestion) if settings.allows(suggestion, inv.channel_id).is_ok() => suggestion,
+        _ => return false,
+    };
+    let corrected = format!("{} {suggestion} {args}", settings.prefix());
+    let reply = Reply::text(inv.locale.error(
+        "did-you-mean",
+        &[("command", &name), ("suggestion", &corrected.trim_end())],
+    ));
+    inv.transport.send(&inv.source, vec![reply]).await;
+    true
+}
+
+/// Strips a case-insensitive command prefix. Prefixes that end in a letter
+/// or digit, like `puppy`, must be followed by whitespace.
+fn strip_prefix<'a>(content: &'a str, prefix: &str) -> Option<&'a str> {
+    let head = content.get(..prefix.len())?;
+    if !head.eq_ignore_ascii_case(prefix) {
+        return None;
+    }
+    let rest = &content[prefix.len()..];
+    let needs_space = prefix.ends_with(|c: char| c.is_alphanumeric());
+    if needs_space && !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some(rest.trim_start())
+}
+
+/// Splits off the first whitespace-delimited word, returning it and the
+/// remainder with leading whitespace removed.
+pub fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim_start();
+    match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], s[i..].trim_start()),
+        None => (s, ""),
+    }
+}
+
+/// Number of single-character insertions, deletions, substitutions and
+/// swaps of neighbours needed to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    // d[i][j] is the distance between the first i chars of a and the first
+    // j chars of b.
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in d.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in d[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            d[i][j] = (d[i - 1][j] + 1)
+                .min(d[i][j - 1] + 1)
+                .min(d[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                d[i][j] = d[i][j].min(d[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    d[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn measures_typos() {
+        assert_eq!(edit_distance("stonks", "stonk"), 1);
+        assert_eq!(edit_distance("wether", "weather"), 1);
+        assert_eq!(edit_distance("hwo", "how"), 1);
+        assert_eq!(edit_distance("bark", "how"), 4);
+        assert_eq!(edit_distance("", "why"), 3);
+    }
+}
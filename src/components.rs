@@ -0,0 +1,280 @@
+use lazy_static::lazy_static;
+use serenity::all::ButtonStyle;
+use serenity::all::UserId;
+use serenity::builder::CreateActionRow;
+use serenity::builder::CreateButton;
+use serenity::builder::{CreateSelectMenu, CreateSelectMenuKind, CreateSelectMenuOption};
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How long components work unless they say otherwise.
+const DEFAULT_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// Discord's limit on custom IDs.
+const MAX_CUSTOM_ID: usize = 100;
+
+/// Discord's limits on action rows per message, buttons per row and options
+/// per select menu.
+const MAX_ROWS: usize = 5;
+const MAX_BUTTONS: usize = 5;
+pub const MAX_OPTIONS: usize = 25;
+
+/// How many arguments too long for a custom ID are kept in memory.
+const STASH_CAPACITY: usize = 1000;
+
+/// A button or select menu on a reply. Using it runs `command` again with
+/// `args`, as if they had been typed after `puppy <command>`.
+#[derive(Clone, Debug)]
+pub struct Component {
+    command: &'static str,
+    args: String,
+    /// Only this user may use it, if set.
+    owner: Option<UserId>,
+    ttl: Duration,
+    pub kind: Kind,
+}
+
+#[derive(Clone, Debug)]
+pub enum Kind {
+    Button {
+        label: String,
+    },
+    /// The chosen option is appended to the arguments.
+    Select {
+        placeholder: String,
+        options: Vec<String>,
+    },
+}
+
+// Commands pick the kinds and options they need.
+#[allow(dead_code)]
+impl Component {
+    pub fn button(command: &'static str, args: impl Into<String>, label: String) -> Self {
+        Component::new(command, args.into(), Kind::Button { label })
+    }
+
+    /// A menu of up to [`MAX_OPTIONS`] options, each shown as it's sent.
+    pub fn select(
+        command: &'static str,
+        args: impl Into<String>,
+        placeholder: String,
+        options: Vec<String>,
+    ) -> Self {
+        let kind = Kind::Select {
+            placeholder,
+            options,
+        };
+        Component::new(command, args.into(), kind)
+    }
+
+    fn new(command: &'static str, args: String, kind: Kind) -> Self {
+        Component {
+            command,
+            args,
+            owner: None,
+            ttl: DEFAULT_TTL,
+            kind,
+        }
+    }
+
+    /// Makes it work only for `user`.
+    pub fn only(mut self, user: UserId) -> Self {
+        self.owner = Some(user);
+        self
+    }
+
+    pub fn expires_in(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Everything needed to handle a click, as
+    /// `command:index:expires:owner:=args`. `index` only keeps the IDs of a
+    /// message's components apart. Arguments that don't fit are stashed,
+    /// and the ID ends in `#key` instead.
+    fn custom_id(&self, index: usize) -> String {
+        let expires = chrono::Utc::now().timestamp() + self.ttl.as_secs() as i64;
+        let owner = self.owner.map_or(0, |owner| owner.get());
+        let head = format!("{}:{index}:{expires}:{owner}:", self.command);
+        // Arguments starting with `#` are stashed too, so they can't be
+        // mistaken for a key.
+        if head.len() + 1 + self.args.len() <= MAX_CUSTOM_ID && !self.args.starts_with('#') {
+            return format!("{head}={}", self.args);
+        }
+        let key = stash(expires, &self.args);
+        format!("{head}#{key}")
+    }
+
+    /// A row of its own, which [`action_rows`] merges with its neighbours.
+    fn into_row(self, index: usize) -> CreateActionRow {
+        match (self.custom_id(index), self.kind) {
+            (custom_id, Kind::Button { label }) => {
+                let button = CreateButton::new(custom_id)
+                    .label(label)
+                    .style(ButtonStyle::Secondary);
+                CreateActionRow::Buttons(vec![button])
+            }
+            (
+                custom_id,
+                Kind::Select {
+                    placeholder,
+                    options,
+                },
+            ) => {
+                let options = options
+                    .into_iter()
+                    .take(MAX_OPTIONS)
+                    .map(|option| CreateSelectMenuOption::new(option.clone(), option))
+                    .collect();
+                let menu =
+                    CreateSelectMenu::new(custom_id, CreateSelectMenuKind::String { options })
+                        .placeholder(placeholder);
+                CreateActionRow::SelectMenu(menu)
+            }
+        }
+    }
+}
+
+/// A component someone used, decoded from its custom ID.
+#[derive(Debug, PartialEq)]
+pub struct Click {
+    pub command: String,
+    pub args: String,
+    pub owner: Option<UserId>,
+    /// Unix time after which it no longer works.
+    pub expires: i64,
+}
+
+impl Click {
+    /// Decodes a custom ID made by [`Component`]. Stashed arguments that are
+    /// gone, e.g. after a restart, count as expired.
+    pub fn parse(custom_id: &str) -> Option<Click> {
+        let mut parts = custom_id.splitn(5, ':');
+        let command = parts.next()?.to_string();
+        let _index = parts.next()?;
+        let expires = parts.next()?.parse().ok()?;
+        let owner = parts.next()?.parse().ok()?;
+        let (args, expires) = match parts.next()?.split_at_checked(1)? {
+            ("=", args) => (args.to_string(), expires),
+            ("#", key) => match unstash(key.parse().ok()?) {
+                Some(args) => (args, expires),
+                None => (String::new(), 0),
+            },
+            _ => return None,
+        };
+        Some(Click {
+            command,
+            args,
+            owner: (owner != 0).then(|| UserId::new(owner)),
+            expires,
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        chrono::Utc::now().timestamp() > self.expires
+    }
+}
+
+/// Lays out `components` in order, in as few rows as Discord allows:
+/// buttons share rows, and each select menu gets its own.
+pub fn action_rows(components: Vec<Component>) -> Vec<CreateActionRow> {
+    let mut rows: Vec<CreateActionRow> = Vec::new();
+    for (index, component) in components.into_iter().enumerate() {
+        match (rows.last_mut(), component.into_row(index)) {
+            (Some(CreateActionRow::Buttons(buttons)), CreateActionRow::Buttons(more))
+                if buttons.len() + more.len() <= MAX_BUTTONS =>
+            {
+                buttons.extend(more)
+            }
+            (_, row) => rows.push(row),
+        }
+    }
+    if rows.len() > MAX_ROWS {
+        tracing::warn!(rows = rows.len(), "Too many components, dropping some");
+        rows.truncate(MAX_ROWS);
+    }
+    rows
+}
+
+#[derive(Default)]
+struct Stash {
+    next: u64,
+    /// Arguments and when they expire, by key. Keys only grow, so the
+    /// first one is the oldest.
+    args: BTreeMap<u64, (i64, String)>,
+}
+
+lazy_static! {
+    static ref STASH: Mutex<Stash> = Mutex::default();
+}
+
+fn stash(expires: i64, args: &str) -> u64 {
+    let now = chrono::Utc::now().timestamp();
+    let mut stash = STASH.lock().unwrap();
+    stash.args.retain(|_, (expires, _)| *expires >= now);
+    while stash.args.len() >= STASH_CAPACITY {
+        stash.args.pop_first();
+    }
+    let key = stash.next;
+    stash.next += 1;
+    stash.args.insert(key, (expires, args.to_string()));
+    key
+}
+
+fn unstash(key: u64) -> Option<String> {
+    let stash = STASH.lock().unwrap();
+    stash.args.get(&key).map(|(_, args)| args.clone())
+}
+
+// The tests use both buttons and menus.
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_custom_ids() {
+        let refresh = Component::button("stonk", "tsla 1d", "Refresh".to_string());
+        let id = refresh.custom_id(0);
+        assert!(id.starts_with("stonk:0:"), "{}", id);
+        let click = Click::parse(&id).unwrap();
+        assert_eq!(click.command, "stonk");
+        assert_eq!(click.args, "tsla 1d");
+        assert_eq!(click.owner, None);
+        assert!(!click.is_expired());
+
+        let question = "why is the sky blue: an explanation for puppies, with lots of detail and at least three examples";
+        let regenerate = Component::button("gpt", question, "Regenerate".to_string())
+            .only(UserId::new(2))
+            .expires_in(Duration::ZERO);
+        let id = regenerate.custom_id(1);
+        assert!(id.len() <= MAX_CUSTOM_ID);
+        let click = Click::parse(&id).unwrap();
+        assert_eq!(click.args, question);
+        assert_eq!(click.owner, Some(UserId::new(2)));
+
+        assert!(Click::parse("gpt:0:1:0:#999999").unwrap().is_expired());
+        assert!(Click::parse("not a component").is_none());
+    }
+
+    #[test]
+    fn lays_out_rows() {
+        let buttons = (0..6).map(|i| Component::button("why", "", i.to_string()));
+        let moves = (0..30).map(|i| i.to_string()).collect();
+        let menu = Component::select("chess", "", "Pick a move".to_string(), moves);
+        let rows = action_rows(buttons.chain(std::iter::once(menu)).collect());
+        let rows = serde_json::to_value(rows).unwrap();
+        let rows = rows.as_array().unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0]["components"].as_array().unwrap().len(), 5);
+        assert_eq!(rows[1]["components"].as_array().unwrap().len(), 1);
+        assert_eq!(rows[2]["components"][0]["type"], 3);
+        assert_eq!(
+            rows[2]["components"][0]["options"]
+                .as_array()
+                .unwrap()
+                .len(),
+            25
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use crate::audit::{Audit, Redaction};
+use crate::logging::Format;
+use crate::store::Store;
+use crate::upstream::{Mode, Upstream};
+use anyhow::Context as _;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::env;
+use std::time::Duration;
+
+/// Bot configuration, read from `woofer.toml` (or the path in
+/// `$WOOFER_CONFIG`). Every credential can also be given through the
+/// environment, which takes precedence over the file. Integrations whose
+/// credentials are missing are disabled.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Config {
+    pub discord: DiscordConfig,
+    pub weather: WeatherConfig,
+    pub metar: MetarConfig,
+    pub gpt: GptConfig,
+    pub store: StoreConfig,
+    pub http: HttpConfig,
+    pub log: LogConfig,
+    pub metrics: MetricsConfig,
+    pub audit: AuditConfig,
+    /// Extra names for commands, e.g. `forecast = "weather"` makes
+    /// `puppy forecast` run `puppy weather`.
+    pub aliases: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct DiscordConfig {
+    pub token: Option<String>,
+    /// User ids that may run `puppy admin` anywhere, including reloads.
+    pub owners: Vec<u64>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct WeatherConfig {
+    pub openweather_token: Option<String>,
+    pub google_maps_token: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct MetarConfig {
+    pub avwx_token: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct GptConfig {
+    pub openrouter_api_key: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct StoreConfig {
+    /// `json` keeps one file per record under `path`, `memory` forgets
+    /// everything on restart.
+    pub backend: String,
+    pub path: String,
+}
+
+impl Default for StoreConfig {
+    fn default() -> Self {
+        StoreConfig {
+            backend: "json".to_string(),
+            path: "data".to_string(),
+        }
+    }
+}
+
+impl StoreConfig {
+    pub fn open(&self) -> anyhow::Result<Store> {
+        match self.backend.as_str() {
+            "json" => Ok(Store::json(&self.path)),
+            "memory" => Ok(Store::memory()),
+            other => Err(anyhow::anyhow!("Unknown store backend {other}")),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct HttpConfig {
+    /// `live` talks to the real APIs, `record` also saves every response
+    /// under `fixtures`, and `replay` serves only the saved responses.
+    pub mode: Mode,
+    pub fixtures: String,
+    /// Overrides for the API base URLs, keyed by service name, e.g.
+    /// `yahoo = "http://localhost:8080"`.
+    pub base_urls: HashMap<String, String>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            mode: Mode::Live,
+            fixtures: "fixtures".to_string(),
+            base_urls: HashMap::new(),
+        }
+    }
+}
+
+impl HttpConfig {
+    pub fn open(&self) -> anyhow::Result<Upstream> {
+        Upstream::new(&self.base_urls, self.mode, &self.fixtures)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct LogConfig {
+    /// A level like `debug`, or per-module directives like
+    /// `discord_woofer=debug,serenity=warn`.
+    pub level: String,
+    pub format: Format,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: "info".to_string(),
+            format: Format::Pretty,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct MetricsConfig {
+    /// Where to serve `GET /metrics`, e.g. `127.0.0.1:9100`. Nothing is
+    /// served if this is missing.
+    pub listen: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// Directory the audit log is written to. Empty turns it off.
+    pub path: String,
+    /// Files are rotated daily, or sooner once they reach this size.
+    pub max_bytes: u64,
+    /// Rotated files older than this are deleted. Too many days to count
+    /// keeps them forever.
+    pub retention_days: u64,
+    /// Commands whose arguments are never written, `gpt` by default.
+    pub redact_commands: Vec<String>,
+    /// Regexes replaced with `[redacted]` in every command's arguments.
+    pub redact_patterns: Vec<String>,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        AuditConfig {
+            path: "audit".to_string(),
+            max_bytes: 10 * 1024 * 1024,
+            retention_days: 90,
+            // Prompts are private, and often long.
+            redact_commands: vec!["gpt".to_string()],
+            // Email addresses.
+            redact_patterns: vec![r"[\w.+-]+@[\w-]+\.[\w.-]+".to_string()],
+        }
+    }
+}
+
+impl AuditConfig {
+    pub fn open(&self) -> anyhow::Result<Audit> {
+        if self.path.is_empty() {
+            return Ok(Audit::off());
+        }
+        let patterns = self
+            .redact_patterns
+            .iter()
+            .map(|pattern| {
+                regex::Regex::new(pattern)
+                    .with_context(|| format!("Invalid audit redact pattern {pattern}"))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let redaction = Redaction {
+            commands: self.redact_commands.clone(),
+            patterns,
+        };
+        let retention = self
+            .retention_days
+            .checked_mul(24 * 60 * 60)
+            .map(Duration::from_secs);
+        Ok(Audit::files(
+            &self.path,
+            self.max_bytes,
+            retention,
+            redaction,
+        ))
+    }
+}
+
+const DEFAULT_PATH: &str = "woofer.toml";
+
+impl Config {
+    pub fn load() -> anyhow::Result<Config> {
+        let path = env::var("WOOFER_CONFIG").unwrap_or_else(|_| DEFAULT_PATH.to_string());
+        let mut config: Config = match std::fs::read_to_string(&path) {
+            Ok(contents) => {
+                toml::from_str(&contents).with_context(|| format!("Invalid config in {path}"))?
+            }
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => Config::default(),
+            Err(why) => return Err(why).with_context(|| format!("Failed to read {path}")),
+        };
+
+        override_from_env(&mut config.discord.token, "DISCORD_TOKEN");
+        override_from_env(&mut config.weather.openweather_token, "FORECAST_TOKEN");
+        override_from_env(&mut config.weather.google_maps_token, "GOOGLE_MAPS_TOKEN");
+        override_from_env(&mut config.metar.avwx_token, "AVWX_TOKEN");
+        override_from_env(&mut config.gpt.openrouter_api_key, "OPENROUTER_API_KEY");
+        Ok(config)
+    }
+}
+
+/// Replaces `value` with `$var` if it is set, and treats empty strings as
+/// missing so that `FOO_TOKEN=` disables an integration.
+fn override_from_env(value: &mut Option<String>, var: &str) {
+    if let Ok(from_env) = env::var(var) {
+        *value = Some(from_env);
+    }
+    if value.as_deref().map(str::trim) == Some("") {
+        *value = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_missing_sections_and_keys() {
+        let config: Config = toml::from_str("[audit]\npath = \"logs\"").unwrap();
+
+        assert_eq!(config.store.backend, "json");
+        assert_eq!(config.store.path, "data");
+
+        assert_eq!(config.audit.path, "logs");
+        assert_eq!(config.audit.max_bytes, 10 * 1024 * 1024);
+        assert_eq!(config.audit.retention_days, 90);
+        assert_eq!(config.audit.redact_commands, ["gpt"]);
+        assert_eq!(config.audit.redact_patterns.len(), 1);
+
+        assert_eq!(config.http.mode, Mode::Live);
+        assert_eq!(config.http.fixtures, "fixtures");
+        assert!(config.http.base_urls.is_empty());
+
+        assert_eq!(config.log.level, "info");
+        assert_eq!(config.log.format, Format::Pretty);
+        assert!(config.discord.token.is_none());
+    }
+
+    #[test]
+    fn opens_audit_log_with_any_retention() {
+        let audit = AuditConfig {
+            path: String::new(),
+            ..AuditConfig::default()
+        };
+        assert!(audit.open().is_ok());
+        let forever = AuditConfig {
+            retention_days: u64::MAX,
+            ..AuditConfig::default()
+        };
+        assert!(forever.open().is_ok());
+        let broken = AuditConfig {
+            redact_patterns: vec!["(".to_string()],
+            ..AuditConfig::default()
+        };
+        assert!(broken.open().is_err());
+    }
+
+    #[test]
+    fn overrides_credentials_from_env() {
+        // Names no other test uses, since the environment is shared.
+        env::set_var("WOOFER_TEST_SET", "from env");
+        env::set_var("WOOFER_TEST_EMPTY", "");
+        env::remove_var("WOOFER_TEST_UNSET");
+
+        let mut value = Some("from file".to_string());
+        override_from_env(&mut value, "WOOFER_TEST_SET");
+        assert_eq!(value.as_deref(), Some("from env"));
+
+        let mut value = Some("from file".to_string());
+        override_from_env(&mut value, "WOOFER_TEST_EMPTY");
+        assert_eq!(value, None);
+
+        let mut value = Some("from file".to_string());
+        override_from_env(&mut value, "WOOFER_TEST_UNSET");
+        assert_eq!(value.as_deref(), Some("from file"));
+
+        let mut value = Some("  ".to_string());
+        override_from_env(&mut value, "WOOFER_TEST_UNSET");
+        assert_eq!(value, None);
+
+        env::remove_var("WOOFER_TEST_SET");
+        env::remove_var("WOOFER_TEST_EMPTY");
+    }
+
+    /// The only test that sets `WOOFER_CONFIG` and the credential variables.
+    #[test]
+    fn loads_file_then_env() {
+        let dir = std::env::temp_dir().join(format!("woofer-config-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("woofer.toml");
+        std::fs::write(
+            &path,
+            r#"
+                [discord]
+                token = "file token"
+                owners = [7]
+
+                [metar]
+                avwx_token = "file avwx"
+
+                [weather]
+                openweather_token = "file weather"
+            "#,
+        )
+        .unwrap();
+        env::set_var("WOOFER_CONFIG", &path);
+        env::set_var("DISCORD_TOKEN", "env token");
+        env::set_var("AVWX_TOKEN", "");
+
+        let config = Config::load().unwrap();
+        assert_eq!(config.discord.token.as_deref(), Some("env token"));
+        assert_eq!(config.discord.owners, [7]);
+        assert_eq!(config.metar.avwx_token, None);
+        assert_eq!(
+            config.weather.openweather_token.as_deref(),
+            Some("file weather")
+        );
+
+        // A missing file is the same as an empty one.
+        env::set_var("WOOFER_CONFIG", dir.join("missing.toml"));
+        let config = Config::load().unwrap();
+        assert_eq!(config.discord.token.as_deref(), Some("env token"));
+        assert!(config.discord.owners.is_empty());
+
+        std::fs::write(&path, "[discord\n").unwrap();
+        env::set_var("WOOFER_CONFIG", &path);
+        assert!(Config::load().is_err());
+
+        for var in ["WOOFER_CONFIG", "DISCORD_TOKEN", "AVWX_TOKEN"] {
+            env::remove_var(var);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
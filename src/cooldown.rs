@@ -0,0 +1,246 @@
+use serenity::prelude::TypeMapKey;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Who shares a bucket of uses.
+// Builds without some commands don't use every scope.
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Scope {
+    User,
+    Channel,
+    Guild,
+}
+
+/// A token bucket: up to `burst` uses at once, with one more use becoming
+/// available every `refill`.
+#[derive(Clone, Copy, Debug)]
+pub struct Limit {
+    pub scope: Scope,
+    pub burst: u32,
+    pub refill: Duration,
+}
+
+impl Limit {
+    // Unused in builds without rate-limited commands.
+    #[allow(dead_code)]
+    pub const fn new(scope: Scope, burst: u32, refill: Duration) -> Self {
+        Limit {
+            scope,
+            burst,
+            refill,
+        }
+    }
+}
+
+/// Ids of the caller in each [`Scope`]. Outside of guilds, the channel
+/// stands in for the guild.
+pub struct Caller {
+    pub user: u64,
+    pub channel: u64,
+    pub guild: u64,
+}
+
+impl Caller {
+    fn id(&self, scope: Scope) -> u64 {
+        match scope {
+            Scope::User => self.user,
+            Scope::Channel => self.channel,
+            Scope::Guild => self.guild,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Refills the bucket up to `now` and returns how long until it holds a
+    /// whole token, if it doesn't already.
+    fn refill(&mut self, limit: &Limit, now: Instant) -> Option<Duration> {
+        let elapsed = now.saturating_duration_since(self.updated);
+        let refilled = elapsed.as_secs_f64() / limit.refill.as_secs_f64();
+        self.tokens = (self.tokens + refilled).min(f64::from(limit.burst));
+        self.updated = now;
+        if self.tokens >= 1.0 {
+            None
+        } else {
+            Some(limit.refill.mul_f64(1.0 - self.tokens))
+        }
+    }
+
+    fn is_full(&self, limit: &Limit, now: Instant) -> bool {
+        let mut bucket = *self;
+        bucket.refill(limit, now);
+        bucket.tokens >= f64::from(limit.burst)
+    }
+}
+
+type Key = (&'static str, Scope, u64);
+
+/// Once this many buckets exist, full ones are dropped since they behave
+/// exactly like new ones.
+const PRUNE_AT: usize = 4096;
+
+/// Rate limits for every command, shared across handler tasks.
+#[derive(Clone, Default)]
+pub struct Cooldowns {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    buckets: HashMap<Key, (Limit, Bucket)>,
+    /// Commands blocked for everyone, e.g. after an upstream 429.
+    paused: HashMap<&'static str, Instant>,
+}
+
+impl TypeMapKey for Cooldowns {
+    type Value = Cooldowns;
+}
+
+impl Cooldowns {
+    /// Takes one use of `command` from each of its buckets, or returns how
+    /// long the caller has to wait. Nothing is taken unless every bucket
+    /// allows it.
+    pub fn check(
+        &self,
+        command: &'static str,
+        limits: &[Limit],
+        caller: &Caller,
+    ) -> Result<(), Duration> {
+        self.check_at(command, limits, caller, Instant::now())
+    }
+
+    fn check_at(
+        &self,
+        command: &'static str,
+        limits: &[Limit],
+        caller: &Caller,
+        now: Instant,
+    ) -> Result<(), Duration> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(&until) = inner.paused.get(command) {
+            if until > now {
+                return Err(until - now);
+            }
+            inner.paused.remove(command);
+        }
+        if limits.is_empty() {
+            return Ok(());
+        }
+
+        if inner.buckets.len() >= PRUNE_AT {
+            inner
+                .buckets
+                .retain(|_, (limit, bucket)| !bucket.is_full(limit, now));
+        }
+
+        let mut wait = Duration::ZERO;
+        for limit in limits {
+            let key = (command, limit.scope, caller.id(limit.scope));
+            let (_, bucket) = inner.buckets.entry(key).or_insert((
+                *limit,
+                Bucket {
+                    tokens: f64::from(limit.burst),
+                    updated: now,
+                },
+            ));
+            if let Some(until_token) = bucket.refill(limit, now) {
+                wait = wait.max(until_token);
+            }
+        }
+        if wait > Duration::ZERO {
+            return Err(wait);
+        }
+
+        for limit in limits {
+            let key = (command, limit.scope, caller.id(limit.scope));
+            if let Some((_, bucket)) = inner.buckets.get_mut(&key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Blocks `command` for everyone for `duration`, e.g. when its upstream
+    /// API asks us to back off.
+    // Unused in builds without gpt.
+    #[allow(dead_code)]
+    pub fn pause(&self, command: &'static str, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut inner = self.inner.lock().unwrap();
+        let paused = inner.paused.entry(command).or_insert(until);
+        *paused = (*paused).max(until);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: [Limit; 2] = [
+        Limit::new(Scope::User, 2, Duration::from_secs(10)),
+        Limit::new(Scope::Guild, 3, Duration::from_secs(5)),
+    ];
+
+    fn caller(user: u64) -> Caller {
+        Caller {
+            user,
+            channel: 100,
+            guild: 1000,
+        }
+    }
+
+    #[test]
+    fn refills_buckets_over_time() {
+        let cooldowns = Cooldowns::default();
+        let start = Instant::now();
+        let check = |user, secs| {
+            cooldowns.check_at(
+                "stonk",
+                &LIMITS,
+                &caller(user),
+                start + Duration::from_secs(secs),
+            )
+        };
+
+        assert_eq!(check(1, 0), Ok(()));
+        assert_eq!(check(1, 0), Ok(()));
+        assert_eq!(check(1, 0), Err(Duration::from_secs(10)));
+        assert_eq!(check(1, 5), Err(Duration::from_secs(5)));
+        assert_eq!(check(1, 10), Ok(()));
+
+        // The guild bucket is shared by everyone in the guild.
+        assert_eq!(check(2, 10), Ok(()));
+        assert_eq!(check(2, 10), Ok(()));
+        assert_eq!(check(3, 10), Err(Duration::from_secs(5)));
+        assert_eq!(check(3, 15), Ok(()));
+    }
+
+    #[test]
+    fn keeps_channels_apart() {
+        let cooldowns = Cooldowns::default();
+        let limits = [Limit::new(Scope::Channel, 1, Duration::from_secs(10))];
+        let in_channel = |channel| Caller {
+            user: 1,
+            channel,
+            guild: 1000,
+        };
+        assert!(cooldowns.check("why", &limits, &in_channel(100)).is_ok());
+        assert!(cooldowns.check("why", &limits, &in_channel(100)).is_err());
+        assert!(cooldowns.check("why", &limits, &in_channel(101)).is_ok());
+    }
+
+    #[test]
+    fn pauses_commands_for_everyone() {
+        let cooldowns = Cooldowns::default();
+        cooldowns.pause("gpt", Duration::from_secs(30));
+        assert!(cooldowns.check("gpt", &[], &caller(1)).is_err());
+        assert!(cooldowns.check("stonk", &[], &caller(1)).is_ok());
+    }
+}
@@ -0,0 +1,200 @@
+use crate::commands::{Reply, Source};
+use crate::transport::Transport;
+use serenity::all::{
+    ChannelId, CommandInteraction, ComponentInteraction, GuildId, MessageId, Permissions, UserId,
+};
+use serenity::async_trait;
+use serenity::http::Typing;
+use serenity::model::channel::Message;
+use serenity::prelude::TypeMapKey;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// How many commands to remember replies for. Editing an older message
+/// does nothing.
+const CAPACITY: usize = 1000;
+
+/// A command message and the bot's replies to it.
+#[derive(Clone)]
+pub struct Sent {
+    pub source: Message,
+    pub replies: Vec<MessageId>,
+}
+
+/// Replies to recent commands that are re-run when edited, so that the
+/// re-run can edit them instead of sending new ones.
+#[derive(Clone, Default)]
+pub struct Replies {
+    inner: Arc<Mutex<Inner>>,
+}
+
+#[derive(Default)]
+struct Inner {
+    sent: HashMap<MessageId, Sent>,
+    /// Source message ids, oldest first.
+    order: VecDeque<MessageId>,
+}
+
+impl TypeMapKey for Replies {
+    type Value = Replies;
+}
+
+impl Replies {
+    /// Remembers `replies` as the answer to `source`, replacing any earlier
+    /// answer to it.
+    pub fn record(&self, source: &Message, replies: Vec<MessageId>) {
+        let mut inner = self.inner.lock().unwrap();
+        let sent = Sent {
+            source: source.clone(),
+            replies,
+        };
+        if inner.sent.insert(source.id, sent).is_none() {
+            inner.order.push_back(source.id);
+        }
+        while inner.order.len() > CAPACITY {
+            if let Some(oldest) = inner.order.pop_front() {
+                inner.sent.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn get(&self, source: MessageId) -> Option<Sent> {
+        self.inner.lock().unwrap().sent.get(&source).cloned()
+    }
+}
+
+/// A [`Transport`] that edits earlier replies instead of sending new ones.
+/// Extra replies are sent as usual, and earlier replies that are no longer
+/// needed are deleted. Notices that the command didn't run, like being told
+/// to slow down, are sent on their own and leave the earlier replies alone.
+pub struct EditTransport<'a> {
+    inner: &'a dyn Transport,
+    replies: Vec<MessageId>,
+}
+
+impl<'a> EditTransport<'a> {
+    pub fn new(inner: &'a dyn Transport, replies: Vec<MessageId>) -> Self {
+        EditTransport { inner, replies }
+    }
+}
+
+#[async_trait]
+impl Transport for EditTransport<'_> {
+    fn current_user_id(&self) -> UserId {
+        self.inner.current_user_id()
+    }
+
+    async fn history(
+        &self,
+        channel: ChannelId,
+        before: Option<MessageId>,
+        limit: u8,
+    ) -> serenity::Result<Vec<Message>> {
+        self.inner.history(channel, before, limit).await
+    }
+
+    async fn send(&self, source: &Source<'_>, replies: Vec<Reply>) -> Vec<MessageId> {
+        let msg = match source {
+            Source::Message(msg) => msg,
+            Source::Slash(_) | Source::Component(_) => {
+                return self.inner.send(source, replies).await
+            }
+        };
+        // Errors still replace the answer, since they're what the edited
+        // command came to.
+        if !replies.is_empty() && replies.iter().all(|r| r.ephemeral && !r.failed) {
+            return self.inner.send(source, replies).await;
+        }
+        let mut ids = Vec::new();
+        let mut unsent = Vec::new();
+        let mut earlier = self.replies.iter();
+        for reply in replies {
+            let id = match earlier.next() {
+                Some(&id) => id,
+                None => {
+                    unsent.push(reply);
+                    continue;
+                }
+            };
+            match self.inner.edit(msg.channel_id, id, reply).await {
+                Ok(()) => ids.push(id),
+                // Most likely someone deleted the reply, so there's nothing
+                // left to edit.
+                Err(why) => tracing::warn!(message = %id, "Error editing reply: {why:?}"),
+            }
+        }
+        for &id in earlier {
+            if let Err(why) = self.inner.delete(msg.channel_id, id).await {
+                tracing::warn!(message = %id, "Error deleting reply: {why:?}");
+            }
+        }
+        if !unsent.is_empty() {
+            ids.extend(self.inner.send(source, unsent).await);
+        }
+        ids
+    }
+
+    async fn edit(
+        &self,
+        channel: ChannelId,
+        message: MessageId,
+        reply: Reply,
+    ) -> serenity::Result<()> {
+        self.inner.edit(channel, message, reply).await
+    }
+
+    async fn delete(&self, channel: ChannelId, message: MessageId) -> serenity::Result<()> {
+        self.inner.delete(channel, message).await
+    }
+
+    async fn post(
+        &self,
+        channel: ChannelId,
+        reply_to: Option<MessageId>,
+        reply: Reply,
+    ) -> serenity::Result<MessageId> {
+        self.inner.post(channel, reply_to, reply).await
+    }
+
+    fn start_typing(&self, channel: ChannelId) -> Option<Typing> {
+        self.inner.start_typing(channel)
+    }
+
+    async fn defer(&self, command: &CommandInteraction) {
+        self.inner.defer(command).await
+    }
+
+    async fn acknowledge(&self, component: &ComponentInteraction) {
+        self.inner.acknowledge(component).await
+    }
+
+    async fn permissions(&self, guild: GuildId, user: UserId) -> Option<Permissions> {
+        self.inner.permissions(guild, user).await
+    }
+
+    async fn channel_permissions(
+        &self,
+        channel: ChannelId,
+        user: UserId,
+    ) -> Option<(GuildId, Permissions)> {
+        self.inner.channel_permissions(channel, user).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::fake;
+
+    #[test]
+    fn forgets_the_oldest_commands() {
+        let replies = Replies::default();
+        for id in 1..=CAPACITY as u64 + 1 {
+            let msg = fake::message(id, fake::user(2, "pup"), "puppy why");
+            replies.record(&msg, vec![MessageId::new(id + 5000)]);
+        }
+        assert!(replies.get(MessageId::new(1)).is_none());
+        let last = replies.get(MessageId::new(CAPACITY as u64 + 1)).unwrap();
+        assert_eq!(last.replies, vec![MessageId::new(CAPACITY as u64 + 5001)]);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::commands::Reply;
+use crate::locale::Locale;
+use std::fmt;
+use std::time::Duration;
+
+/// What went wrong, in terms that are safe to show in Discord. Attach one
+/// to an [`anyhow::Error`] with `.context(...)` or return it directly; the
+/// full error chain only goes to the logs.
+// Commands only raise some of these.
+#[allow(dead_code)]
+#[derive(Debug)]
+pub enum UserError {
+    /// The thing the user asked about doesn't exist, e.g. a place or ticker.
+    /// `what` picks the `not-found-*` message, e.g. `place`.
+    NotFound { what: &'static str, name: String },
+    /// An external API failed or returned something we couldn't read.
+    UpstreamDown(&'static str),
+    /// An external API asked us to back off.
+    RateLimited(Option<Duration>),
+    /// The request can't work no matter how often it's retried. `what`
+    /// picks the `bad-input-*` message.
+    BadInput { what: &'static str, input: String },
+}
+
+impl UserError {
+    pub fn localize(&self, locale: Locale) -> String {
+        match self {
+            UserError::NotFound { what, name } => {
+                locale.text(&format!("not-found-{what}"), &[("name", name)])
+            }
+            UserError::UpstreamDown(service) => {
+                locale.text("upstream-down", &[("service", service)])
+            }
+            UserError::RateLimited(Some(wait)) => {
+                locale.text("rate-limited-for", &[("seconds", &wait.as_secs().max(1))])
+            }
+            UserError::RateLimited(None) => locale.text("rate-limited", &[]),
+            UserError::BadInput { what, input } => {
+                locale.text(&format!("bad-input-{what}"), &[("input", input)])
+            }
+        }
+    }
+}
+
+impl fmt::Display for UserError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.localize(Locale::En))
+    }
+}
+
+impl std::error::Error for UserError {}
+
+/// Logs `err` in full and turns it into a reply that only says as much as
+/// its [`UserError`], if it has one. A component that fails leaves its
+/// message alone.
+#[allow(dead_code)]
+pub fn reply(locale: Locale, command: &str, err: &anyhow::Error) -> Reply {
+    tracing::error!(command, "{err:?}");
+    if let Some(user_error) = err.downcast_ref::<UserError>() {
+        return failed(locale, &user_error.localize(locale));
+    }
+    failed(locale, &locale.text("something-went-wrong", &[]))
+}
+
+fn failed(locale: Locale, message: &str) -> Reply {
+    Reply {
+        failed: true,
+        ..Reply::text(format!("{} {message}", locale.error_prefix())).ephemeral()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hides_details_from_users() {
+        let err = anyhow::anyhow!("connection refused: secret-token")
+            .context(UserError::UpstreamDown("OpenWeather"))
+            .context("fetching weather");
+        assert_eq!(
+            reply(Locale::En, "weather", &err).content.as_deref(),
+            Some(
+                "<a:pupgone:1061133208676204605> It didn't work! OpenWeather isn't working right now, try again later."
+            )
+        );
+
+        let err = anyhow::anyhow!("secret-token").context("no context");
+        assert!(!reply(Locale::En, "weather", &err)
+            .content
+            .unwrap()
+            .contains("secret-token"));
+    }
+}
@@ -0,0 +1,168 @@
+use crate::commands::{Command, Help, Invocation, Reply, SLASH_COMMAND};
+use crate::locale::Locale;
+use crate::settings::{Denied, GuildSettings, Settings};
+use serenity::all::CommandOptionType;
+use serenity::async_trait;
+use serenity::builder::{CreateCommandOption, CreateEmbed, CreateEmbedFooter};
+
+/// Lists the registered commands, or explains one of them.
+pub struct HelpCommand;
+
+/// Where `name` may run in this guild, if it's restricted at all.
+fn status(locale: Locale, settings: &GuildSettings, name: &str) -> Option<String> {
+    match settings.restriction(name)? {
+        Denied::Disabled => Some(locale.text("help-disabled", &[])),
+        Denied::OnlyIn(channels) => {
+            let mentions: Vec<String> = channels.iter().map(|c| format!("<#{c}>")).collect();
+            let channels = mentions.join(", ");
+            Some(locale.text("help-only-in", &[("channels", &channels)]))
+        }
+    }
+}
+
+fn syntax(prefix: &str, help: &Help) -> String {
+    if help.usage.is_empty() {
+        format!("{prefix} {}", help.name)
+    } else {
+        format!("{prefix} {} {}", help.name, help.usage)
+    }
+}
+
+fn overview(inv: &Invocation<'_>, settings: &GuildSettings) -> Reply {
+    let prefix = settings.prefix();
+    let (triggers, commands) = inv.registry.help(inv.locale);
+    let mut lines = Vec::new();
+    let triggers = triggers
+        .iter()
+        .map(|help| (format!("`{}`", help.examples.join("`, `")), help));
+    let commands = commands
+        .iter()
+        .map(|help| (format!("`{}`", syntax(prefix, help)), help));
+    for (syntax, help) in triggers.chain(commands) {
+        let mut line = format!("{syntax} {}", help.description);
+        if let Some(status) = status(inv.locale, settings, help.name) {
+            line.push_str(&format!(" ({status})"));
+        }
+        lines.push(line);
+    }
+    Reply::embed(
+        CreateEmbed::new()
+            .title(format!("{prefix} help"))
+            .description(lines.join("\n"))
+            .footer(CreateEmbedFooter::new(inv.locale.text(
+                "help-footer",
+                &[("prefix", &prefix), ("slash", &SLASH_COMMAND)],
+            ))),
+    )
+}
+
+fn details(locale: Locale, settings: &GuildSettings, help: &Help) -> Reply {
+    let prefix = settings.prefix();
+    let mut embed = CreateEmbed::new()
+        .title(format!("{prefix} {}", help.name))
+        .description(&help.description)
+        .field(
+            locale.text("help-usage", &[]),
+            format!(
+                "`{}`\n`/{SLASH_COMMAND} {}`",
+                syntax(prefix, help),
+                help.name
+            ),
+            false,
+        );
+
+    if !help.options.is_empty() {
+        let options: Vec<String> = help
+            .options
+            .iter()
+            .map(|option| {
+                let mut line = format!("`{}` {}", option.name, option.description);
+                if option.required {
+                    line.push_str(&format!(" ({})", locale.text("help-required", &[])));
+                }
+                if !option.choices.is_empty() {
+                    line.push_str(&format!(": {}", option.choices.join(", ")));
+                }
+                line
+            })
+            .collect();
+        embed = embed.field(locale.text("help-options", &[]), options.join("\n"), false);
+    }
+    if !help.examples.is_empty() {
+        let examples: Vec<String> = help
+            .examples
+            .iter()
+            .map(|args| format!("`{}`", format!("{prefix} {} {args}", help.name).trim_end()))
+            .collect();
+        embed = embed.field(
+            locale.text("help-examples", &[]),
+            examples.join("\n"),
+            false,
+        );
+    }
+    if !help.aliases.is_empty() {
+        embed = embed.field(
+            locale.text("help-aliases", &[]),
+            help.aliases.join(", "),
+            false,
+        );
+    }
+    let status =
+        status(locale, settings, help.name).unwrap_or_else(|| locale.text("help-enabled", &[]));
+    Reply::embed(embed.field(locale.text("help-status", &[]), status, false))
+}
+
+#[async_trait]
+impl Command for HelpCommand {
+    type Args = String;
+
+    fn name(&self) -> &'static str {
+        "help"
+    }
+
+    fn description(&self, locale: Locale) -> String {
+        locale.text("description-help", &[])
+    }
+
+    fn rerun_on_edit(&self) -> bool {
+        true
+    }
+
+    fn usage(&self) -> &'static str {
+        "[command]"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["", "stonk"]
+    }
+
+    fn parse(&self, args: &str) -> Option<String> {
+        if args.contains(char::is_whitespace) {
+            None
+        } else {
+            Some(args.to_lowercase())
+        }
+    }
+
+    fn options(&self) -> Vec<CreateCommandOption> {
+        vec![CreateCommandOption::new(
+            CommandOptionType::String,
+            "command",
+            Locale::En.text("option-help-command", &[]),
+        )]
+    }
+
+    async fn run(&self, inv: &Invocation<'_>, command: String) -> Vec<Reply> {
+        let settings = inv.shared::<Settings>().await.get(inv.guild_id).await;
+        if command.is_empty() {
+            return vec![overview(inv, &settings)];
+        }
+        match inv.registry.help_for(&command, inv.locale) {
+            Some(help) => vec![details(inv.locale, &settings, &help)],
+            None => vec![Reply::text(inv.locale.error(
+                "no-such-command-try-help",
+                &[("command", &command), ("prefix", &settings.prefix())],
+            ))],
+        }
+    }
+}
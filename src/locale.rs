@@ -0,0 +1,227 @@
+#[cfg(feature = "why")]
+use crate::puppywhy::Grammar;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+
+/// A language the bot can reply in.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Locale {
+    #[default]
+    En,
+    De,
+    Es,
+}
+
+impl Locale {
+    pub const ALL: [Locale; 3] = [Locale::En, Locale::De, Locale::Es];
+
+    /// The ISO 639-1 code, which OpenWeather also accepts as `lang`.
+    pub fn code(self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::De => "de",
+            Locale::Es => "es",
+        }
+    }
+
+    /// What Discord calls the languages this covers, for describing slash
+    /// commands to users who picked them.
+    pub fn discord_codes(self) -> &'static [&'static str] {
+        match self {
+            Locale::En => &["en-US", "en-GB"],
+            Locale::De => &["de"],
+            Locale::Es => &["es-ES", "es-419"],
+        }
+    }
+
+    /// Accepts codes like `de`, and regional ones like `es-MX`.
+    pub fn parse(s: &str) -> Option<Locale> {
+        let language = s.split(['-', '_']).next()?.to_lowercase();
+        Locale::ALL.iter().copied().find(|l| l.code() == language)
+    }
+
+    /// Every supported code, formatted for a reply.
+    pub fn list() -> String {
+        let codes: Vec<String> = Locale::ALL.iter().map(|l| format!("`{l}`")).collect();
+        codes.join(", ")
+    }
+
+    fn source(self) -> &'static str {
+        match self {
+            Locale::En => include_str!("../assets/locales/en.toml"),
+            Locale::De => include_str!("../assets/locales/de.toml"),
+            Locale::Es => include_str!("../assets/locales/es.toml"),
+        }
+    }
+
+    /// The message for `key` with each `{name}` replaced by its argument.
+    /// Messages missing from this locale's catalog fall back to English.
+    pub fn text(self, key: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+        let template = CATALOGS[&self]
+            .messages
+            .get(key)
+            .or_else(|| CATALOGS[&Locale::En].messages.get(key));
+        match template {
+            Some(template) => fill(template, |name| {
+                let (_, value) = args.iter().find(|(n, _)| *n == name)?;
+                Some(value.to_string())
+            }),
+            None => {
+                tracing::warn!(key, "missing message");
+                key.to_string()
+            }
+        }
+    }
+
+    /// The rules `puppy why` makes up excuses from.
+    #[cfg(feature = "why")]
+    pub fn grammar(self) -> &'static Grammar {
+        &CATALOGS[&self].why
+    }
+
+    /// The "it didn't work" puppy that starts every error reply.
+    pub fn error_prefix(self) -> String {
+        format!("{ERROR_EMOJI} {}", self.text("error", &[]))
+    }
+
+    /// [`Locale::text`] behind the [`Locale::error_prefix`].
+    pub fn error(self, key: &str, args: &[(&str, &dyn fmt::Display)]) -> String {
+        format!("{} {}", self.error_prefix(), self.text(key, args))
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+pub const ERROR_EMOJI: &str = "<a:pupgone:1061133208676204605>";
+
+/// `template` with each `{name}` replaced by `value(name)`. It's one pass,
+/// so whatever is filled in is never itself searched for names, and names
+/// `value` doesn't know are left as they are.
+pub fn fill(template: &str, mut value: impl FnMut(&str) -> Option<String>) -> String {
+    let mut filled = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        filled.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let replaced = after.find('}').and_then(|end| {
+            let name = &after[..end];
+            let valid =
+                !name.is_empty() && name.chars().all(|c| c == '_' || c.is_ascii_lowercase());
+            Some((value(name).filter(|_| valid)?, end))
+        });
+        match replaced {
+            Some((value, end)) => {
+                filled.push_str(&value);
+                rest = &after[end + 1..];
+            }
+            None => {
+                filled.push('{');
+                rest = after;
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+struct Catalog {
+    messages: HashMap<String, String>,
+    #[cfg(feature = "why")]
+    why: Grammar,
+}
+
+impl Catalog {
+    /// Messages are the top-level strings, and the `[why]` table holds the
+    /// grammar.
+    fn parse(source: &str) -> Result<Catalog, toml::de::Error> {
+        let mut table: toml::Table = toml::from_str(source)?;
+        #[cfg(feature = "why")]
+        let why = match table.remove("why") {
+            Some(why) => why.try_into()?,
+            None => Grammar::default(),
+        };
+        #[cfg(not(feature = "why"))]
+        table.remove("why");
+        Ok(Catalog {
+            messages: table.try_into()?,
+            #[cfg(feature = "why")]
+            why,
+        })
+    }
+}
+
+lazy_static! {
+    static ref CATALOGS: HashMap<Locale, Catalog> = Locale::ALL
+        .iter()
+        .copied()
+        .map(|locale| {
+            let catalog = Catalog::parse(locale.source())
+                .unwrap_or_else(|why| panic!("Invalid catalog for {}: {}", locale, why));
+            (locale, catalog)
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+    use std::collections::BTreeSet;
+
+    fn placeholders(template: &str) -> BTreeSet<String> {
+        let re = Regex::new(r"\{([a-z_]+)\}").unwrap();
+        re.captures_iter(template)
+            .map(|c| c[1].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn catalogs_match_english() {
+        let english = &CATALOGS[&Locale::En].messages;
+        for locale in Locale::ALL {
+            let catalog = &CATALOGS[&locale].messages;
+            for (key, template) in english {
+                let translated = catalog
+                    .get(key)
+                    .unwrap_or_else(|| panic!("{} is missing {}", locale, key));
+                assert_eq!(
+                    placeholders(translated),
+                    placeholders(template),
+                    "{} {}",
+                    locale,
+                    key
+                );
+            }
+            for key in catalog.keys() {
+                assert!(english.contains_key(key), "{} has extra {}", locale, key);
+            }
+        }
+    }
+
+    #[test]
+    fn fills_in_arguments() {
+        assert_eq!(
+            Locale::De.text("no-such-command", &[("command", &"bark")]),
+            "Es gibt keinen Befehl `bark`."
+        );
+        assert_eq!(
+            Locale::En.text("slow-down", &[("command", &"{seconds}"), ("seconds", &3)]),
+            "Slow down! 🐶 You can use `{seconds}` again in 3 seconds."
+        );
+        assert_eq!(Locale::En.text("none", &[("x", &1)]), "none");
+        assert_eq!(
+            fill("{a} {b} {} {A} {a", |name| (name == "a")
+                .then(|| "1".to_string())),
+            "1 {b} {} {A} {a"
+        );
+        assert_eq!(Locale::parse("es-MX"), Some(Locale::Es));
+        assert_eq!(Locale::parse("xx"), None);
+    }
+}
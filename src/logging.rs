@@ -0,0 +1,134 @@
+use crate::config::Config;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::Deserialize;
+use std::io::{self, Write};
+use std::sync::Arc;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Pretty,
+    Json,
+}
+
+const REDACTED: &str = "[redacted]";
+
+lazy_static! {
+    /// Credentials that end up in URLs or headers no matter which key they
+    /// hold, e.g. `appid=...` in an OpenWeather request.
+    static ref SECRET_PARAM: Regex =
+        Regex::new(r"(?i)\b(key|appid|token|api_key|apikey)=[^&\s\x22]+").unwrap();
+    static ref BEARER: Regex = Regex::new(r"(?i)\bbearer\s+[^\s\x22]+").unwrap();
+}
+
+/// Scrubs secrets out of formatted log lines before they're written.
+#[derive(Clone, Default)]
+pub struct Redactor {
+    secrets: Arc<Vec<String>>,
+}
+
+impl Redactor {
+    /// Redacts every credential in `config`, plus anything that looks like
+    /// one.
+    pub fn new(config: &Config) -> Self {
+        let secrets = [
+            &config.discord.token,
+            &config.weather.openweather_token,
+            &config.weather.google_maps_token,
+            &config.metar.avwx_token,
+            &config.gpt.openrouter_api_key,
+        ];
+        Redactor {
+            secrets: Arc::new(secrets.iter().filter_map(|s| (*s).clone()).collect()),
+        }
+    }
+
+    pub fn redact(&self, line: &str) -> String {
+        let mut line = SECRET_PARAM
+            .replace_all(line, format!("$1={REDACTED}").as_str())
+            .into_owned();
+        line = BEARER
+            .replace_all(&line, format!("Bearer {REDACTED}").as_str())
+            .into_owned();
+        for secret in self.secrets.iter() {
+            line = line.replace(secret.as_str(), REDACTED);
+        }
+        line
+    }
+}
+
+/// A writer that redacts each write on its way to `inner`. The formatter
+/// writes every event in one go, so secrets never straddle two writes.
+pub struct RedactingWriter<W> {
+    redactor: Redactor,
+    inner: W,
+}
+
+impl<W: Write> Write for RedactingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let line = self.redactor.redact(&String::from_utf8_lossy(buf));
+        self.inner.write_all(line.as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for Redactor {
+    type Writer = RedactingWriter<io::Stderr>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RedactingWriter {
+            redactor: self.clone(),
+            inner: io::stderr(),
+        }
+    }
+}
+
+/// Installs the global subscriber. `$RUST_LOG` takes precedence over the
+/// configured level.
+pub fn init(config: &Config) -> anyhow::Result<()> {
+    let filter = match std::env::var("RUST_LOG") {
+        Ok(filter) => EnvFilter::try_new(filter)?,
+        Err(_) => EnvFilter::try_new(&config.log.level)?,
+    };
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(Redactor::new(config));
+    let installed = match config.log.format {
+        Format::Pretty => builder.try_init(),
+        Format::Json => builder.json().try_init(),
+    };
+    installed.map_err(|why| anyhow::anyhow!(why))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_secrets() {
+        let mut config = Config::default();
+        config.gpt.openrouter_api_key = Some("sk-or-hunter2".to_string());
+        let redactor = Redactor::new(&config);
+
+        assert_eq!(
+            redactor.redact("GET /data/3.0/onecall?lat=1&appid=abc123&units=metric"),
+            "GET /data/3.0/onecall?lat=1&appid=[redacted]&units=metric"
+        );
+        assert_eq!(
+            redactor.redact(r#"headers: {"authorization": "Bearer xyz"}"#),
+            r#"headers: {"authorization": "Bearer [redacted]"}"#
+        );
+        assert_eq!(
+            redactor.redact("error: sk-or-hunter2 is invalid"),
+            "error: [redacted] is invalid"
+        );
+    }
+}
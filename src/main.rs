@@ -1,30 +1,110 @@
-use lazy_static::lazy_static;
-use regex::Regex;
-use serenity::all::CreateEmbedFooter;
-use serenity::builder::{CreateAttachment, CreateEmbed, CreateMessage};
-use serenity::model::Timestamp;
 use serenity::{
     async_trait,
-    model::guild::Emoji,
-    model::{channel::Message, gateway::Ready},
+    model::application::{Command, Interaction},
+    model::{channel::Message, event::MessageUpdateEvent, gateway::Ready},
     prelude::*,
 };
-use std::{collections::HashMap, env, fs::File, io::prelude::*, sync::Arc};
 
+mod admin;
+mod audit;
+mod cache;
+mod commands;
+mod components;
+mod config;
+mod cooldown;
+mod edits;
+mod error;
+mod help;
+mod locale;
+mod logging;
+mod metrics;
+mod prefs;
+#[cfg(feature = "chess")]
 mod puppychess;
+#[cfg(feature = "gpt")]
 mod puppygpt;
+#[cfg(feature = "remind")]
+mod puppyremind;
+#[cfg(feature = "stonk")]
 mod puppystonk;
+#[cfg(feature = "weather")]
 mod puppyweather;
+#[cfg(feature = "why")]
 mod puppywhy;
+mod puppywoof;
+#[cfg(any(feature = "chess", feature = "stonk"))]
+mod render;
+mod retry;
+mod scheduler;
+mod settings;
+mod shutdown;
+mod store;
+mod transport;
+mod upstream;
 mod utils;
-use rand::prelude::IndexedRandom;
+
+use std::sync::Arc;
+use transport::{SerenityTransport, Transport};
 
 struct Handler {
-    openweather_token: String,
-    google_maps_token: String,
-    openrouter_api_key: String,
-    avwx_token: String,
-    no_reasons: Vec<String>,
+    registry: commands::Registry,
+    shutdown: shutdown::Tracker,
+}
+
+impl Handler {
+    fn new(registry: commands::Registry) -> Self {
+        Handler {
+            registry,
+            shutdown: shutdown::Tracker::default(),
+        }
+    }
+
+    async fn handle_message(
+        &self,
+        transport: &dyn Transport,
+        data: &Arc<RwLock<TypeMap>>,
+        msg: &Message,
+    ) {
+        if msg.author.id == transport.current_user_id() {
+            return;
+        }
+        let _active = match self.shutdown.begin() {
+            Some(active) => active,
+            None => return,
+        };
+
+        self.registry.dispatch(transport, data, msg).await;
+    }
+
+    /// Re-runs the command in `msg` after its author edited it, editing the
+    /// bot's earlier replies. Only messages the bot answered recently count.
+    async fn handle_edit(
+        &self,
+        transport: &dyn Transport,
+        data: &Arc<RwLock<TypeMap>>,
+        msg: &Message,
+    ) {
+        if msg.author.id == transport.current_user_id() {
+            return;
+        }
+        let _active = match self.shutdown.begin() {
+            Some(active) => active,
+            None => return,
+        };
+        let replies = data.read().await.get::<edits::Replies>().cloned();
+        let sent = match replies.and_then(|replies| replies.get(msg.id)) {
+            Some(sent) => sent,
+            None => return,
+        };
+        // Discord also sends updates when it embeds a link, which don't
+        // change the content.
+        if sent.source.content == msg.content {
+            return;
+        }
+
+        let transport = edits::EditTransport::new(transport, sent.replies);
+        self.registry.dispatch_edit(&transport, data, msg).await;
+    }
 }
 
 #[async_trait]
@@ -35,180 +115,47 @@ impl EventHandler for Handler {
     // Event handlers are dispatched through a threadpool, and so multiple
     // events can be dispatched simultaneously.
     async fn message(&self, ctx: Context, msg: Message) {
-        if msg.author.id == ctx.cache.current_user().id {
-            return;
-        }
+        let transport = SerenityTransport::new(&ctx);
+        self.handle_message(&transport, &ctx.data, &msg).await;
+    }
 
-        lazy_static! {
-            static ref WOOF_RE: Regex = Regex::new(
-                r"^((oua+f+\s*)+|(w(a|o|0|u|🌕)+r*f\s*)+|(aw+(o|0|🌕)+\s*)+|(b(a|o)+rk\s*)+|(汪\s*)+|(ワン\s*)+|(わん\s*)+|(гав\s*)+|(uowhf\s*)+|(arflee+bloo+\s*)+)+(!|！)*$"
-            )
-            .unwrap();
-            static ref WEATHER_RE: Regex = Regex::new(r"^puppy weather\s\w+").unwrap();
-            static ref METAR_RE: Regex = Regex::new(r"^puppy metar\s\w+").unwrap();
-            static ref STONK_RE: Regex = Regex::new(r"^puppy stonk\s\w+").unwrap();
-            static ref CHESS_RE: Regex = Regex::new(r"^puppy chess\s\w*").unwrap();
-            static ref GPT_RE: Regex = Regex::new(r"^puppy gpt\s\w*").unwrap();
-        }
-        const ERROR_MSG: &str = "<a:pupgone:1061133208676204605> It didn't work!";
-        let content = &msg.content;
-        let lower = content.to_lowercase();
-        if WOOF_RE.is_match(&lower) {
-            if let Err(why) = msg.reply(&ctx.http, content).await {
-                eprintln!("Error sending message: {:?}", why);
-            }
-        } else if lower == "puppy why" {
-            if let Err(why) = msg.reply(&ctx.http, puppywhy::why()).await {
-                eprintln!("Error sending message: {:?}", why);
-            }
-        } else if lower == "puppy how" {
-            if let Err(why) = msg
-                .reply(&ctx.http, "https://github.com/dllu/discord-woofer-rust")
-                .await
-            {
-                eprintln!("Error sending message: {:?}", why);
-            }
-        } else if lower == "puppy no" {
-            let reason = self.no_reasons.choose(&mut rand::rng()).unwrap();
-            if let Err(why) = msg.reply(&ctx.http, reason).await {
-                eprintln!("Error sending message: {:?}", why);
-            }
-        } else if STONK_RE.is_match(&lower) {
-            let typing = msg.channel_id.start_typing(&ctx.http);
-            let ticker = &lower[12..];
-            let res = puppystonk::stonk(ticker).await;
-            match res {
-                Ok((stonk, filename, timestamp)) => {
-                    typing.stop();
-                    let embed = CreateEmbed::new()
-                        .title(lower)
-                        .description(stonk)
-                        .image(format!("attachment://{filename}"))
-                        .timestamp(Timestamp::from_unix_timestamp(timestamp).unwrap());
-                    let builder = CreateMessage::new().embed(embed).add_file(
-                        CreateAttachment::path(format!("./{filename}"))
-                            .await
-                            .unwrap(),
-                    );
-
-                    if let Err(why) = msg.channel_id.send_message(&ctx.http, builder).await {
-                        println!("Error sending message: {why:?}");
-                    }
-                    std::fs::remove_file(filename).unwrap();
-                }
-                Err(whyy) => {
-                    eprintln!("Error with getting stonk: {:?}", whyy);
-                    if let Err(why) = msg.reply(&ctx.http, format!("{ERROR_MSG} {whyy:?}")).await {
-                        eprintln!("Error sending message: {:?}", why);
-                    }
-                }
-            }
-        } else if WEATHER_RE.is_match(&lower) {
-            let typing = msg.channel_id.start_typing(&ctx.http);
-            let mut units = String::from("kelvin");
-            let mut offset = 14; // base length of "puppy weather "
-            lazy_static! {
-                static ref CELSIUS_RE: Regex = Regex::new(r"^celsius\s\w*").unwrap();
-                static ref FAHRENHEIT_RE: Regex = Regex::new(r"^fahrenheit\s\w*").unwrap();
-                static ref KELVIN_RE: Regex = Regex::new(r"^kelvin\s\w*").unwrap();
-            }
-            if CELSIUS_RE.is_match(&lower[offset..]) {
-                offset += 8; // base length of "celsius "
-                units = String::from("celsius");
-            } else if FAHRENHEIT_RE.is_match(&lower[offset..]) {
-                offset += 11; // base length of "fahrenheit "
-                units = String::from("fahrenheit");
-            } else if KELVIN_RE.is_match(&lower[offset..]) {
-                offset += 7; // base length of "kelvin "
-            }
-            let address = &lower[offset..];
-            // TODO: error handlin
-            let location = puppyweather::geocode(address.to_string(), &self.google_maps_token)
-                .await
-                .unwrap();
-            let weather = puppyweather::weather(&location, &self.openweather_token)
-                .await
-                .unwrap();
-            let response =
-                puppyweather::weather_string(address.to_string(), &location, &units, weather);
-            typing.stop();
-            if let Err(why) = msg.reply(&ctx.http, response).await {
-                eprintln!("Error sending message: {:?}", why);
-            }
-        } else if METAR_RE.is_match(&lower) {
-            let typing = msg.channel_id.start_typing(&ctx.http);
-            let address = &lower[12..];
-            // TODO: error handlin
-            let weather = puppyweather::metar(address, &self.avwx_token)
-                .await
-                .unwrap();
-            typing.stop();
-            if let Err(why) = msg.reply(&ctx.http, weather).await {
-                eprintln!("Error sending message: {:?}", why);
-            }
-        } else if CHESS_RE.is_match(&lower) {
-            let mut res = puppychess::chess(&ctx, &msg).await;
-            if let Err(why2) = res {
-                eprintln!("Error making chess move: {:?}", why2);
+    async fn message_update(
+        &self,
+        ctx: Context,
+        _old_if_available: Option<Message>,
+        _new: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        // The cache doesn't keep messages, so rebuild the edited one from
+        // the copy kept alongside the replies.
+        let replies = ctx.data.read().await.get::<edits::Replies>().cloned();
+        let mut msg = match replies.and_then(|replies| replies.get(event.id)) {
+            Some(sent) => sent.source,
+            None => return,
+        };
+        event.apply_to_message(&mut msg);
+        let transport = SerenityTransport::new(&ctx);
+        self.handle_edit(&transport, &ctx.data, &msg).await;
+    }
 
-                res = puppychess::chess_illegal_move(&ctx, &msg).await;
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let _active = match self.shutdown.begin() {
+            Some(active) => active,
+            None => return,
+        };
+        let transport = SerenityTransport::new(&ctx);
+        match interaction {
+            Interaction::Command(command) if command.data.name == commands::SLASH_COMMAND => {
+                self.registry
+                    .dispatch_slash(&transport, &ctx.data, &command)
+                    .await;
             }
-            if let Err(why) = puppychess::reply(&ctx, &msg, res.unwrap()).await {
-                eprintln!("Error sending message: {:?}", why);
-            }
-        } else if GPT_RE.is_match(&lower) {
-            let typing = msg.channel_id.start_typing(&ctx.http);
-            let response = puppygpt::gpt(&ctx, &msg, &self.openrouter_api_key).await;
-            match response {
-                Ok((think, res)) => {
-                    let parts = split_string(&res);
-
-                    typing.stop();
-
-                    for (i, part) in parts.iter().enumerate() {
-                        let mut temp_file = None;
-                        let mut builder =
-                            CreateMessage::new().content(part).reference_message(&msg);
-                        if i == 0 {
-                            if let Some(ref thonk) = think {
-                                if thonk.len() < 2000 {
-                                    builder = builder.embed(
-                                        CreateEmbed::new()
-                                            .description("Think")
-                                            .footer(CreateEmbedFooter::new(thonk)),
-                                    );
-                                } else {
-                                    let filename =
-                                        format!("think_{}.txt", blake3::hash(thonk.as_bytes()));
-                                    let mut file = File::create(&filename).unwrap();
-                                    file.write_all(thonk.as_bytes()).unwrap();
-                                    let attachment =
-                                        CreateAttachment::path(filename.to_string()).await.unwrap();
-
-                                    builder = builder.add_file(attachment);
-                                    temp_file = Some(filename);
-                                }
-                            }
-                        }
-
-                        if let Err(why) = msg.channel_id.send_message(&ctx.http, builder).await {
-                            eprintln!("Error sending reply: {why:?}");
-                        }
-                        if let Some(filename) = temp_file {
-                            if let Err(why) = std::fs::remove_file(filename) {
-                                eprintln!("Error deleting temporary file: {:?}", why);
-                            }
-                        }
-                    }
-                }
-                Err(why2) => {
-                    typing.stop();
-
-                    if let Err(why) = msg.reply(&ctx.http, format!("{ERROR_MSG} {why2:?}")).await {
-                        eprintln!("Error sending message: {:?}", why);
-                    }
-                }
+            Interaction::Component(component) => {
+                self.registry
+                    .dispatch_component(&transport, &ctx.data, &component)
+                    .await;
             }
+            _ => {}
         }
     }
 
@@ -219,52 +166,154 @@ impl EventHandler for Handler {
     //
     // In this case, just print what the current user's username is.
     async fn ready(&self, ctx: Context, ready: Ready) {
-        let mut emojis = Vec::new();
-        for guild_status in &ready.guilds {
-            match guild_status.id.emojis(&ctx.http).await {
-                Ok(guild_emojis) => emojis.extend(guild_emojis),
-                Err(why) => eprintln!(
-                    "Error fetching emojis for guild {}: {:?}",
-                    guild_status.id, why
-                ),
-            }
+        if let Err(why) =
+            Command::set_global_commands(&ctx.http, vec![self.registry.slash_command()]).await
+        {
+            tracing::error!("Error registering slash commands: {why:?}");
         }
 
-        let emoji_count = puppygpt::set_available_emojis(emojis);
-        println!("{} is connected!", ready.user.name);
-        println!("Loaded {emoji_count} available custom emojis for puppy gpt");
+        tracing::info!(user = %ready.user.name, "connected");
+
+        let scheduler = ctx.data.read().await.get::<scheduler::Scheduler>().cloned();
+        if let Some(scheduler) = scheduler {
+            scheduler.start(scheduler::JobContext {
+                transport: Arc::new(SerenityTransport::new(&ctx)),
+                data: ctx.data.clone(),
+            });
+        }
+
+        #[cfg(feature = "gpt")]
+        {
+            let mut emojis = Vec::new();
+            for guild_status in &ready.guilds {
+                match guild_status.id.emojis(&ctx.http).await {
+                    Ok(guild_emojis) => emojis.extend(guild_emojis),
+                    Err(why) => tracing::warn!(
+                        guild = %guild_status.id,
+                        "Error fetching emojis: {why:?}"
+                    ),
+                }
+            }
+
+            let emoji_count = puppygpt::set_available_emojis(emojis);
+            tracing::info!(emoji_count, "loaded custom emojis for puppy gpt");
+        }
     }
 
+    #[cfg(feature = "gpt")]
     async fn guild_emojis_update(
         &self,
         ctx: Context,
         _guild_id: serenity::all::GuildId,
-        _current_state: HashMap<serenity::all::EmojiId, Emoji>,
+        _current_state: std::collections::HashMap<serenity::all::EmojiId, serenity::all::Emoji>,
     ) {
         let mut emojis = Vec::new();
         for guild_id in ctx.cache.guilds() {
             match guild_id.emojis(&ctx.http).await {
                 Ok(guild_emojis) => emojis.extend(guild_emojis),
-                Err(why) => eprintln!("Error fetching emojis for guild {guild_id}: {why:?}"),
+                Err(why) => tracing::warn!(guild = %guild_id, "Error fetching emojis: {why:?}"),
             }
         }
 
         let emoji_count = puppygpt::set_available_emojis(emojis);
-        println!("Reloaded {emoji_count} available custom emojis for puppy gpt");
+        tracing::info!(emoji_count, "reloaded custom emojis for puppy gpt");
+    }
+}
+
+/// The scheduler, with a handler for each kind of job this build runs.
+fn scheduler(store: &store::Store) -> scheduler::Scheduler {
+    let scheduler = scheduler::Scheduler::new(store.clone());
+    #[cfg(feature = "remind")]
+    scheduler.register(puppyremind::JOB_KIND, puppyremind::Deliver);
+    scheduler.register(audit::PRUNE_JOB, audit::Prune);
+    // It's saved with the other jobs, so it only needs adding once.
+    if scheduler.jobs(audit::PRUNE_JOB).is_empty() {
+        if let Err(why) = scheduler.schedule_cron(audit::PRUNE_JOB, "@daily", ()) {
+            tracing::error!("Error scheduling audit log pruning: {why:?}");
+        }
+    }
+    scheduler
+}
+
+/// Every command this build supports, with integrations that lack
+/// credentials in `config` replaced by placeholders.
+fn build_registry(config: &config::Config) -> commands::Registry {
+    let mut registry = commands::Registry::default();
+    registry
+        .trigger(puppywoof::WoofCommand)
+        .register(puppywoof::HowCommand)
+        .register(settings::SettingsCommand)
+        .register(prefs::LocaleCommand)
+        .register(help::HelpCommand)
+        .register(admin::AdminCommand::default());
+
+    #[cfg(feature = "why")]
+    registry
+        .register(puppywhy::WhyCommand)
+        .register(puppywhy::NoCommand);
+
+    #[cfg(feature = "stonk")]
+    registry.register(puppystonk::StonkCommand);
+
+    #[cfg(feature = "weather")]
+    registry
+        .register_configured(
+            "weather",
+            config
+                .weather
+                .openweather_token
+                .clone()
+                .zip(config.weather.google_maps_token.clone())
+                .map(
+                    |(openweather_token, google_maps_token)| puppyweather::WeatherCommand {
+                        openweather_token,
+                        google_maps_token,
+                    },
+                ),
+        )
+        .register_configured(
+            "metar",
+            config
+                .metar
+                .avwx_token
+                .clone()
+                .map(|avwx_token| puppyweather::MetarCommand { avwx_token }),
+        );
+
+    #[cfg(feature = "chess")]
+    registry.register(puppychess::ChessCommand);
+
+    #[cfg(feature = "remind")]
+    registry
+        .register(puppyremind::RemindCommand)
+        .register(prefs::TimezoneCommand);
+
+    #[cfg(feature = "gpt")]
+    registry.register_configured(
+        "gpt",
+        config
+            .gpt
+            .openrouter_api_key
+            .clone()
+            .map(|api_key| puppygpt::GptCommand { api_key }),
+    );
+
+    for (alias, command) in &config.aliases {
+        registry.alias(alias, command);
     }
+
+    registry
 }
 
 #[tokio::main]
 async fn main() {
-    let discord_token =
-        env::var("DISCORD_TOKEN").expect("Expected $DISCORD_TOKEN in the environment");
-    let openweather_token =
-        env::var("FORECAST_TOKEN").expect("Expected $FORECAST_TOKEN in the environment");
-    let google_maps_token =
-        env::var("GOOGLE_MAPS_TOKEN").expect("Expected $GOOGLE_MAPS_TOKEN in the environment");
-    let openrouter_api_key =
-        env::var("OPENROUTER_API_KEY").expect("Expected $OPENROUTER_API_KEY in the environment");
-    let avwx_token = env::var("AVWX_TOKEN").expect("Expected $AVWX_TOKEN in the environment");
+    let config = config::Config::load().expect("Failed to load config");
+    logging::init(&config).expect("Invalid log config");
+    let discord_token = config
+        .discord
+        .token
+        .clone()
+        .expect("Expected a Discord token in woofer.toml or $DISCORD_TOKEN");
 
     let intents = GatewayIntents::GUILDS
         | GatewayIntents::GUILD_EMOJIS_AND_STICKERS
@@ -272,22 +321,25 @@ async fn main() {
         | GatewayIntents::DIRECT_MESSAGES
         | GatewayIntents::MESSAGE_CONTENT;
 
-    let mut file = File::open("assets/no_reasons.json").expect("Failed to open no_reasons.json");
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)
-        .expect("Failed to read no_reasons.json");
-    let no_reasons: Vec<String> = serde_json::from_str(&contents).expect("Invalid JSON format");
+    let store = config.store.open().expect("Failed to open store");
+    let upstream = config.http.open().expect("Invalid http config");
+    let audit = config.audit.open().expect("Invalid audit config");
+
+    if let Some(addr) = &config.metrics.listen {
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .expect("Failed to bind the metrics listener");
+        tracing::info!(%addr, "serving metrics");
+        tokio::spawn(metrics::serve(listener));
+    }
+
+    let handler = Handler::new(build_registry(&config));
+    let tracker = handler.shutdown.clone();
+    let jobs = scheduler(&store);
 
     // Create a new instance of the Client, logging in as a bot. This will
     // automatically prepend your bot token with "Bot ", which is a requirement
     // by Discord for bot users.
-    let handler = Handler {
-        openweather_token,
-        google_maps_token,
-        openrouter_api_key,
-        avwx_token,
-        no_reasons,
-    };
 
     let mut client = Client::builder(&discord_token, intents)
         .event_handler(handler)
@@ -295,49 +347,712 @@ async fn main() {
         .expect("Err creating client");
     {
         let mut data = client.data.write().await;
-        data.insert::<puppychess::ChessGame>(Arc::new(RwLock::new(HashMap::default())));
+        data.insert::<settings::Settings>(settings::Settings::new(store.clone()));
+        data.insert::<prefs::Prefs>(prefs::Prefs::new(store.clone()));
+        data.insert::<scheduler::Scheduler>(jobs.clone());
+        data.insert::<store::Store>(store);
+        data.insert::<upstream::Upstream>(upstream);
+        data.insert::<audit::Audit>(audit);
+        data.insert::<admin::Owners>(admin::Owners::new(&config.discord.owners));
+        #[cfg(feature = "why")]
+        data.insert::<puppywhy::NoReasons>(
+            puppywhy::NoReasons::load().expect("Failed to load no_reasons.json"),
+        );
+        data.insert::<cooldown::Cooldowns>(cooldown::Cooldowns::default());
+        data.insert::<edits::Replies>(edits::Replies::default());
+        #[cfg(feature = "chess")]
+        data.insert::<puppychess::ChessGame>(Arc::new(RwLock::new(
+            std::collections::HashMap::default(),
+        )));
     }
 
+    let shard_manager = client.shard_manager.clone();
+    #[cfg(feature = "chess")]
+    let data = client.data.clone();
+    tokio::spawn(async move {
+        shutdown::signal().await;
+        tracing::info!("shutting down");
+        let (unfinished, unfinished_jobs) = tokio::join!(
+            tracker.stop(shutdown::DRAIN_TIMEOUT),
+            jobs.stop(shutdown::DRAIN_TIMEOUT),
+        );
+        if unfinished > 0 {
+            tracing::warn!(unfinished, "gave up waiting for commands");
+        }
+        if unfinished_jobs > 0 {
+            tracing::warn!(unfinished_jobs, "gave up waiting for jobs");
+        }
+        #[cfg(feature = "chess")]
+        puppychess::save_all(&data).await;
+        shard_manager.shutdown_all().await;
+    });
+
     // Finally, start a single shard, and start listening to events.
     //
     // Shards will automatically attempt to reconnect, and will perform
     // exponential backoff until it reconnects.
     if let Err(why) = client.start().await {
-        println!("Client error: {:?}", why);
+        tracing::error!("Client error: {why:?}");
     }
 }
 
-fn split_string(input: &str) -> Vec<String> {
-    const MAX_LENGTH: usize = 2000;
-    let mut result = Vec::new();
-
-    // Check if the input is already within the limit.
-    if input.len() <= MAX_LENGTH {
-        result.push(input.to_string());
-    } else {
-        let mut start_index = 0;
-        while start_index < input.len() {
-            // Determine the end index for the current piece, ensuring we don't go beyond the input's length.
-            let mut end_index = std::cmp::min(start_index + MAX_LENGTH, input.len());
-            if end_index == input.len() {
-                result.push(input[start_index..].to_string());
-                break;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transport::fake::{self, FakeTransport};
+
+    fn handler() -> Handler {
+        let mut registry = commands::Registry::default();
+        registry
+            .trigger(puppywoof::WoofCommand)
+            .register(puppywoof::HowCommand)
+            .register(settings::SettingsCommand)
+            .register(prefs::LocaleCommand)
+            .register(help::HelpCommand)
+            .register(admin::AdminCommand::default());
+        #[cfg(feature = "chess")]
+        registry.register(puppychess::ChessCommand);
+        #[cfg(feature = "remind")]
+        registry
+            .register(puppyremind::RemindCommand)
+            .register(prefs::TimezoneCommand);
+        registry.alias("commands", "help");
+        Handler::new(registry)
+    }
+
+    const OWNER_ID: u64 = 7;
+
+    fn data() -> Arc<RwLock<TypeMap>> {
+        let mut data = TypeMap::new();
+        let store = store::Store::memory();
+        data.insert::<settings::Settings>(settings::Settings::new(store.clone()));
+        data.insert::<prefs::Prefs>(prefs::Prefs::new(store.clone()));
+        data.insert::<scheduler::Scheduler>(scheduler(&store));
+        data.insert::<store::Store>(store);
+        data.insert::<cooldown::Cooldowns>(cooldown::Cooldowns::default());
+        data.insert::<edits::Replies>(edits::Replies::default());
+        data.insert::<admin::Owners>(admin::Owners::new(&[OWNER_ID]));
+        data.insert::<audit::Audit>(audit::Audit::memory(audit::Redaction::default()));
+        #[cfg(feature = "chess")]
+        data.insert::<puppychess::ChessGame>(Arc::new(RwLock::new(
+            std::collections::HashMap::default(),
+        )));
+        Arc::new(RwLock::new(data))
+    }
+
+    /// A bot with fresh state, talking to Discord through a fake.
+    struct Bot {
+        handler: Handler,
+        transport: FakeTransport,
+        data: Arc<RwLock<TypeMap>>,
+    }
+
+    impl Bot {
+        fn new() -> Self {
+            Bot::with_admins(&[])
+        }
+
+        /// A bot where `admins` have the administrator permission.
+        fn with_admins(admins: &[u64]) -> Self {
+            Bot {
+                handler: handler(),
+                transport: FakeTransport {
+                    admins: admins.to_vec(),
+                    ..Default::default()
+                },
+                data: data(),
             }
-            let current_piece = &input[start_index..end_index];
-
-            if let Some(last_newline) = current_piece.rfind("\n\n") {
-                end_index = start_index + last_newline + 1;
-            } else if let Some(last_newline) = current_piece.rfind('\n') {
-                end_index = start_index + last_newline + 1;
-            } else if let Some(last_space) = current_piece.rfind(char::is_whitespace) {
-                end_index = start_index + last_space + 1;
+        }
+
+        /// Sends `content` as user `author` and returns what the bot replied.
+        async fn send(&self, author: u64, content: &str) -> Vec<commands::Reply> {
+            let msg = fake::message(1000, fake::user(author, "pup"), content);
+            self.handler
+                .handle_message(&self.transport, &self.data, &msg)
+                .await;
+            self.transport.take_sent()
+        }
+
+        /// The text of the first reply to `content`.
+        async fn text(&self, author: u64, content: &str) -> String {
+            let replies = self.send(author, content).await;
+            replies[0].content.clone().unwrap()
+        }
+
+        /// The embed of the only reply to `content`, as JSON.
+        async fn embed(&self, author: u64, content: &str) -> serde_json::Value {
+            let replies = self.send(author, content).await;
+            assert_eq!(replies.len(), 1);
+            serde_json::to_value(replies[0].embed.as_ref().unwrap()).unwrap()
+        }
+
+        /// Uses the component with `custom_id` as user `author`.
+        #[cfg(any(feature = "chess", feature = "gpt"))]
+        async fn click(
+            &self,
+            author: u64,
+            custom_id: &str,
+            values: &[&str],
+        ) -> Vec<commands::Reply> {
+            let component = fake::component(fake::user(author, "pup"), custom_id, values);
+            self.handler
+                .registry
+                .dispatch_component(&self.transport, &self.data, &component)
+                .await;
+            self.transport.take_sent()
+        }
+    }
+
+    #[tokio::test]
+    async fn echoes_woofs() {
+        let bot = Bot::new();
+
+        let replies = bot.send(2, "Woof woof!").await;
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].content.as_deref(), Some("Woof woof!"));
+        assert_eq!(replies[0].reply_to, Some(false));
+
+        for content in ["awoooo", "汪汪", "arfleeeebloo", "meow"] {
+            let replies = bot.send(2, content).await;
+            assert_eq!(replies.len(), usize::from(content != "meow"), "{content}");
+        }
+    }
+
+    #[tokio::test]
+    async fn ignores_own_messages() {
+        let bot = Bot::new();
+        assert!(bot.send(fake::BOT_ID, "woof").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn ignores_messages_while_shutting_down() {
+        let bot = Bot::new();
+        assert_eq!(
+            bot.handler
+                .shutdown
+                .stop(std::time::Duration::from_millis(10))
+                .await,
+            0
+        );
+        assert!(bot.send(2, "woof").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn ignores_unknown_commands() {
+        let bot = Bot::new();
+        assert!(bot.send(2, "puppy bark").await.is_empty());
+        assert!(bot.send(2, "puppy how now").await.is_empty());
+        assert_eq!(
+            bot.text(2, "PUPPY how").await,
+            "https://github.com/dllu/discord-woofer-rust"
+        );
+    }
+
+    #[tokio::test]
+    async fn authorizes_admin_commands() {
+        let bot = Bot::with_admins(&[2]);
+        let not_allowed = "You're not allowed to do that.";
+
+        assert!(bot.text(3, "puppy admin").await.ends_with(not_allowed));
+        assert!(bot.text(2, "puppy admin").await.contains("Uptime: "));
+        assert!(bot
+            .text(2, "puppy admin reload config")
+            .await
+            .ends_with(not_allowed));
+        assert!(bot.text(OWNER_ID, "puppy admin").await.contains("Uptime: "));
+
+        // Admin roles make their members admins too.
+        bot.send(2, "puppy settings admins <@&55>").await;
+        let msg = fake::with_roles(
+            fake::message(1000, fake::user(4, "pup"), "puppy admin"),
+            &[55],
+        );
+        bot.handler
+            .handle_message(&bot.transport, &bot.data, &msg)
+            .await;
+        let replies = bot.transport.take_sent();
+        assert!(replies[0].content.as_deref().unwrap().contains("Uptime: "));
+    }
+
+    #[cfg(feature = "chess")]
+    #[tokio::test]
+    async fn clears_chess_games() {
+        let bot = Bot::new();
+
+        bot.send(2, "puppy chess e4").await;
+        assert_eq!(
+            bot.text(OWNER_ID, "puppy admin clear chess").await,
+            "Cleared the chess game in this channel."
+        );
+        assert_eq!(
+            bot.text(OWNER_ID, "puppy admin clear chess").await,
+            "There's no chess game in this channel."
+        );
+    }
+
+    #[tokio::test]
+    async fn audits_commands() {
+        let bot = Bot::with_admins(&[9]);
+        let audit = |user: u64| {
+            let bot = &bot;
+            async move {
+                let embed = bot.embed(9, &format!("puppy admin audit <@{user}>")).await;
+                embed["description"].as_str().unwrap().to_string()
             }
+        };
+
+        bot.send(2, "puppy how").await;
+        bot.send(9, "puppy settings disable how").await;
+        bot.send(2, "puppy how").await;
+        bot.send(2, "Woof woof!").await;
+        bot.send(2, "not a command").await;
+
+        let log = audit(2).await;
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 4, "{}", log);
+        assert_eq!(lines[0], "Latest commands by <@2>:");
+        assert!(lines[1].starts_with("✅ <t:"), "{}", lines[1]);
+        assert!(
+            lines[1].ends_with("<#100> `woof Woof woof!`"),
+            "{}",
+            lines[1]
+        );
+        assert!(lines[2].starts_with("🚫") && lines[2].ends_with("`how`"));
+        assert!(lines[3].starts_with("✅") && lines[3].ends_with("`how`"));
+
+        // Only admins can look, though trying is recorded too.
+        let replies = bot.send(2, "puppy admin audit <@9>").await;
+        assert!(replies[0].embed.is_none());
+        assert!(audit(2).await.contains("`admin audit <@9>`"));
+
+        assert_eq!(audit(5).await, "No commands by <@5> in the audit log here.");
+    }
+
+    #[tokio::test]
+    async fn applies_guild_settings() {
+        let bot = Bot::with_admins(&[2]);
+
+        let denied = bot.text(3, "puppy settings prefix !").await;
+        assert!(denied.ends_with("Only server admins can change settings."));
+
+        bot.send(2, "puppy settings prefix !").await;
+        assert!(bot.send(3, "puppy how").await.is_empty());
+        assert_eq!(
+            bot.text(3, "!how").await,
+            "https://github.com/dllu/discord-woofer-rust"
+        );
+
+        bot.send(2, "!settings disable woof").await;
+        assert!(bot.send(3, "woof").await.is_empty());
+
+        bot.send(2, "!settings channels how <#55>").await;
+        assert!(bot
+            .text(3, "!how")
+            .await
+            .ends_with("`how` only works in <#55>."));
+
+        let unknown = bot.text(2, "!settings disable bark").await;
+        assert!(unknown.ends_with("There's no `bark` command."));
+        let admin = bot.text(2, "!settings disable admin").await;
+        assert!(admin.ends_with("`admin` can't be restricted."));
+
+        // Settings survive a restart because they are saved in the store.
+        let store = bot.data.read().await.get::<store::Store>().unwrap().clone();
+        let fresh = settings::Settings::new(store)
+            .get(Some(serenity::all::GuildId::new(fake::GUILD_ID)))
+            .await;
+        assert_eq!(fresh.prefix(), "!");
+        assert!(fresh.disabled.contains("woof"));
+    }
+
+    #[tokio::test]
+    async fn suggests_commands_for_typos() {
+        let bot = Bot::new();
+
+        assert!(bot
+            .text(2, "puppy setings prefix !")
+            .await
+            .ends_with("There's no `setings` command. Did you mean `puppy settings prefix !`?"));
+        assert!(bot
+            .text(2, "puppy comands")
+            .await
+            .ends_with("Did you mean `puppy help`?"));
+
+        // Aliases from the config work like built-in ones.
+        let replies = bot.send(2, "puppy commands").await;
+        assert!(replies[0].embed.is_some());
+    }
 
-            result.push(input[start_index..end_index].to_string());
+    #[tokio::test]
+    async fn reruns_edited_commands() {
+        let bot = Bot::new();
+        let first_reply = serenity::all::MessageId::new(fake::FIRST_SENT_ID);
+        let edit = |content: &str| fake::message(1000, fake::user(2, "pup"), content);
 
-            start_index = end_index;
+        assert!(bot
+            .text(2, "puppy help bark")
+            .await
+            .contains("There's no `bark` command."));
+
+        bot.handler
+            .handle_edit(&bot.transport, &bot.data, &edit("puppy help how"))
+            .await;
+        let edited = bot.transport.take_edited();
+        assert_eq!(edited.len(), 1);
+        assert_eq!(edited[0].0, first_reply);
+        assert!(edited[0].1.embed.is_some());
+        assert!(bot.transport.take_sent().is_empty());
+
+        // Unchanged content, commands with side effects and triggers aren't
+        // re-run.
+        for content in ["puppy help how", "puppy chess e4", "woof"] {
+            bot.handler
+                .handle_edit(&bot.transport, &bot.data, &edit(content))
+                .await;
+            assert!(bot.transport.take_edited().is_empty(), "{}", content);
+            assert!(bot.transport.take_sent().is_empty(), "{}", content);
+        }
+
+        // Neither are messages the bot never answered.
+        let other = fake::message(2000, fake::user(2, "pup"), "puppy how");
+        bot.handler
+            .handle_edit(&bot.transport, &bot.data, &other)
+            .await;
+        assert!(bot.transport.take_edited().is_empty());
+    }
+
+    #[cfg(feature = "remind")]
+    #[tokio::test]
+    async fn sets_and_delivers_reminders() {
+        use scheduler::JobHandler;
+
+        let bot = Bot::new();
+
+        assert_eq!(
+            bot.text(2, "puppy timezone europe/berlin").await,
+            "Okay, your timezone is now `Europe/Berlin`."
+        );
+        let set = bot
+            .text(2, "puppy remind me in 2h to check the oven @everyone")
+            .await;
+        assert!(set.starts_with("Okay, I'll remind you <t:"), "{}", set);
+        // The first job is the audit log pruning.
+        assert!(set.ends_with("It's reminder 2."), "{}", set);
+        assert!(bot
+            .text(3, "puppy remind me whenever")
+            .await
+            .contains("I don't know when `whenever` is."));
+        assert!(bot
+            .text(2, "puppy remind <#55> tomorrow 9am standup")
+            .await
+            .ends_with("Only server admins can set reminders for other channels."));
+        for channel in [fake::OTHER_GUILD_CHANNEL_ID, fake::READ_ONLY_CHANNEL_ID] {
+            let request = format!("puppy remind <#{channel}> tomorrow 9am standup");
+            assert!(bot
+                .text(OWNER_ID, &request)
+                .await
+                .ends_with("I can only remind channels of this server that you can post in."));
         }
+        assert!(bot
+            .text(2, "puppy remind list")
+            .await
+            .contains("check the oven"));
+
+        // When it's due, the reminder replies to the request and pings.
+        let scheduler = bot
+            .data
+            .read()
+            .await
+            .get::<scheduler::Scheduler>()
+            .unwrap()
+            .clone();
+        let job = scheduler.jobs(puppyremind::JOB_KIND).remove(0);
+        let delivery = Arc::new(FakeTransport::default());
+        let ctx = scheduler::JobContext {
+            transport: delivery.clone(),
+            data: bot.data.clone(),
+        };
+        puppyremind::Deliver.run(&ctx, &job).await.unwrap();
+        let posted = delivery.posted.lock().unwrap().remove(0);
+        assert_eq!(posted.0.get(), fake::CHANNEL_ID);
+        assert_eq!(posted.1.map(|id| id.get()), Some(1000));
+        assert_eq!(
+            posted.2.content.as_deref(),
+            Some("<@2> You asked me to remind you: check the oven @\u{200B}everyone")
+        );
+
+        assert!(bot
+            .text(3, "puppy remind cancel 2")
+            .await
+            .ends_with("You don't have a reminder 2."));
+        assert_eq!(
+            bot.text(2, "puppy remind cancel 2").await,
+            "Cancelled reminder 2."
+        );
+        assert_eq!(
+            bot.text(2, "puppy remind list").await,
+            "You don't have any reminders."
+        );
+
+        let set = bot
+            .text(OWNER_ID, "puppy remind <#55> tomorrow 9am standup")
+            .await;
+        assert!(set.contains("in <#55>. It's reminder 3."), "{}", set);
     }
 
-    result
+    #[tokio::test]
+    async fn replies_in_the_chosen_language() {
+        let bot = Bot::with_admins(&[2]);
+
+        assert_eq!(
+            bot.text(3, "puppy locale de").await,
+            "Okay, ich antworte dir ab jetzt auf Deutsch."
+        );
+        let denied = bot.text(3, "puppy settings prefix !").await;
+        assert!(denied.ends_with("Nur Server-Admins können Einstellungen ändern."));
+
+        // The guild's language applies to everyone without their own.
+        bot.send(2, "puppy settings locale es").await;
+        let denied = bot.text(4, "puppy settings prefix !").await;
+        assert!(
+            denied.ends_with("Solo los administradores del servidor pueden cambiar los ajustes.")
+        );
+        let denied = bot.text(3, "puppy settings prefix !").await;
+        assert!(denied.ends_with("Nur Server-Admins können Einstellungen ändern."));
+
+        let unknown = bot.text(4, "puppy locale xx").await;
+        assert!(unknown.contains("No hablo `xx`."));
+    }
+
+    /// The full registry, with every integration configured.
+    fn configured_registry() -> commands::Registry {
+        let mut config = config::Config::default();
+        config.weather.openweather_token = Some("test".to_string());
+        config.weather.google_maps_token = Some("test".to_string());
+        config.metar.avwx_token = Some("test".to_string());
+        config.gpt.openrouter_api_key = Some("test".to_string());
+        build_registry(&config)
+    }
+
+    #[test]
+    fn help_examples_parse() {
+        let registry = configured_registry();
+        let (triggers, commands) = registry.help(locale::Locale::En);
+        for help in triggers.iter().chain(&commands) {
+            for example in help.examples {
+                assert!(
+                    registry.parses(help.name, example),
+                    "{} {example}",
+                    help.name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn describes_commands_in_every_locale() {
+        let registry = configured_registry();
+        for locale in locale::Locale::ALL {
+            let (triggers, commands) = registry.help(locale);
+            for help in triggers.iter().chain(&commands) {
+                // Missing messages come out as their key, and Discord
+                // refuses descriptions over 100 characters.
+                let description = &help.description;
+                assert!(!description.starts_with("description-"), "{}", description);
+                assert!(description.chars().count() <= 100, "{}", description);
+                for option in &help.options {
+                    let description = &option.description;
+                    assert!(!description.starts_with("option-"), "{}", description);
+                    assert!(description.chars().count() <= 100, "{}", description);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn generates_help() {
+        let bot = Bot {
+            handler: Handler::new(configured_registry()),
+            ..Bot::with_admins(&[2])
+        };
+
+        let overview = bot.embed(2, "puppy help").await;
+        let listing = overview["description"].as_str().unwrap();
+        let (_, commands) = bot.handler.registry.help(locale::Locale::En);
+        for help in &commands {
+            assert!(
+                listing.contains(&format!("`puppy {}", help.name)),
+                "{}",
+                listing
+            );
+        }
+
+        bot.send(2, "puppy settings disable how").await;
+        let how = bot.embed(2, "puppy help how").await;
+        let status = how["fields"].as_array().unwrap().last().unwrap();
+        assert_eq!(status["value"], "disabled on this server");
+
+        #[cfg(feature = "weather")]
+        {
+            let weather = bot.embed(2, "puppy help weather").await;
+            let options = weather["fields"][1]["value"].as_str().unwrap();
+            assert!(options.contains("`units` temperature unit: kelvin, celsius, fahrenheit"));
+        }
+
+        assert!(bot
+            .text(2, "puppy help bark")
+            .await
+            .contains("There's no `bark` command."));
+
+        bot.send(2, "puppy locale de").await;
+        let how = bot.embed(2, "puppy help how").await;
+        assert_eq!(how["description"], "verlinkt den Quellcode");
+    }
+
+    #[cfg(feature = "why")]
+    #[tokio::test]
+    async fn tells_spammers_to_slow_down() {
+        let bot = Bot {
+            handler: Handler::new(configured_registry()),
+            ..Bot::new()
+        };
+        for _ in 0..5 {
+            assert!(!bot.text(2, "puppy why").await.starts_with("Slow down!"));
+        }
+        assert!(bot.text(3, "puppy why").await.starts_with("Slow down!"));
+
+        // An edit that's rate limited gets told so, but the answer stays
+        // and is still the one a later edit replaces.
+        let edit = fake::message(1000, fake::user(2, "pup"), "puppy  why");
+        bot.handler
+            .handle_edit(&bot.transport, &bot.data, &edit)
+            .await;
+        assert!(bot.transport.take_edited().is_empty());
+        let sent = bot.transport.take_sent();
+        assert!(sent[0]
+            .content
+            .as_deref()
+            .unwrap()
+            .starts_with("Slow down!"));
+        let replies = bot.data.read().await.get::<edits::Replies>().cloned();
+        let answer = serenity::all::MessageId::new(fake::FIRST_SENT_ID + 4);
+        assert_eq!(replies.unwrap().get(edit.id).unwrap().replies, [answer]);
+    }
+
+    #[cfg(feature = "chess")]
+    #[tokio::test]
+    async fn plays_chess() {
+        let bot = Bot::new();
+
+        let replies = bot.send(2, "puppy chess e4").await;
+        let board = serde_json::to_value(replies[0].embed.as_ref().unwrap()).unwrap();
+        assert_eq!(board["fields"][0]["value"], "1. e4");
+        assert_eq!(board["image"]["url"], "attachment://chess.png");
+        let image = &replies[0].attachments[0];
+        assert_eq!(image.filename, "chess.png");
+        assert!(image.data.starts_with(b"\x89PNG"));
+
+        let board = bot.embed(2, "puppy chess e5").await;
+        assert!(board["description"]
+            .as_str()
+            .unwrap()
+            .starts_with("Someone else has to make a move first"));
+
+        let board = bot.embed(3, "puppy chess Ke2").await;
+        assert!(board["description"]
+            .as_str()
+            .unwrap()
+            .starts_with("Illegal move!!!!!"));
+
+        let board = bot.embed(3, "puppy chess e5").await;
+        assert_eq!(board["fields"][0]["value"], "1. e4 e5");
+
+        // Without a move, it shows whose turn it is without playing.
+        let board = bot.embed(3, "puppy chess").await;
+        assert!(board["description"]
+            .as_str()
+            .unwrap()
+            .starts_with("White to move."));
+        assert_eq!(board["fields"][0]["value"], "1. e4 e5");
+    }
+
+    /// The custom IDs of the components on `reply`, in order.
+    #[cfg(any(feature = "chess", feature = "gpt"))]
+    fn custom_ids(reply: &commands::Reply) -> Vec<String> {
+        let rows = components::action_rows(reply.components.clone());
+        let rows = serde_json::to_value(rows).unwrap();
+        rows.as_array()
+            .unwrap()
+            .iter()
+            .flat_map(|row| row["components"].as_array().unwrap())
+            .map(|component| component["custom_id"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    // Owned buttons only come from gpt.
+    #[cfg(feature = "gpt")]
+    #[tokio::test]
+    async fn refuses_expired_and_others_components() {
+        let bot = Bot::new();
+
+        let replies = bot.click(2, "how:0:1:0:=", &[]).await;
+        assert!(replies[0].ephemeral);
+        assert!(replies[0]
+            .content
+            .as_deref()
+            .unwrap()
+            .contains("That has expired."));
+
+        let mine = commands::Reply::text("woof").component(
+            components::Component::button("how", "", "How".to_string())
+                .only(serenity::all::UserId::new(2)),
+        );
+        let id = &custom_ids(&mine)[0];
+        let replies = bot.click(3, id, &[]).await;
+        assert!(replies[0].ephemeral);
+        assert!(replies[0]
+            .content
+            .as_deref()
+            .unwrap()
+            .contains("Only <@2> can use that."));
+
+        let replies = bot.click(2, id, &[]).await;
+        assert_eq!(replies.len(), 1);
+        assert!(!replies[0].ephemeral);
+    }
+
+    #[cfg(feature = "chess")]
+    #[tokio::test]
+    async fn moves_from_chess_menus() {
+        let bot = Bot::new();
+        let replies = bot.send(2, "puppy chess e4").await;
+        let ids = custom_ids(&replies[0]);
+        // Black has 20 moves, which fit in one menu.
+        assert_eq!(ids.len(), 1);
+
+        let replies = bot.click(3, &ids[0], &["e5"]).await;
+        let board = serde_json::to_value(replies[0].embed.as_ref().unwrap()).unwrap();
+        assert_eq!(board["fields"][0]["value"], "1. e4 e5");
+        assert_eq!(custom_ids(&replies[0]).len(), 2);
+    }
+
+    #[cfg(feature = "chess")]
+    #[tokio::test]
+    async fn resumes_chess_from_store() {
+        let bot = Bot::new();
+        bot.send(2, "puppy chess d4").await;
+
+        // Forget the in-memory games, as if the bot had restarted.
+        bot.data
+            .read()
+            .await
+            .get::<puppychess::ChessGame>()
+            .unwrap()
+            .write()
+            .await
+            .clear();
+
+        let board = bot.embed(3, "puppy chess d5").await;
+        assert_eq!(board["fields"][0]["value"], "1. d4 d5");
+    }
 }
@@ -0,0 +1,248 @@
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Upper bounds of the latency histogram buckets, in seconds. gpt answers
+/// routinely take tens of seconds, so the buckets reach further than usual.
+const BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Name, type and help text of every metric, in the order they're exposed.
+const DESCRIPTIONS: [(&str, &str, &str); 5] = [
+    (
+        "woofer_commands_total",
+        "counter",
+        "Commands and triggers that parsed, by outcome.",
+    ),
+    (
+        "woofer_command_duration_seconds",
+        "histogram",
+        "Time from parsing a command to sending its replies.",
+    ),
+    (
+        "woofer_upstream_requests_total",
+        "counter",
+        "Requests to external APIs, by HTTP status or \"error\".",
+    ),
+    (
+        "woofer_upstream_duration_seconds",
+        "histogram",
+        "Time until an external API responded.",
+    ),
+    (
+        "woofer_upstream_cache_hits_total",
+        "counter",
+        "Requests to external APIs answered by the cache or another request.",
+    ),
+];
+
+type Labels = Vec<(&'static str, String)>;
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations at or below each of [`BUCKETS`].
+    buckets: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: Duration) {
+        let secs = value.as_secs_f64();
+        for (bucket, &bound) in self.buckets.iter_mut().zip(BUCKETS.iter()) {
+            if secs <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+}
+
+/// Counters and histograms, rendered in the Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    counters: Mutex<BTreeMap<(&'static str, Labels), u64>>,
+    histograms: Mutex<BTreeMap<(&'static str, Labels), Histogram>>,
+}
+
+lazy_static! {
+    pub static ref METRICS: Metrics = Metrics::default();
+}
+
+impl Metrics {
+    fn inc(&self, name: &'static str, labels: Labels) {
+        *self
+            .counters
+            .lock()
+            .unwrap()
+            .entry((name, labels))
+            .or_default() += 1;
+    }
+
+    fn observe(&self, name: &'static str, labels: Labels, value: Duration) {
+        let mut histograms = self.histograms.lock().unwrap();
+        histograms.entry((name, labels)).or_default().observe(value);
+    }
+
+    /// Records a command that ran to completion.
+    pub fn command_handled(&self, command: &str, elapsed: Duration) {
+        let labels = vec![("command", command.to_string())];
+        self.inc(
+            "woofer_commands_total",
+            [labels.clone(), vec![("outcome", "handled".to_string())]].concat(),
+        );
+        self.observe("woofer_command_duration_seconds", labels, elapsed);
+    }
+
+    /// Records a command that was turned away by its cooldown.
+    pub fn command_rate_limited(&self, command: &str) {
+        self.inc(
+            "woofer_commands_total",
+            vec![
+                ("command", command.to_string()),
+                ("outcome", "rate_limited".to_string()),
+            ],
+        );
+    }
+
+    /// Records an external API call, with `status` missing if it never got
+    /// a response.
+    pub fn upstream_request(&self, service: &str, status: Option<u16>, elapsed: Duration) {
+        let status = status.map_or_else(|| "error".to_string(), |s| s.to_string());
+        let labels = vec![("service", service.to_string())];
+        self.inc(
+            "woofer_upstream_requests_total",
+            [labels.clone(), vec![("status", status)]].concat(),
+        );
+        self.observe("woofer_upstream_duration_seconds", labels, elapsed);
+    }
+
+    /// Records an external API call that didn't need its own request.
+    pub fn upstream_cache_hit(&self, service: &str) {
+        self.inc(
+            "woofer_upstream_cache_hits_total",
+            vec![("service", service.to_string())],
+        );
+    }
+
+    pub fn render(&self) -> String {
+        let counters = self.counters.lock().unwrap();
+        let histograms = self.histograms.lock().unwrap();
+        let mut out = String::new();
+        for (name, kind, help) in DESCRIPTIONS {
+            let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} {kind}");
+            for ((_, labels), value) in counters.iter().filter(|((n, _), _)| *n == name) {
+                let _ = writeln!(out, "{name}{} {value}", format_labels(labels, None));
+            }
+            for ((_, labels), histogram) in histograms.iter().filter(|((n, _), _)| *n == name) {
+                for (bound, count) in BUCKETS.iter().zip(histogram.buckets) {
+                    let le = Some(bound.to_string());
+                    let _ = writeln!(out, "{name}_bucket{} {count}", format_labels(labels, le));
+                }
+                let inf = Some("+Inf".to_string());
+                let labels_inf = format_labels(labels, inf);
+                let plain = format_labels(labels, None);
+                let _ = writeln!(out, "{name}_bucket{labels_inf} {}", histogram.count);
+                let _ = writeln!(out, "{name}_sum{plain} {}", histogram.sum);
+                let _ = writeln!(out, "{name}_count{plain} {}", histogram.count);
+            }
+        }
+        out
+    }
+}
+
+fn format_labels(labels: &Labels, le: Option<String>) -> String {
+    let le = le.map(|le| ("le", le));
+    let pairs: Vec<String> = labels
+        .iter()
+        .cloned()
+        .chain(le)
+        .map(|(name, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{name}=\"{value}\"")
+        })
+        .collect();
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+/// Answers `GET /metrics` on `listener` until the process exits. Anything
+/// else gets a 404.
+pub async fn serve(listener: TcpListener) {
+    loop {
+        let (mut stream, _) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(why) => {
+                tracing::warn!("Error accepting metrics connection: {why:?}");
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            let mut request = [0; 1024];
+            let n = match stream.read(&mut request).await {
+                Ok(n) => n,
+                Err(_) => return,
+            };
+            let (status, body) = if request[..n].starts_with(b"GET /metrics ") {
+                ("200 OK", METRICS.render())
+            } else {
+                ("404 Not Found", String::new())
+            };
+            let response = format!(
+                "HTTP/1.1 {status}\r\ncontent-type: text/plain; version=0.0.4\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            let _ = stream.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_prometheus_text() {
+        let metrics = Metrics::default();
+        metrics.command_handled("gpt", Duration::from_millis(700));
+        metrics.command_rate_limited("gpt");
+        let text = metrics.render();
+
+        assert!(text.contains("# TYPE woofer_commands_total counter\n"));
+        assert!(text.contains("woofer_commands_total{command=\"gpt\",outcome=\"handled\"} 1\n"));
+        assert!(
+            text.contains("woofer_commands_total{command=\"gpt\",outcome=\"rate_limited\"} 1\n")
+        );
+        assert!(
+            text.contains("woofer_command_duration_seconds_bucket{command=\"gpt\",le=\"0.5\"} 0\n")
+        );
+        assert!(
+            text.contains("woofer_command_duration_seconds_bucket{command=\"gpt\",le=\"1\"} 1\n")
+        );
+    }
+
+    #[test]
+    fn renders_upstream_requests() {
+        let metrics = Metrics::default();
+        metrics.upstream_request("openrouter", Some(429), Duration::from_millis(40));
+        metrics.upstream_request("openrouter", Some(429), Duration::from_millis(60));
+        let text = metrics.render();
+
+        assert!(text
+            .contains("woofer_upstream_requests_total{service=\"openrouter\",status=\"429\"} 2\n"));
+        assert!(text.contains(
+            "woofer_upstream_duration_seconds_bucket{service=\"openrouter\",le=\"+Inf\"} 2\n"
+        ));
+        assert!(text.contains("woofer_upstream_duration_seconds_count{service=\"openrouter\"} 2\n"));
+    }
+}
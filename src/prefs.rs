@@ -0,0 +1,239 @@
+use crate::commands::{Command, Invocation, Reply};
+use crate::locale::Locale;
+use crate::store::{Record, Store};
+use serde::{Deserialize, Serialize};
+use serenity::all::{CommandOptionType, UserId};
+use serenity::async_trait;
+use serenity::builder::CreateCommandOption;
+use serenity::prelude::TypeMapKey;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// What a user has told the bot about themselves.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct UserPrefs {
+    /// Overrides the guild's language for this user.
+    pub locale: Option<Locale>,
+    /// IANA name like `Europe/Berlin`, used to read times in reminders.
+    pub timezone: Option<String>,
+}
+
+#[cfg(feature = "remind")]
+impl UserPrefs {
+    pub fn timezone(&self) -> Option<chrono_tz::Tz> {
+        self.timezone.as_deref()?.parse().ok()
+    }
+}
+
+impl Record for UserPrefs {
+    const KIND: &'static str = "user_prefs";
+    const VERSION: u32 = 1;
+}
+
+/// Per-user preferences, cached in memory and persisted in the [`Store`].
+#[derive(Clone)]
+pub struct Prefs {
+    store: Store,
+    cache: Arc<RwLock<HashMap<UserId, UserPrefs>>>,
+}
+
+impl TypeMapKey for Prefs {
+    type Value = Prefs;
+}
+
+impl Prefs {
+    pub fn new(store: Store) -> Self {
+        Prefs {
+            store,
+            cache: Arc::default(),
+        }
+    }
+
+    pub async fn get(&self, user: UserId) -> UserPrefs {
+        if let Some(prefs) = self.cache.read().await.get(&user) {
+            return prefs.clone();
+        }
+        let prefs = match self.store.get::<UserPrefs>(&user.to_string()) {
+            Ok(prefs) => prefs.unwrap_or_default(),
+            Err(why) => {
+                tracing::error!(%user, "Error loading preferences: {why:?}");
+                UserPrefs::default()
+            }
+        };
+        self.cache.write().await.insert(user, prefs.clone());
+        prefs
+    }
+
+    pub async fn update(
+        &self,
+        user: UserId,
+        change: impl FnOnce(&mut UserPrefs),
+    ) -> anyhow::Result<UserPrefs> {
+        let mut prefs = self.get(user).await;
+        change(&mut prefs);
+        self.store.put(&user.to_string(), &prefs)?;
+        self.cache.write().await.insert(user, prefs.clone());
+        Ok(prefs)
+    }
+}
+
+/// Lets users pick the language the bot replies to them in.
+pub struct LocaleCommand;
+
+#[async_trait]
+impl Command for LocaleCommand {
+    type Args = String;
+
+    fn name(&self) -> &'static str {
+        "locale"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["language"]
+    }
+
+    fn description(&self, locale: Locale) -> String {
+        locale.text("description-locale", &[])
+    }
+
+    fn usage(&self) -> &'static str {
+        "[language]"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["", "de"]
+    }
+
+    fn parse(&self, args: &str) -> Option<String> {
+        if args.contains(char::is_whitespace) {
+            None
+        } else {
+            Some(args.to_string())
+        }
+    }
+
+    fn options(&self) -> Vec<CreateCommandOption> {
+        let mut language = CreateCommandOption::new(
+            CommandOptionType::String,
+            "language",
+            Locale::En.text("option-locale-language", &[]),
+        );
+        for locale in Locale::ALL {
+            language = language.add_string_choice(locale.code(), locale.code());
+        }
+        vec![language]
+    }
+
+    async fn run(&self, inv: &Invocation<'_>, language: String) -> Vec<Reply> {
+        if language.is_empty() {
+            return vec![Reply::text(inv.locale.text(
+                "locale-show",
+                &[("locale", &inv.locale), ("locales", &Locale::list())],
+            ))];
+        }
+        let locale = match Locale::parse(&language) {
+            Some(locale) => locale,
+            None => {
+                return vec![Reply::text(inv.locale.error(
+                    "locale-unknown",
+                    &[("locale", &language), ("locales", &Locale::list())],
+                ))]
+            }
+        };
+        let prefs = inv.shared::<Prefs>().await;
+        match prefs
+            .update(inv.author.id, |p| p.locale = Some(locale))
+            .await
+        {
+            Ok(_) => vec![Reply::text(locale.text("locale-set", &[]))],
+            Err(why) => {
+                tracing::error!("Error saving preferences: {why:?}");
+                vec![Reply::text(inv.locale.error("locale-save-failed", &[]))]
+            }
+        }
+    }
+}
+
+/// Lets users set the timezone that reminders are read in.
+#[cfg(feature = "remind")]
+pub struct TimezoneCommand;
+
+#[cfg(feature = "remind")]
+#[async_trait]
+impl Command for TimezoneCommand {
+    type Args = String;
+
+    fn name(&self) -> &'static str {
+        "timezone"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["tz"]
+    }
+
+    fn description(&self, locale: Locale) -> String {
+        locale.text("description-timezone", &[])
+    }
+
+    fn usage(&self) -> &'static str {
+        "[timezone]"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["", "America/New_York"]
+    }
+
+    fn parse(&self, args: &str) -> Option<String> {
+        if args.contains(char::is_whitespace) {
+            None
+        } else {
+            Some(args.to_string())
+        }
+    }
+
+    fn options(&self) -> Vec<CreateCommandOption> {
+        vec![CreateCommandOption::new(
+            CommandOptionType::String,
+            "timezone",
+            Locale::En.text("option-timezone-timezone", &[]),
+        )]
+    }
+
+    async fn run(&self, inv: &Invocation<'_>, timezone: String) -> Vec<Reply> {
+        let prefs = inv.shared::<Prefs>().await;
+        if timezone.is_empty() {
+            let current = prefs.get(inv.author.id).await.timezone();
+            let current = current.unwrap_or(chrono_tz::Tz::UTC);
+            return vec![Reply::text(
+                inv.locale
+                    .text("timezone-show", &[("timezone", &current.name())]),
+            )];
+        }
+        let tz = chrono_tz::TZ_VARIANTS
+            .iter()
+            .find(|tz| tz.name().eq_ignore_ascii_case(&timezone));
+        let tz = match tz {
+            Some(tz) => tz,
+            None => {
+                return vec![Reply::text(
+                    inv.locale
+                        .error("timezone-unknown", &[("timezone", &timezone)]),
+                )]
+            }
+        };
+        match prefs
+            .update(inv.author.id, |p| p.timezone = Some(tz.name().to_string()))
+            .await
+        {
+            Ok(_) => vec![Reply::text(
+                inv.locale.text("timezone-set", &[("timezone", &tz.name())]),
+            )],
+            Err(why) => {
+                tracing::error!("Error saving preferences: {why:?}");
+                vec![Reply::text(inv.locale.error("timezone-save-failed", &[]))]
+            }
+        }
+    }
+}
@@ -1,7 +1,17 @@
-use crate::utils;
-use anyhow::{anyhow, Result};
+use crate::commands::{Command, Invocation, Reply};
+use crate::components::{self, Component};
+use crate::error;
+use crate::locale::Locale;
+use crate::render;
+use crate::store::{Record, Store};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, CommandOptionType};
+use serenity::async_trait;
+use serenity::builder::CreateCommandOption;
 use serenity::prelude::*;
 use shakmaty::Position;
+use std::time::Duration;
 use std::{collections::HashMap, sync::Arc};
 
 pub struct ChessGame;
@@ -25,91 +35,202 @@ impl ChessState {
             moves: Vec::new(),
         }
     }
+
+    /// Resumes the game saved for `channel`, or starts a new one.
+    fn load(store: &Store, channel: &str) -> Self {
+        let record = match store.get::<ChessRecord>(channel) {
+            Ok(Some(record)) => record,
+            Ok(None) => return ChessState::new(),
+            Err(why) => {
+                tracing::error!(%channel, "Error loading chess game: {why:?}");
+                return ChessState::new();
+            }
+        };
+        let pos = shakmaty::fen::Fen::from_ascii(record.fen.as_bytes())
+            .map_err(anyhow::Error::from)
+            .and_then(|fen| {
+                fen.into_position(shakmaty::CastlingMode::Standard)
+                    .map_err(anyhow::Error::from)
+            });
+        match pos {
+            Ok(pos) => ChessState {
+                pos,
+                user_id: record.user_id,
+                user_name: record.user_name,
+                moves: record.moves,
+            },
+            Err(why) => {
+                tracing::error!(%channel, "Error restoring chess game: {why:?}");
+                ChessState::new()
+            }
+        }
+    }
+
+    fn save(&self, store: &Store, channel: &str) {
+        let record = ChessRecord {
+            fen: shakmaty::fen::Fen::from_position(
+                self.pos.clone(),
+                shakmaty::EnPassantMode::Legal,
+            )
+            .to_string(),
+            user_id: self.user_id.clone(),
+            user_name: self.user_name.clone(),
+            moves: self.moves.clone(),
+        };
+        if let Err(why) = store.put(channel, &record) {
+            tracing::error!(%channel, "Error saving chess game: {why:?}");
+        }
+    }
+}
+
+/// A [`ChessState`] as persisted in the [`Store`], keyed by channel id.
+#[derive(Serialize, Deserialize)]
+struct ChessRecord {
+    fen: String,
+    user_id: Option<String>,
+    user_name: Option<String>,
+    moves: Vec<String>,
+}
+
+impl Record for ChessRecord {
+    const KIND: &'static str = "chess";
+    const VERSION: u32 = 1;
+}
+
+/// The in-memory games, plus the store they are saved to.
+async fn games(data: &Arc<RwLock<TypeMap>>) -> (<ChessGame as TypeMapKey>::Value, Store) {
+    let data_read = data.read().await;
+    (
+        data_read
+            .get::<ChessGame>()
+            .expect("Expected ChessGame")
+            .clone(),
+        data_read.get::<Store>().expect("Expected Store").clone(),
+    )
+}
+
+/// Saves every game in memory, e.g. before shutting down.
+pub async fn save_all(data: &Arc<RwLock<TypeMap>>) {
+    let (games, store) = {
+        let data_read = data.read().await;
+        (
+            data_read.get::<ChessGame>().cloned(),
+            data_read.get::<Store>().cloned(),
+        )
+    };
+    if let Some((games, store)) = games.zip(store) {
+        for (channel, game) in games.read().await.iter() {
+            if !game.moves.is_empty() {
+                game.save(&store, channel);
+            }
+        }
+    }
+}
+
+/// Ends the game in `channel`, returning whether there was one.
+pub async fn clear(data: &Arc<RwLock<TypeMap>>, channel: ChannelId) -> Result<bool> {
+    let (game_lock, store) = games(data).await;
+    let channel = channel.to_string();
+    let cached = game_lock.write().await.remove(&channel);
+    let saved = store.get::<ChessRecord>(&channel)?.is_some();
+    store.delete::<ChessRecord>(&channel)?;
+    Ok(saved || cached.is_some_and(|game| !game.moves.is_empty()))
 }
 
 pub struct ChessOutput {
     status: String,
-    url: String,
+    board: shakmaty::Chess,
     pgn: String,
+    /// Legal moves for whoever plays next, offered in menus below the board.
+    moves: Vec<String>,
 }
 
-pub async fn reply(
-    ctx: &serenity::prelude::Context,
-    msg: &serenity::all::Message,
-    chess: ChessOutput,
-) -> Result<()> {
-    let embed = serenity::builder::CreateEmbed::new()
-        .description(chess.status)
-        .image(chess.url)
-        .field("move history", chess.pgn, false)
-        .timestamp(serenity::model::Timestamp::now());
-    let builder = serenity::builder::CreateMessage::new().embed(embed);
+/// Leaves a row of the reply free, since Discord allows five.
+const MAX_MENUS: usize = 4;
 
-    if let Err(why) = msg.channel_id.send_message(&ctx.http, builder).await {
-        println!("Error sending message: {why:?}");
+/// Games are slow, so menus keep working longer than most components. A
+/// menu on a board that has moved on just offers moves that are now illegal.
+const MENU_TTL: Duration = Duration::from_secs(60 * 60);
+
+impl ChessOutput {
+    async fn reply(self, locale: Locale) -> Result<Reply> {
+        let board = render::attachment(board_svg(&self.board), BOARD_FILENAME).await?;
+        let embed = serenity::builder::CreateEmbed::new()
+            .description(self.status)
+            .image(format!("attachment://{BOARD_FILENAME}"))
+            .field(locale.text("chess-history", &[]), self.pgn, false)
+            .timestamp(serenity::model::Timestamp::now());
+        let mut reply = Reply::embed(embed).attachment(board);
+        for chunk in self.moves.chunks(components::MAX_OPTIONS).take(MAX_MENUS) {
+            let placeholder = locale.text(
+                "chess-pick-move",
+                &[("first", &chunk[0]), ("last", &chunk[chunk.len() - 1])],
+            );
+            let menu = Component::select("chess", "", placeholder, chunk.to_vec());
+            reply = reply.component(menu.expires_in(MENU_TTL));
+        }
+        Ok(reply)
     }
-    Ok(())
 }
 
-pub async fn chess_illegal_move(
-    ctx: &serenity::prelude::Context,
-    msg: &serenity::all::Message,
-) -> Result<ChessOutput> {
-    let game_lock = {
-        let data_read = ctx.data.read().await;
-        data_read
-            .get::<ChessGame>()
-            .expect("Expected ChessGame")
-            .clone()
-    };
+/// Every legal move in `pos`, in standard algebraic notation.
+fn legal_moves(pos: &shakmaty::Chess) -> Vec<String> {
+    pos.legal_moves()
+        .iter()
+        .map(|m| shakmaty::san::San::from_move(pos, m).to_string())
+        .collect()
+}
+
+/// The board in this channel with its legal moves, either because the
+/// player's move wasn't one of them or because they asked whose turn it is.
+pub async fn chess_legal_moves(inv: &Invocation<'_>, illegal: bool) -> Result<ChessOutput> {
+    let (game_lock, store) = games(inv.data).await;
+    let channel = inv.channel_id.to_string();
     let mut map = game_lock.write().await;
     let entry = map
-        .entry(msg.channel_id.to_string())
-        .or_insert_with(|| Box::new(ChessState::new()));
+        .entry(channel.clone())
+        .or_insert_with(|| Box::new(ChessState::load(&store, &channel)));
     let pos = &entry.pos;
-
-    let moves = pos.legal_moves();
-    let move_strings: Vec<String> = moves
-        .iter()
-        .map(|m| {
-            let san = shakmaty::san::San::from_move(pos, m);
-            san.to_string()
-        })
-        .collect();
-
+    let move_strings = legal_moves(pos);
     let moves_string = move_strings.join(", ");
+    let key = match (illegal, pos.turn()) {
+        (true, _) => "chess-illegal",
+        (false, shakmaty::Color::White) => "chess-white-to-move",
+        (false, shakmaty::Color::Black) => "chess-black-to-move",
+    };
 
     Ok(ChessOutput {
-        status: format!("Illegal move!!!!! The valid moves are {moves_string}."),
-        url: fen_url(entry.pos.clone())?,
+        status: inv.locale.text(key, &[("moves", &moves_string)]),
+        board: entry.pos.clone(),
         pgn: format_pgn(&entry.moves),
+        moves: move_strings,
     })
 }
 
-pub async fn chess(ctx: &Context, msg: &serenity::all::Message) -> Result<ChessOutput> {
-    let san_str = &msg.content[12..];
+pub async fn chess(inv: &Invocation<'_>, san_str: &str) -> Result<ChessOutput> {
     let san: shakmaty::san::San = san_str.parse()?;
 
-    let game_lock = {
-        let data_read = ctx.data.read().await;
-        data_read
-            .get::<ChessGame>()
-            .expect("Expected ChessGame")
-            .clone()
-    };
-    let current_player = msg.author.id.to_string();
+    let (game_lock, store) = games(inv.data).await;
+    let channel = inv.channel_id.to_string();
+    let current_player = inv.author.id.to_string();
     let mut map = game_lock.write().await;
     let entry = map
-        .entry(msg.channel_id.to_string())
-        .or_insert_with(|| Box::new(ChessState::new()));
+        .entry(channel.clone())
+        .or_insert_with(|| Box::new(ChessState::load(&store, &channel)));
     if let Some(previous_player) = &(entry.user_id) {
         if previous_player == &current_player {
-            let username = entry.user_name.as_ref().unwrap();
+            // Records saved without a name still mention the player.
+            let username = match &entry.user_name {
+                Some(name) => name.clone(),
+                None => format!("<@{previous_player}>"),
+            };
             return Ok(ChessOutput {
-                status : format!("Someone else has to make a move first!!!!! The last player to make a move is {username}."),
-                url : fen_url(entry.pos.clone())?,
-                pgn : format_pgn(&entry.moves),
-                });
+                status: inv.locale.text("chess-wait-turn", &[("player", &username)]),
+                board: entry.pos.clone(),
+                pgn: format_pgn(&entry.moves),
+                // Still up for the other players.
+                moves: legal_moves(&entry.pos),
+            });
         }
     }
     let pos = &entry.pos;
@@ -117,6 +238,7 @@ pub async fn chess(ctx: &Context, msg: &serenity::all::Message) -> Result<ChessO
     let pos_next = pos.clone().play(&mov)?;
 
     let status: String;
+    let mut next_moves = Vec::new();
 
     let mut new_moves = entry.moves.clone();
     new_moves.push(san_str.to_string());
@@ -127,37 +249,149 @@ pub async fn chess(ctx: &Context, msg: &serenity::all::Message) -> Result<ChessO
             **entry = ChessState {
                 pos: pos_next.clone(),
                 user_id: Some(current_player),
-                user_name: Some(utils::author_name_from_msg(msg)),
+                user_name: Some(inv.author_name()),
                 moves: new_moves,
             };
+            entry.save(&store, &channel);
             status = "".to_string();
+            next_moves = legal_moves(&pos_next);
         }
         Some(outcome) => {
             match outcome {
                 shakmaty::Outcome::Decisive { winner: w } => match w {
-                    shakmaty::Color::White => status = format!("White wins! {} 1-0", pgn),
-                    shakmaty::Color::Black => status = format!("Black wins! {} 0-1", pgn),
+                    shakmaty::Color::White => {
+                        status = inv.locale.text("chess-white-wins", &[("pgn", &pgn)])
+                    }
+                    shakmaty::Color::Black => {
+                        status = inv.locale.text("chess-black-wins", &[("pgn", &pgn)])
+                    }
                 },
-                shakmaty::Outcome::Draw => status = format!("Draw! {} 1/2-1/2", pgn),
+                shakmaty::Outcome::Draw => status = inv.locale.text("chess-draw", &[("pgn", &pgn)]),
             }
             **entry = ChessState::new();
+            if let Err(why) = store.delete::<ChessRecord>(&channel) {
+                tracing::error!(%channel, "Error deleting chess game: {why:?}");
+            }
         }
     }
 
     Ok(ChessOutput {
         status,
-        url: fen_url(pos_next)?,
+        board: pos_next,
         pgn,
+        moves: next_moves,
     })
 }
 
-fn fen_url(pos: shakmaty::Chess) -> Result<String> {
-    let fen = shakmaty::fen::Epd::from_position(pos, shakmaty::EnPassantMode::Legal).to_string();
-    let f = fen
-        .split(' ')
-        .next()
-        .ok_or_else(|| anyhow!("FEN is malformed"))?;
-    Ok(format!("https://chess.dllu.net/{f}.png"))
+pub struct ChessCommand;
+
+#[async_trait]
+impl Command for ChessCommand {
+    type Args = String;
+
+    fn name(&self) -> &'static str {
+        "chess"
+    }
+
+    fn description(&self, locale: Locale) -> String {
+        locale.text("description-chess", &[])
+    }
+
+    fn usage(&self) -> &'static str {
+        "[move]"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["", "e4", "Nf3"]
+    }
+
+    /// Without a move, shows the board and whose turn it is.
+    fn parse(&self, args: &str) -> Option<String> {
+        Some(args.to_string())
+    }
+
+    fn options(&self) -> Vec<CreateCommandOption> {
+        vec![CreateCommandOption::new(
+            CommandOptionType::String,
+            "move",
+            Locale::En.text("option-chess-move", &[]),
+        )]
+    }
+
+    async fn run(&self, inv: &Invocation<'_>, san: String) -> Vec<Reply> {
+        let res = if san.is_empty() {
+            chess_legal_moves(inv, false).await
+        } else {
+            match chess(inv, &san).await {
+                Ok(output) => Ok(output),
+                Err(why2) => {
+                    tracing::debug!("Error making chess move: {why2:?}");
+                    chess_legal_moves(inv, true).await
+                }
+            }
+        };
+        let reply = match res {
+            Ok(output) => output.reply(inv.locale).await,
+            Err(why) => Err(why),
+        };
+        match reply {
+            Ok(reply) => vec![reply],
+            Err(why) => vec![error::reply(inv.locale, self.name(), &why)],
+        }
+    }
+}
+
+/// What the board is called in the reply's embed.
+const BOARD_FILENAME: &str = "chess.png";
+
+/// Side of a square, in pixels.
+const SQUARE: u32 = 64;
+
+/// The board from white's side, with the pieces drawn as chess symbols.
+fn board_svg(pos: &shakmaty::Chess) -> String {
+    let size = SQUARE * 8;
+    let mut svg = format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{size}" height="{size}">
+            <style>.piece {{ font: {}px 'DejaVu Sans'; text-anchor: middle; }}</style>"##,
+        SQUARE * 3 / 4
+    );
+    for row in 0..8 {
+        for column in 0..8 {
+            let (x, y) = (column * SQUARE, row * SQUARE);
+            let color = if (row + column) % 2 == 0 {
+                "#f0d9b5"
+            } else {
+                "#b58863"
+            };
+            svg.push_str(&format!(
+                r#"<rect x="{x}" y="{y}" width="{SQUARE}" height="{SQUARE}" fill="{color}"/>"#
+            ));
+            let square = shakmaty::Square::new((7 - row) * 8 + column);
+            let piece = match pos.board().piece_at(square) {
+                Some(piece) => piece,
+                None => continue,
+            };
+            // The filled symbols, outlined for white.
+            let symbol = match piece.role {
+                shakmaty::Role::King => '♚',
+                shakmaty::Role::Queen => '♛',
+                shakmaty::Role::Rook => '♜',
+                shakmaty::Role::Bishop => '♝',
+                shakmaty::Role::Knight => '♞',
+                shakmaty::Role::Pawn => '♟',
+            };
+            let style = match piece.color {
+                shakmaty::Color::White => r##"fill="#fff" stroke="#000" stroke-width="1.5""##,
+                shakmaty::Color::Black => r##"fill="#000""##,
+            };
+            let (center, baseline) = (x + SQUARE / 2, y + SQUARE * 4 / 5);
+            svg.push_str(&format!(
+                r#"<text x="{center}" y="{baseline}" class="piece" {style}>{symbol}</text>"#
+            ));
+        }
+    }
+    svg.push_str("</svg>");
+    svg
 }
 
 fn format_pgn(moves: &[String]) -> String {
@@ -1,21 +1,25 @@
+use crate::commands::{Command, Invocation, Reply};
+use crate::components::Component;
+use crate::cooldown::{Cooldowns, Limit, Scope};
+use crate::error::{self, UserError};
+use crate::locale::Locale;
+use crate::retry::Retry;
+use crate::upstream::{Service, Upstream};
 use crate::utils;
 use anyhow::anyhow;
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use serenity::builder::GetMessages;
+use serenity::all::CommandOptionType;
+use serenity::async_trait;
+use serenity::builder::{CreateAttachment, CreateCommandOption, CreateEmbed, CreateEmbedFooter};
 use serenity::model::channel::Embed;
 use serenity::model::guild::Emoji;
-use serenity::prelude::*;
 use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
 use std::sync::RwLock as StdRwLock;
-use std::time::{Duration, Instant};
-use tokio::sync::RwLock;
+use std::time::Duration;
 
-// Global rate limit tracker for 429s
 lazy_static! {
-    static ref NEXT_ALLOWED_REQUEST: Arc<RwLock<Option<Instant>>> = Arc::new(RwLock::new(None));
     static ref EMOJI_MAPPINGS: StdRwLock<HashMap<String, String>> = StdRwLock::new(HashMap::new());
 }
 
@@ -53,21 +57,33 @@ struct Payload {
     // reasoning_format: String,
 }
 
-async fn get_messages(ctx: &Context, msg: &serenity::all::Message) -> Vec<Message> {
-    let channel_id = msg.channel_id;
-    let builder = GetMessages::new().before(msg.id).limit(16);
-    let mut history = channel_id.messages(&ctx.http, builder).await.unwrap();
-    let latest_author = utils::author_name_from_msg(&msg);
+async fn get_messages(inv: &Invocation<'_>, question: &str) -> Vec<Message> {
+    let before = inv.message().map(|msg| msg.id);
+    // Without the history, e.g. for lack of permission to read it, the
+    // question is still worth answering.
+    let mut history = match inv.transport.history(inv.channel_id, before, 16).await {
+        Ok(history) => history,
+        Err(why) => {
+            tracing::warn!("Error fetching history: {why:?}");
+            Vec::new()
+        }
+    };
+    let latest_author = inv.author_name();
 
     // Since Serenity returns messages in reverse chronological order, reverse to get oldest first.
     history.reverse();
-    history.push(msg.clone());
+    if let Some(msg) = inv.message() {
+        history.push(msg.clone());
+    }
 
     let now = chrono::Utc::now();
     let one_day_ago = now - chrono::Duration::hours(24);
     history.retain(|m| m.timestamp.unix_timestamp() >= one_day_ago.timestamp());
 
-    let authors = (*history).iter().map(utils::author_name_from_msg);
+    let authors = (*history)
+        .iter()
+        .map(utils::author_name_from_msg)
+        .chain(std::iter::once(latest_author.clone()));
 
     let mut unique_authors = HashSet::new();
     let authors: Vec<String> = authors
@@ -75,6 +91,7 @@ async fn get_messages(ctx: &Context, msg: &serenity::all::Message) -> Vec<Messag
         .collect();
     let authors = authors.join(", ");
     let iso_date = format!("{}", now.format("%Y-%m-%d"));
+    let language = inv.locale.text("gpt-language", &[]);
 
     let prompt = format!(
         r##"
@@ -98,6 +115,8 @@ Always try to respond in at least one or two sentences unless explicitly asked n
 
 Please be as concise as possible in your thought process.
 
+{language}
+
 In this conversation, there are the following participants: {authors}. You are responding to the latest message by {latest_author}."##
     );
 
@@ -109,7 +128,7 @@ In this conversation, there are the following participants: {authors}. You are r
     }];
 
     for msg in (*history).iter() {
-        if msg.author.id == ctx.cache.current_user().id {
+        if msg.author.id == inv.transport.current_user_id() {
             let mut content = msg_content_for_gpt(msg);
             if content.starts_with(OUTPUT_PREFIX) {
                 content = content[OUTPUT_PREFIX.len()..].to_string();
@@ -151,6 +170,17 @@ In this conversation, there are the following participants: {authors}. You are r
         }
     }
 
+    // Slash commands have no message in the history, so add the question
+    // the same way a `puppy gpt` message would appear.
+    if inv.message().is_none() {
+        messages.push(Message {
+            role: "user".to_string(),
+            content: format!("{}: {}", latest_author, sanitize_discord_emojis(question)),
+            reasoning: None,
+            name: Some(latest_author),
+        });
+    }
+
     messages
 }
 
@@ -185,12 +215,16 @@ fn embeds_to_text(embeds: &[Embed]) -> String {
     formatted.join("\n\n")
 }
 
+/// Whether `embed` holds reasoning, labelled in whichever language the
+/// asker had.
 fn is_think_embed(embed: &Embed) -> bool {
-    embed
-        .description
-        .as_deref()
-        .map(|description| description.trim().eq_ignore_ascii_case("Think"))
-        .unwrap_or(false)
+    let description = match embed.description.as_deref() {
+        Some(description) => description.trim(),
+        None => return false,
+    };
+    Locale::ALL
+        .iter()
+        .any(|locale| description.eq_ignore_ascii_case(&locale.text("gpt-think", &[])))
 }
 
 fn embed_to_text(embed: &Embed) -> Option<String> {
@@ -306,6 +340,10 @@ pub fn set_available_emojis(emojis: impl IntoIterator<Item = Emoji>) -> usize {
     mappings.len()
 }
 
+pub fn available_emoji_count() -> usize {
+    EMOJI_MAPPINGS.read().unwrap().len()
+}
+
 fn replace_discord_emojis(input: &str) -> String {
     lazy_static! {
         static ref RE: Regex = Regex::new(r":([a-zA-Z0-9_]+):").unwrap();
@@ -324,30 +362,17 @@ fn replace_discord_emojis(input: &str) -> String {
 }
 
 pub async fn gpt(
-    ctx: &Context,
-    msg: &serenity::all::Message,
+    inv: &Invocation<'_>,
+    question: &str,
     api_key: &str,
 ) -> anyhow::Result<(Option<String>, String)> {
-    let client = reqwest::Client::new();
-
-    // Wait if we're currently rate limited
-    {
-        let guard = NEXT_ALLOWED_REQUEST.read().await;
-        if let Some(instant) = *guard {
-            let now = Instant::now();
-            if now < instant {
-                let wait = instant - now;
-                println!("Rate limited, waiting for {:?}", wait);
-                tokio::time::sleep(wait).await;
-            }
-        }
-    }
+    let upstream = inv.shared::<Upstream>().await;
 
-    let messages = get_messages(ctx, msg).await;
-    if msg.content == "puppy gpt debug" && msg.author.name == "purplepuppy" {
-        println!("{messages:#?}");
+    let messages = get_messages(inv, question).await;
+    if question == "debug" && inv.is_owner().await {
+        tracing::info!("{messages:#?}");
         return Ok((
-            Some("Debug data has been printed to stdout! :pupsplit:".to_string()),
+            Some(inv.locale.text("gpt-debug-logged", &[])),
             "".to_string(),
         ));
     }
@@ -361,65 +386,234 @@ pub async fn gpt(
         // reasoning_format: "parsed".to_string(),
     };
 
-    let max_retries = 3;
-    let mut attempts = 0;
-    loop {
-        let response = client
-            .post("https://openrouter.ai/api/v1/chat/completions")
-            .header("Authorization", format!("Bearer {}", api_key))
-            .header("Content-Type", "application/json")
-            .json(&payload)
-            .send()
-            .await?;
-
-        if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
-            // Parse retry-after header
-            let retry_after = response
-                .headers()
-                .get("retry-after")
-                .and_then(|v| v.to_str().ok())
-                .and_then(|s| s.parse::<u64>().ok())
-                .unwrap_or(5); // Default to 5 seconds if missing
-
-            let until = Instant::now() + Duration::from_secs(retry_after);
-            {
-                let mut guard = NEXT_ALLOWED_REQUEST.write().await;
-                *guard = Some(until);
-            }
-            println!(
-                "429 received, pausing for {} seconds (attempt {}/{})",
-                retry_after,
-                attempts + 1,
-                max_retries
-            );
-            tokio::time::sleep(Duration::from_secs(retry_after)).await;
-            attempts += 1;
-            if attempts >= max_retries {
-                return Err(anyhow!(
-                    "Rate limited after {} retries, try again in {} seconds",
-                    max_retries,
-                    retry_after
-                ));
-            }
-            continue;
+    let response = upstream
+        .post(Service::OpenRouter, "/api/v1/chat/completions")
+        .header("Authorization", format!("Bearer {}", api_key))
+        .header("Content-Type", "application/json")
+        .json(&payload)
+        .retry(RETRY)
+        .send()
+        .await?;
+
+    if response.status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        // Everyone else gets told to slow down until OpenRouter is ready.
+        let retry_after = response.retry_after().unwrap_or(Duration::from_secs(5));
+        inv.shared::<Cooldowns>().await.pause("gpt", retry_after);
+        tracing::warn!(?retry_after, "429 received, pausing");
+    }
+
+    let response: ChatCompletionResponse = response.error_for_status()?.json()?;
+
+    if let Some(choice) = response.choices.first() {
+        let mut output = choice.message.content.clone();
+        if output.starts_with("woofer: ") || output.starts_with("Woofer: ") {
+            output = choice.message.content[8..].to_string();
+        }
+
+        let message = replace_discord_emojis(&output);
+        Ok((choice.message.reasoning.clone(), message.to_string()))
+    } else {
+        Err(anyhow!("No choices found in the response")
+            .context(UserError::UpstreamDown(Service::OpenRouter.label())))
+    }
+}
+
+/// Answers routinely take tens of seconds, and the free models are often
+/// briefly rate limited.
+const RETRY: Retry = Retry {
+    max_delay: Duration::from_secs(30),
+    timeout: Duration::from_secs(120),
+    ..Retry::DEFAULT
+};
+
+/// Free OpenRouter models have a small daily quota shared by every guild.
+const LIMITS: &[Limit] = &[
+    Limit::new(Scope::User, 2, Duration::from_secs(30)),
+    Limit::new(Scope::Guild, 6, Duration::from_secs(10)),
+];
+
+pub struct GptCommand {
+    pub api_key: String,
+}
+
+#[async_trait]
+impl Command for GptCommand {
+    type Args = String;
+
+    fn name(&self) -> &'static str {
+        "gpt"
+    }
+
+    fn description(&self, locale: Locale) -> String {
+        locale.text("description-gpt", &[])
+    }
+
+    fn rerun_on_edit(&self) -> bool {
+        true
+    }
+
+    fn limits(&self) -> &'static [Limit] {
+        LIMITS
+    }
+
+    fn usage(&self) -> &'static str {
+        "[question]"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["what is the best dog breed?"]
+    }
+
+    fn typing(&self) -> bool {
+        true
+    }
+
+    fn parse(&self, args: &str) -> Option<String> {
+        if args.is_empty() {
+            None
+        } else {
+            Some(args.to_string())
         }
+    }
+
+    fn options(&self) -> Vec<CreateCommandOption> {
+        vec![CreateCommandOption::new(
+            CommandOptionType::String,
+            "question",
+            Locale::En.text("option-gpt-question", &[]),
+        )
+        .required(true)]
+    }
 
-        if !response.status().is_success() {
-            dbg!(&response);
-            return Err(anyhow!("Request failed with status: {}", response.status()));
+    async fn run(&self, inv: &Invocation<'_>, question: String) -> Vec<Reply> {
+        let (think, res) = match gpt(inv, &question, &self.api_key).await {
+            Ok(response) => response,
+            Err(why) => return vec![error::reply(inv.locale, self.name(), &why)],
+        };
+
+        let mut replies = Vec::new();
+        for (i, part) in split_string(&res).into_iter().enumerate() {
+            let mut reply = Reply {
+                content: Some(part),
+                reply_to: Some(true),
+                ..Default::default()
+            };
+            if i == 0 {
+                // Asking again costs tokens, so only the asker gets to.
+                let label = inv.locale.text("button-regenerate", &[]);
+                let regenerate = Component::button("gpt", question.as_str(), label);
+                reply = reply.component(regenerate.only(inv.author.id));
+                if let Some(ref thonk) = think {
+                    if thonk.len() < 2000 {
+                        reply.embed = Some(
+                            CreateEmbed::new()
+                                .description(inv.locale.text("gpt-think", &[]))
+                                .footer(CreateEmbedFooter::new(thonk)),
+                        );
+                    } else {
+                        reply = reply
+                            .attachment(CreateAttachment::bytes(thonk.as_bytes(), "think.txt"));
+                    }
+                }
+            }
+            replies.push(reply);
         }
-        let response: ChatCompletionResponse = response.json().await?;
+        replies
+    }
+}
 
-        if let Some(choice) = response.choices.first() {
-            let mut output = choice.message.content.clone();
-            if output.starts_with("woofer: ") || output.starts_with("Woofer: ") {
-                output = choice.message.content[8..].to_string();
+fn split_string(input: &str) -> Vec<String> {
+    const MAX_LENGTH: usize = 2000;
+    let mut result = Vec::new();
+
+    // Check if the input is already within the limit.
+    if input.len() <= MAX_LENGTH {
+        result.push(input.to_string());
+    } else {
+        let mut start_index = 0;
+        while start_index < input.len() {
+            // Determine the end index for the current piece, ensuring we don't go beyond the input's length.
+            let mut end_index = std::cmp::min(start_index + MAX_LENGTH, input.len());
+            if end_index == input.len() {
+                result.push(input[start_index..].to_string());
+                break;
+            }
+            // Never split a character.
+            while !input.is_char_boundary(end_index) {
+                end_index -= 1;
+            }
+            let current_piece = &input[start_index..end_index];
+
+            if let Some(last_newline) = current_piece.rfind("\n\n") {
+                end_index = start_index + last_newline + 1;
+            } else if let Some(last_newline) = current_piece.rfind('\n') {
+                end_index = start_index + last_newline + 1;
+            } else if let Some((last_space, space)) = current_piece
+                .char_indices()
+                .rev()
+                .find(|(_, c)| c.is_whitespace())
+            {
+                end_index = start_index + last_space + space.len_utf8();
             }
 
-            let message = replace_discord_emojis(&output);
-            return Ok((choice.message.reasoning.clone(), message.to_string()));
-        } else {
-            return Err(anyhow::anyhow!("No choices found in the response"));
+            result.push(input[start_index..end_index].to_string());
+
+            start_index = end_index;
         }
     }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::fake::{self, FakeTransport};
+    use std::sync::Arc;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn assembles_history() {
+        let transport = FakeTransport::default();
+        let data = Arc::new(RwLock::new(serenity::prelude::TypeMap::new()));
+        let alice = fake::user(2, "alice");
+        let bob = fake::user(3, "bob");
+
+        let question = fake::message(10, alice.clone(), "puppy gpt what is <:woof:123>?");
+        let mut answer = fake::message(11, fake::user(fake::BOT_ID, "woofer"), "a dog noise");
+        answer.referenced_message = Some(Box::new(question.clone()));
+        let chess = fake::message(12, fake::user(fake::BOT_ID, "woofer"), "Illegal move!!!!!");
+        *transport.history.lock().unwrap() = vec![question, answer, chess];
+
+        let msg = fake::message(13, bob, "puppy gpt and why?");
+        let registry = crate::commands::Registry::default();
+        let inv = Invocation::from_message(&transport, &data, &registry, &msg);
+        let messages = get_messages(&inv, "and why?").await;
+
+        let roles: Vec<&str> = messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, ["system", "user", "assistant", "user", "user"]);
+        assert!(messages[0].content.ends_with(
+            "participants: alice, woofer, bob. You are responding to the latest message by bob."
+        ));
+        assert_eq!(messages[1].content, "alice: what is :woof:?");
+        assert_eq!(messages[2].content, "a dog noise");
+        assert_eq!(messages[3].content, "woofer_bot: Illegal move!!!!!");
+        assert_eq!(messages[4].content, "bob: and why?");
+    }
+
+    #[test]
+    fn splits_long_responses() {
+        let paragraph = "woof ".repeat(300);
+        let input = format!("{paragraph}\n\n{paragraph}");
+        let parts = split_string(&input);
+        assert_eq!(parts.len(), 2);
+        assert!(parts.iter().all(|part| part.len() <= 2000));
+        assert_eq!(parts.concat(), input);
+
+        // No whitespace, and characters that straddle the limit.
+        let input = format!("a{}", "🐶".repeat(700));
+        let parts = split_string(&input);
+        assert!(parts.iter().all(|part| part.len() <= 2000));
+        assert_eq!(parts.concat(), input);
+    }
 }
@@ -0,0 +1,529 @@
+use crate::commands::{split_word, Command, Invocation, Reply, Source};
+use crate::cooldown::{Limit, Scope};
+use crate::locale::Locale;
+use crate::prefs::Prefs;
+use crate::scheduler::{Job, JobContext, JobHandler, Scheduler};
+use anyhow::Result;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, TimeZone, Utc, Weekday};
+use chrono_tz::Tz;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, CommandOptionType, MessageId, UserId};
+use serenity::async_trait;
+use serenity::builder::CreateCommandOption;
+
+/// Kind of the scheduler jobs that deliver reminders.
+pub const JOB_KIND: &str = "remind";
+
+/// How many reminders one user may have waiting.
+const MAX_PER_USER: usize = 25;
+
+/// The time of day for reminders that only give a day, like `tomorrow`.
+const DEFAULT_TIME: (u32, u32) = (9, 0);
+
+const LIMITS: &[Limit] = &[Limit::new(
+    Scope::User,
+    5,
+    std::time::Duration::from_secs(60),
+)];
+
+/// A reminder as it is saved in its job's payload.
+#[derive(Serialize, Deserialize)]
+struct Reminder {
+    user: UserId,
+    channel: ChannelId,
+    /// The message that asked for the reminder, which the reminder replies
+    /// to if it's in the same channel.
+    reply_to: Option<MessageId>,
+    text: String,
+    locale: Locale,
+}
+
+pub enum RemindArgs {
+    List,
+    Cancel(u64),
+    Add {
+        /// `None` for `me`, i.e. the current channel.
+        channel: Option<ChannelId>,
+        request: String,
+    },
+}
+
+pub struct RemindCommand;
+
+#[async_trait]
+impl Command for RemindCommand {
+    type Args = RemindArgs;
+
+    fn name(&self) -> &'static str {
+        "remind"
+    }
+
+    fn description(&self, locale: Locale) -> String {
+        locale.text("description-remind", &[])
+    }
+
+    fn usage(&self) -> &'static str {
+        "[me|#channel] [when] [what]|list|cancel [id]"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &[
+            "me in 2h30m to check the oven",
+            "<#1104866433463025704> tomorrow 9am standup",
+            "me friday at 17:30 to call mom",
+            "list",
+            "cancel 3",
+        ]
+    }
+
+    fn limits(&self) -> &'static [Limit] {
+        LIMITS
+    }
+
+    fn parse(&self, args: &str) -> Option<RemindArgs> {
+        let (first, rest) = split_word(args);
+        match first.to_lowercase().as_str() {
+            "list" if rest.is_empty() => Some(RemindArgs::List),
+            "cancel" => rest.parse().ok().map(RemindArgs::Cancel),
+            "me" if !rest.is_empty() => Some(RemindArgs::Add {
+                channel: None,
+                request: rest.to_string(),
+            }),
+            _ if !rest.is_empty() => Some(RemindArgs::Add {
+                channel: Some(parse_channel(first)?),
+                request: rest.to_string(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn options(&self) -> Vec<CreateCommandOption> {
+        vec![CreateCommandOption::new(
+            CommandOptionType::String,
+            "reminder",
+            Locale::En.text("option-remind-reminder", &[]),
+        )
+        .required(true)]
+    }
+
+    async fn run(&self, inv: &Invocation<'_>, args: RemindArgs) -> Vec<Reply> {
+        let scheduler = inv.shared::<Scheduler>().await;
+        let reply = match args {
+            RemindArgs::List => list(inv, &scheduler),
+            RemindArgs::Cancel(id) => cancel(inv, &scheduler, id),
+            RemindArgs::Add { channel, request } => add(inv, &scheduler, channel, &request).await,
+        };
+        vec![Reply::text(reply)]
+    }
+}
+
+/// Parses a `<#id>` channel mention.
+fn parse_channel(mention: &str) -> Option<ChannelId> {
+    let id = mention.strip_prefix("<#")?.strip_suffix('>')?;
+    id.parse().ok().filter(|&id| id != 0).map(ChannelId::new)
+}
+
+/// The author's reminders, soonest first.
+fn reminders(inv: &Invocation<'_>, scheduler: &Scheduler) -> Vec<(Job, Reminder)> {
+    scheduler
+        .jobs(JOB_KIND)
+        .into_iter()
+        .filter_map(|job| {
+            let reminder: Reminder = serde_json::from_value(job.payload.clone()).ok()?;
+            Some((job, reminder))
+        })
+        .filter(|(_, reminder)| reminder.user == inv.author.id)
+        .collect()
+}
+
+fn list(inv: &Invocation<'_>, scheduler: &Scheduler) -> String {
+    let reminders = reminders(inv, scheduler);
+    if reminders.is_empty() {
+        return inv.locale.text("remind-none", &[]);
+    }
+    let lines: Vec<String> = reminders
+        .iter()
+        .map(|(job, reminder)| {
+            inv.locale.text(
+                "remind-list-item",
+                &[
+                    ("id", &job.id),
+                    ("time", &job.next_run.timestamp()),
+                    ("channel", &reminder.channel),
+                    ("text", &reminder.text),
+                ],
+            )
+        })
+        .collect();
+    lines.join("\n")
+}
+
+fn cancel(inv: &Invocation<'_>, scheduler: &Scheduler, id: u64) -> String {
+    let own = reminders(inv, scheduler)
+        .iter()
+        .any(|(job, _)| job.id == id);
+    if !own {
+        return inv.locale.error("remind-not-found", &[("id", &id)]);
+    }
+    match scheduler.cancel(id) {
+        Ok(_) => inv.locale.text("remind-cancelled", &[("id", &id)]),
+        Err(why) => {
+            tracing::error!("Error cancelling reminder: {why:?}");
+            inv.locale.error("remind-save-failed", &[])
+        }
+    }
+}
+
+async fn add(
+    inv: &Invocation<'_>,
+    scheduler: &Scheduler,
+    channel: Option<ChannelId>,
+    request: &str,
+) -> String {
+    // Anyone can be reminded where they are, but only admins may have the
+    // bot post somewhere else, and only in this server where they could post
+    // themselves.
+    let channel = match channel {
+        Some(channel) if channel != inv.channel_id => {
+            if !inv.is_admin().await {
+                return inv.locale.error("remind-channel-admin-only", &[]);
+            }
+            let allowed = match inv
+                .transport
+                .channel_permissions(channel, inv.author.id)
+                .await
+            {
+                Some((guild, permissions)) => {
+                    Some(guild) == inv.guild_id
+                        && permissions.view_channel()
+                        && permissions.send_messages()
+                }
+                None => false,
+            };
+            if !allowed {
+                return inv.locale.error("remind-channel-unavailable", &[]);
+            }
+            channel
+        }
+        Some(channel) => channel,
+        None => inv.channel_id,
+    };
+    if reminders(inv, scheduler).len() >= MAX_PER_USER {
+        return inv
+            .locale
+            .error("remind-too-many", &[("count", &MAX_PER_USER)]);
+    }
+
+    let prefs = inv.shared::<Prefs>().await.get(inv.author.id).await;
+    let tz = prefs.timezone().unwrap_or(Tz::UTC);
+    let words: Vec<&str> = request.split_whitespace().collect();
+    let (at, used) = match parse_when(&words, Utc::now().with_timezone(&tz)) {
+        Some(when) => when,
+        None => {
+            return inv
+                .locale
+                .error("remind-bad-time", &[("request", &request)]);
+        }
+    };
+    if at <= Utc::now() {
+        return inv.locale.error("remind-past", &[]);
+    }
+    let mut text = &words[used..];
+    if text
+        .first()
+        .is_some_and(|word| word.eq_ignore_ascii_case("to"))
+    {
+        text = &text[1..];
+    }
+
+    let reply_to = match inv.source {
+        Source::Message(msg) if channel == msg.channel_id => Some(msg.id),
+        _ => None,
+    };
+    let reminder = Reminder {
+        user: inv.author.id,
+        channel,
+        reply_to,
+        text: defuse_mentions(&text.join(" ")),
+        locale: inv.locale,
+    };
+    match scheduler.schedule_once(JOB_KIND, at, &reminder) {
+        Ok(id) => inv.locale.text(
+            "remind-set",
+            &[
+                ("id", &id),
+                ("time", &at.timestamp()),
+                ("channel", &channel),
+            ],
+        ),
+        Err(why) => {
+            tracing::error!("Error saving reminder: {why:?}");
+            inv.locale.error("remind-save-failed", &[])
+        }
+    }
+}
+
+/// Breaks `@everyone`, `@here` and role and user mentions, so that a
+/// reminder can only ping the person who asked for it.
+fn defuse_mentions(text: &str) -> String {
+    text.replace('@', "@\u{200B}")
+}
+
+/// Delivers reminders when their jobs come due.
+pub struct Deliver;
+
+#[async_trait]
+impl JobHandler for Deliver {
+    async fn run(&self, ctx: &JobContext, job: &Job) -> Result<()> {
+        let reminder: Reminder = serde_json::from_value(job.payload.clone())?;
+        let key = if reminder.text.is_empty() {
+            "remind-ping-empty"
+        } else {
+            "remind-ping"
+        };
+        let content = reminder
+            .locale
+            .text(key, &[("user", &reminder.user), ("text", &reminder.text)]);
+        let mut reply = Reply::text(content);
+        reply.reply_to = Some(true);
+        ctx.transport
+            .post(reminder.channel, reminder.reply_to, reply)
+            .await?;
+        Ok(())
+    }
+}
+
+/// When a reminder is due, parsed from the start of `words`, along with how
+/// many words that took. Understands durations like `in 2h30m` or
+/// `in 1 day and 3 hours`, and days and times like `tomorrow 9am`,
+/// `friday at 17:30`, `2026-12-24 noon` or just `6pm`, in `now`'s timezone.
+fn parse_when(words: &[&str], now: DateTime<Tz>) -> Option<(DateTime<Utc>, usize)> {
+    if words.first()?.eq_ignore_ascii_case("in") {
+        let (duration, used) = parse_duration(&words[1..])?;
+        return Some((now.with_timezone(&Utc) + duration, used + 1));
+    }
+
+    let mut used = 0;
+    let day = parse_day(&words[used..], now.date_naive());
+    if let Some((_, n)) = day {
+        used += n;
+    }
+    let at = words
+        .get(used)
+        .is_some_and(|word| word.eq_ignore_ascii_case("at"));
+    if at {
+        used += 1;
+    }
+    let time = parse_clock(&words[used..], at);
+    match (day, time) {
+        (None, None) => return None,
+        (_, None) if at => return None,
+        (_, Some((_, n))) => used += n,
+        _ => {}
+    }
+
+    let time = time.map_or_else(
+        || NaiveTime::from_hms_opt(DEFAULT_TIME.0, DEFAULT_TIME.1, 0),
+        |(time, _)| Some(time),
+    )?;
+    let date = day.map_or(now.date_naive(), |(date, _)| date);
+    let mut when = local(now.timezone(), date, time)?;
+    // A time without a day means the next time the clock shows it.
+    if day.is_none() && when <= now {
+        when = local(now.timezone(), date.succ_opt()?, time)?;
+    }
+    Some((when.with_timezone(&Utc), used))
+}
+
+/// `date` at `time` in `tz`. Times skipped by daylight saving move forward
+/// by an hour, and repeated ones are taken the first time around.
+fn local(tz: Tz, date: NaiveDate, time: NaiveTime) -> Option<DateTime<Tz>> {
+    let naive = date.and_time(time);
+    tz.from_local_datetime(&naive).earliest().or_else(|| {
+        tz.from_local_datetime(&(naive + Duration::hours(1)))
+            .earliest()
+    })
+}
+
+fn unit_seconds(unit: &str) -> Option<i64> {
+    match unit {
+        "s" | "sec" | "secs" | "second" | "seconds" => Some(1),
+        "m" | "min" | "mins" | "minute" | "minutes" => Some(60),
+        "h" | "hr" | "hrs" | "hour" | "hours" => Some(3600),
+        "d" | "day" | "days" => Some(86400),
+        "w" | "wk" | "week" | "weeks" => Some(7 * 86400),
+        _ => None,
+    }
+}
+
+lazy_static! {
+    static ref COMPACT_DURATION: Regex = Regex::new(r"^(?:\d+[a-z]+)+$").unwrap();
+    static ref DURATION_PART: Regex = Regex::new(r"(\d+)([a-z]+)").unwrap();
+    static ref CLOCK: Regex = Regex::new(r"^(\d{1,2})(?::(\d{2}))?(am|pm)?$").unwrap();
+}
+
+/// A duration like `2h30m`, `90 minutes` or `an hour and 5 min`, and how
+/// many words it took.
+fn parse_duration(words: &[&str]) -> Option<(Duration, usize)> {
+    let mut seconds: i64 = 0;
+    let mut used = 0;
+    loop {
+        let rest = &words[used..];
+        let word = match rest.first() {
+            Some(word) => word.to_lowercase(),
+            None => break,
+        };
+        // `and` only belongs to the duration if more of it follows.
+        let (skip, word) = match (word.as_str(), rest.get(1)) {
+            ("and", Some(next)) if seconds > 0 => (1, next.to_lowercase()),
+            _ => (0, word),
+        };
+        let part = if COMPACT_DURATION.is_match(&word) {
+            DURATION_PART
+                .captures_iter(&word)
+                .map(|c| Some(c[1].parse::<i64>().ok()? * unit_seconds(&c[2])?))
+                .sum::<Option<i64>>()
+                .map(|s| (s, 1))
+        } else {
+            let count = match word.as_str() {
+                "a" | "an" => Some(1),
+                word => word.parse::<i64>().ok(),
+            };
+            let unit = rest
+                .get(skip + 1)
+                .and_then(|unit| unit_seconds(&unit.to_lowercase()));
+            count.zip(unit).map(|(count, unit)| (count * unit, 2))
+        };
+        match part {
+            Some((part, n)) => {
+                seconds = seconds.checked_add(part)?;
+                used += skip + n;
+            }
+            None => break,
+        }
+    }
+    // Longer than this is more likely a typo than a plan.
+    if seconds == 0 || seconds > 5 * 366 * 86400 {
+        return None;
+    }
+    Some((Duration::seconds(seconds), used))
+}
+
+/// A day like `today`, `tomorrow`, `friday`, `next friday` or `2026-12-24`,
+/// and how many words it took. Weekdays mean the next one after today.
+fn parse_day(words: &[&str], today: NaiveDate) -> Option<(NaiveDate, usize)> {
+    let first = words.first()?.to_lowercase();
+    match first.as_str() {
+        "today" => return Some((today, 1)),
+        "tomorrow" => return Some((today.succ_opt()?, 1)),
+        _ => {}
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(&first, "%Y-%m-%d") {
+        return Some((date, 1));
+    }
+    let (skip, word) = match first.as_str() {
+        "next" | "on" => (1, words.get(1)?.to_lowercase()),
+        _ => (0, first),
+    };
+    let weekday: Weekday = word.parse().ok()?;
+    let ahead = (7 + weekday.num_days_from_monday() - today.weekday().num_days_from_monday()) % 7;
+    let ahead = if ahead == 0 { 7 } else { ahead };
+    Some((today + Duration::days(i64::from(ahead)), skip + 1))
+}
+
+/// A time of day like `9am`, `9:30 pm`, `17:30`, `noon` or `midnight`, and
+/// how many words it took. Bare hours like `9` only count after `at`.
+fn parse_clock(words: &[&str], after_at: bool) -> Option<(NaiveTime, usize)> {
+    let first = words.first()?.to_lowercase();
+    match first.as_str() {
+        "noon" => return Some((NaiveTime::from_hms_opt(12, 0, 0)?, 1)),
+        "midnight" => return Some((NaiveTime::from_hms_opt(0, 0, 0)?, 1)),
+        _ => {}
+    }
+    let captures = CLOCK.captures(&first)?;
+    let mut hour: u32 = captures[1].parse().ok()?;
+    let minute: u32 = captures
+        .get(2)
+        .map_or(Some(0), |m| m.as_str().parse().ok())?;
+    let mut used = 1;
+    let mut meridiem = captures.get(3).map(|m| m.as_str().to_string());
+    if meridiem.is_none() {
+        if let Some(next) = words.get(1).map(|w| w.to_lowercase()) {
+            if next == "am" || next == "pm" {
+                meridiem = Some(next);
+                used += 1;
+            }
+        }
+    }
+    match meridiem.as_deref() {
+        Some(meridiem) => {
+            if !(1..=12).contains(&hour) {
+                return None;
+            }
+            hour %= 12;
+            if meridiem == "pm" {
+                hour += 12;
+            }
+        }
+        None if captures.get(2).is_none() && !after_at => return None,
+        None => {}
+    }
+    Some((NaiveTime::from_hms_opt(hour, minute, 0)?, used))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Friday 2026-10-16 at 15:00 in Berlin.
+    fn now() -> DateTime<Tz> {
+        chrono_tz::Europe::Berlin
+            .with_ymd_and_hms(2026, 10, 16, 15, 0, 0)
+            .unwrap()
+    }
+
+    fn when(request: &str) -> Option<(String, usize)> {
+        let words: Vec<&str> = request.split_whitespace().collect();
+        parse_when(&words, now()).map(|(at, used)| {
+            let local = at.with_timezone(&chrono_tz::Europe::Berlin);
+            (local.format("%Y-%m-%d %H:%M").to_string(), used)
+        })
+    }
+
+    #[test]
+    fn parses_reminder_times() {
+        let cases = [
+            ("in 2h30m to check the oven", "2026-10-16 17:30", 2),
+            ("in 1 day and 3 hours", "2026-10-17 18:00", 6),
+            ("in an hour and stuff", "2026-10-16 16:00", 3),
+            ("tomorrow 9am standup", "2026-10-17 09:00", 2),
+            ("tomorrow standup", "2026-10-17 09:00", 1),
+            ("friday at 17:30 call mom", "2026-10-23 17:30", 3),
+            ("next monday 9:15 pm", "2026-10-19 21:15", 4),
+            ("at 9 to go", "2026-10-17 09:00", 2),
+            ("6pm dinner", "2026-10-16 18:00", 1),
+            ("2026-12-24 noon", "2026-12-24 12:00", 2),
+            // The clocks go back on 2026-10-25.
+            ("2026-10-25 at 02:30", "2026-10-25 02:30", 3),
+        ];
+        for (request, expected, used) in cases {
+            assert_eq!(
+                when(request),
+                Some((expected.to_string(), used)),
+                "{}",
+                request
+            );
+        }
+        for request in [
+            "check the oven",
+            "in a while",
+            "at lunch",
+            "9 things",
+            "13pm",
+        ] {
+            assert_eq!(when(request), None, "{}", request);
+        }
+    }
+}
@@ -1,6 +1,19 @@
+use crate::commands::{split_word, Command, Invocation, Reply};
+use crate::components::Component;
+use crate::cooldown::{Limit, Scope};
+use crate::error::{self, UserError};
+use crate::locale::Locale;
+use crate::render;
+use crate::retry::Retry;
+use crate::upstream::{Service, Upstream};
 use anyhow::anyhow;
 use rusty_money::iso;
 use serde::Deserialize;
+use serenity::all::CommandOptionType;
+use serenity::async_trait;
+use serenity::builder::{CreateAttachment, CreateCommandOption, CreateEmbed};
+use serenity::model::Timestamp;
+use std::time::Duration;
 
 #[derive(Deserialize, Debug)]
 struct Stonk {
@@ -27,7 +40,76 @@ struct Meta {
     regular_market_price: f64, // seems to come pre-rounded
     //
     #[serde(rename = "previousClose")]
-    previous_close: f64,
+    previous_close: Option<f64>, // only present for the 1d range
+
+    #[serde(rename = "chartPreviousClose")]
+    chart_previous_close: f64,
+}
+
+impl Meta {
+    /// The close that the chart is compared against.
+    fn reference_close(&self) -> f64 {
+        self.previous_close.unwrap_or(self.chart_previous_close)
+    }
+}
+
+/// Time span of a stonk chart, as understood by Yahoo's `range` parameter.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Range {
+    Day,
+    FiveDays,
+    Month,
+    SixMonths,
+    Year,
+    FiveYears,
+    Max,
+}
+
+impl Range {
+    pub const ALL: [Range; 7] = [
+        Range::Day,
+        Range::FiveDays,
+        Range::Month,
+        Range::SixMonths,
+        Range::Year,
+        Range::FiveYears,
+        Range::Max,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Range::Day => "1d",
+            Range::FiveDays => "5d",
+            Range::Month => "1mo",
+            Range::SixMonths => "6mo",
+            Range::Year => "1y",
+            Range::FiveYears => "5y",
+            Range::Max => "max",
+        }
+    }
+
+    fn interval(self) -> &'static str {
+        match self {
+            Range::Day => "1m",
+            Range::FiveDays => "15m",
+            Range::Month => "1h",
+            Range::SixMonths | Range::Year => "1d",
+            Range::FiveYears => "1wk",
+            Range::Max => "1mo",
+        }
+    }
+}
+
+impl std::str::FromStr for Range {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Range> {
+        Range::ALL
+            .iter()
+            .copied()
+            .find(|range| range.as_str() == s)
+            .ok_or_else(|| anyhow!("unknown range {s}"))
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -75,30 +157,14 @@ fn find_min_max_f64(numbers: &[Option<f64>]) -> (Option<f64>, Option<f64>) {
     (min_number, max_number)
 }
 
-fn plot_svg(result: &Result) -> anyhow::Result<(String, i64)> {
-    let quote = &result.indicators.quote[0].close;
-
-    let (min_ts, max_ts) = find_min_max_i64(&result.timestamp);
-    let min_ts: i64 = min_ts.ok_or_else(|| anyhow!("no min ts found"))?;
-    let max_ts: i64 = max_ts.ok_or_else(|| anyhow!("no max ts found"))?;
-
-    let (min_quote, max_quote) = find_min_max_f64(quote);
-    let mut min_quote: f64 = min_quote.ok_or_else(|| anyhow!("no min quote found"))?;
-    let mut max_quote: f64 = max_quote.ok_or_else(|| anyhow!("no max quote found"))?;
-    let previous_close = result.meta.previous_close;
-    if previous_close < min_quote {
-        min_quote = previous_close;
-    }
-    if previous_close > max_quote {
-        max_quote = previous_close;
-    }
-
+/// Regular trading hours of the day containing `ts`, as unix timestamps.
+fn market_hours(ts: i64) -> anyhow::Result<(i64, i64)> {
     use chrono::{TimeZone, Utc};
     use chrono_tz::America::New_York;
     let first_dt = Utc
-        .timestamp_opt(min_ts, 0)
+        .timestamp_opt(ts, 0)
         .single()
-        .ok_or_else(|| anyhow!("Invalid timestamp: {}", min_ts))?
+        .ok_or_else(|| anyhow!("Invalid timestamp: {}", ts))?
         .with_timezone(&New_York);
     let naive_date = first_dt.date_naive();
     let market_open_naive = naive_date
@@ -115,8 +181,42 @@ fn plot_svg(result: &Result) -> anyhow::Result<(String, i64)> {
         .from_local_datetime(&market_close_naive)
         .single()
         .ok_or_else(|| anyhow!("Ambiguous market close datetime"))?;
-    let market_open_utc = market_open.with_timezone(&Utc).timestamp();
-    let market_close_utc = market_close.with_timezone(&Utc).timestamp();
+    Ok((
+        market_open.with_timezone(&Utc).timestamp(),
+        market_close.with_timezone(&Utc).timestamp(),
+    ))
+}
+
+/// Plots the closing prices. Intraday charts also mark the regular trading
+/// hours, which are meaningless over longer ranges.
+fn plot_svg(result: &Result, intraday: bool) -> anyhow::Result<(String, i64)> {
+    let quote = &result
+        .indicators
+        .quote
+        .first()
+        .ok_or_else(|| anyhow!("no quotes found"))?
+        .close;
+
+    let (min_ts, max_ts) = find_min_max_i64(&result.timestamp);
+    let min_ts: i64 = min_ts.ok_or_else(|| anyhow!("no min ts found"))?;
+    let max_ts: i64 = max_ts.ok_or_else(|| anyhow!("no max ts found"))?;
+
+    let (min_quote, max_quote) = find_min_max_f64(quote);
+    let mut min_quote: f64 = min_quote.ok_or_else(|| anyhow!("no min quote found"))?;
+    let mut max_quote: f64 = max_quote.ok_or_else(|| anyhow!("no max quote found"))?;
+    let previous_close = result.meta.reference_close();
+    if previous_close < min_quote {
+        min_quote = previous_close;
+    }
+    if previous_close > max_quote {
+        max_quote = previous_close;
+    }
+
+    let (market_open_utc, market_close_utc) = if intraday {
+        market_hours(min_ts)?
+    } else {
+        (min_ts, max_ts)
+    };
 
     const WIDTH: i64 = 2048;
     const HEIGHT: i64 = 768;
@@ -128,9 +228,17 @@ fn plot_svg(result: &Result) -> anyhow::Result<(String, i64)> {
     };
     let grey = "#888";
 
+    // A single price, or a flat one, is drawn across the middle.
     let close_y = |close: f64| -> f64 {
-        (HEIGHT as f64) * (0.90 - 0.8 * (close - min_quote) / (max_quote - min_quote))
+        let fraction = if max_quote > min_quote {
+            (close - min_quote) / (max_quote - min_quote)
+        } else {
+            0.5
+        };
+        (HEIGHT as f64) * (0.90 - 0.8 * fraction)
     };
+    // A single data point is drawn at the left edge.
+    let span = (max_ts - min_ts).max(1);
 
     let mut svg_out: String = format!(
         r##"<svg xmlns="http://www.w3.org/2000/svg" width="{WIDTH}" height="{HEIGHT}">
@@ -146,16 +254,16 @@ fn plot_svg(result: &Result) -> anyhow::Result<(String, i64)> {
         .to_string(),
     );
 
-    if market_open_utc >= min_ts && market_open_utc <= max_ts {
-        let open_x = (market_open_utc - min_ts) * WIDTH / (max_ts - min_ts);
+    if intraday && market_open_utc >= min_ts && market_open_utc <= max_ts {
+        let open_x = (market_open_utc - min_ts) * WIDTH / span;
         svg_out.push_str(
             &format!(
                 r#"<line x1="{open_x}" y1="0" x2="{open_x}" y2="{HEIGHT}" stroke="{grey}" stroke-dasharray="16" stroke-width="4" />"#
             )
         );
     }
-    if market_close_utc >= min_ts && market_close_utc <= max_ts {
-        let close_x = (market_close_utc - min_ts) * WIDTH / (max_ts - min_ts);
+    if intraday && market_close_utc >= min_ts && market_close_utc <= max_ts {
+        let close_x = (market_close_utc - min_ts) * WIDTH / span;
         svg_out.push_str(
             &format!(
                 r#"<line x1="{close_x}" y1="0" x2="{close_x}" y2="{HEIGHT}" stroke="{grey}" stroke-dasharray="16" stroke-width="4" />"#
@@ -176,7 +284,7 @@ fn plot_svg(result: &Result) -> anyhow::Result<(String, i64)> {
 
     for pair in result.timestamp.iter().zip(quote.iter()) {
         if let (Some(timestamp), Some(close)) = pair {
-            let x = (timestamp - min_ts) * WIDTH / (max_ts - min_ts);
+            let x = (timestamp - min_ts) * WIDTH / span;
             let y = close_y(*close);
             svg_out.push_str(&format!("{x},{y} ").to_string());
             if *timestamp >= market_open_utc && *timestamp <= market_close_utc {
@@ -202,35 +310,24 @@ fn plot_svg(result: &Result) -> anyhow::Result<(String, i64)> {
     Ok((svg_out, max_ts))
 }
 
-fn save_png(svg: &str) -> anyhow::Result<String> {
-    let mut fontdb = resvg::usvg::fontdb::Database::new();
-    fontdb.load_system_fonts();
-    fontdb.set_monospace_family("DejaVu Sans Mono");
-    let opt = resvg::usvg::Options::default();
-    let rtree = resvg::usvg::Tree::from_str(svg, &opt, &fontdb)?;
-
-    let pixmap_size = rtree.size();
-    let mut pixmap =
-        resvg::tiny_skia::Pixmap::new(pixmap_size.width() as u32, pixmap_size.height() as u32)
-            .ok_or_else(|| anyhow!("couldn't allocate pixmap"))?;
-
-    resvg::render(
-        &rtree,
-        resvg::tiny_skia::Transform::identity(),
-        &mut pixmap.as_mut(),
-    );
-    let filename = format!("{}.png", uuid::Uuid::new_v4()).to_string();
-    pixmap.save_png(&filename)?;
-    Ok(filename)
-}
-
-fn stonk_to_image(stonk_result: &Result, ticker: &str) -> anyhow::Result<(String, String, i64)> {
-    let (svg, latest_ts) = plot_svg(stonk_result)?;
-    let filename = save_png(&svg)?;
-    let currency = iso::find(&stonk_result.meta.currency)
-        .expect("currency code missing from response metadata");
+/// What the chart is called in the reply's embed.
+const CHART_FILENAME: &str = "stonk.png";
+
+async fn stonk_to_image(
+    stonk_result: &Result,
+    ticker: &str,
+    range: Range,
+) -> anyhow::Result<(String, CreateAttachment, i64)> {
+    let (svg, latest_ts) = plot_svg(stonk_result, range == Range::Day)?;
+    let chart = render::attachment(svg, CHART_FILENAME).await?;
+    // Some listings are quoted in units that aren't ISO currencies, like
+    // GBp for pence, so fall back to the code itself.
+    let currency = match iso::find(&stonk_result.meta.currency) {
+        Some(currency) => currency.symbol.to_string(),
+        None => format!("{} ", stonk_result.meta.currency),
+    };
 
-    let emoji = if stonk_result.meta.regular_market_price > stonk_result.meta.previous_close {
+    let emoji = if stonk_result.meta.regular_market_price > stonk_result.meta.reference_close() {
         "<:puprocket:1213637827619848284>"
     } else {
         "<:ruprocket:1213637826374144000>"
@@ -238,35 +335,236 @@ fn stonk_to_image(stonk_result: &Result, ticker: &str) -> anyhow::Result<(String
 
     let out = format!(
         "{}: {}{} {}",
-        ticker, currency.symbol, stonk_result.meta.regular_market_price, emoji
+        ticker, currency, stonk_result.meta.regular_market_price, emoji
     );
-    Ok((out, filename, latest_ts))
+    Ok((out, chart, latest_ts))
 }
 
-pub async fn stonk(ticker: &str) -> anyhow::Result<(String, String, i64)> {
+/// Whether `ticker` looks like a Yahoo symbol, e.g. `tsla`, `brk-b`,
+/// `^gspc` or `eurusd=x`, and so can't change the request's path.
+fn is_ticker(ticker: &str) -> bool {
+    let valid = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '^' | '=');
+    (1..=20).contains(&ticker.len())
+        && ticker.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '^')
+        && ticker.chars().all(valid)
+}
+
+/// Long enough to spare Yahoo when everyone checks the same ticker, short
+/// enough that the price is still current.
+const QUOTE_TTL: Duration = Duration::from_secs(30);
+
+pub async fn stonk(
+    upstream: &Upstream,
+    ticker: &str,
+    range: Range,
+) -> anyhow::Result<(String, CreateAttachment, i64)> {
+    if !is_ticker(ticker) {
+        return Err(UserError::BadInput {
+            what: "ticker",
+            input: ticker.to_string(),
+        }
+        .into());
+    }
     // TODO use a source that has not been officially discontinued
-    let stonk_url = format!(
-        "https://query1.finance.yahoo.com/v8/finance/chart/{}?includePrePost=true",
-        ticker
-    );
+    let response = upstream
+        .get(Service::Yahoo, &format!("/v8/finance/chart/{ticker}"))
+        .query("includePrePost", "true")
+        .query("range", range.as_str())
+        .query("interval", range.interval())
+        // Mimic curl user-agent
+        .header(
+            "User-Agent",
+            "Mozilla/4.0 (compatible; MSIE 6.0; Windows NT 5.2; .NET CLR 1.0.3705;)",
+        )
+        .cache(QUOTE_TTL)
+        .retry(Retry::DEFAULT)
+        .send()
+        .await?;
+
+    if response.status == reqwest::StatusCode::NOT_FOUND {
+        return Err(anyhow!("{}", response.body).context(UserError::NotFound {
+            what: "stock",
+            name: ticker.to_string(),
+        }));
+    }
+    let stonk = response.error_for_status()?.json::<Stonk>()?;
+    let stonk_result: &Result = stonk
+        .chart
+        .result
+        .first()
+        .ok_or_else(|| UserError::NotFound {
+            what: "stock",
+            name: ticker.to_string(),
+        })?;
+    stonk_to_image(stonk_result, ticker, range).await
+}
+
+/// Yahoo starts refusing requests from clients that poll it too often.
+const LIMITS: &[Limit] = &[
+    Limit::new(Scope::User, 3, Duration::from_secs(10)),
+    Limit::new(Scope::Guild, 10, Duration::from_secs(5)),
+];
+
+pub struct StonkCommand;
+
+pub struct StonkArgs {
+    ticker: String,
+    range: Range,
+}
+
+#[async_trait]
+impl Command for StonkCommand {
+    type Args = StonkArgs;
+
+    fn name(&self) -> &'static str {
+        "stonk"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["stock"]
+    }
+
+    fn description(&self, locale: Locale) -> String {
+        locale.text("description-stonk", &[])
+    }
+
+    fn rerun_on_edit(&self) -> bool {
+        true
+    }
 
-    let client = reqwest::Client::builder()
-        .user_agent("Mozilla/4.0 (compatible; MSIE 6.0; Windows NT 5.2; .NET CLR 1.0.3705;)") // Mimic curl user-agent
-        .build()?;
+    fn limits(&self) -> &'static [Limit] {
+        LIMITS
+    }
+
+    fn usage(&self) -> &'static str {
+        "[ticker] [range]"
+    }
 
-    let response = client.get(&stonk_url).send().await;
+    fn examples(&self) -> &'static [&'static str] {
+        &["tsla", "aapl 5d"]
+    }
 
-    match response {
-        Ok(resp) => {
-            // Check if the request was successful
-            let status = resp.status();
-            if status.is_success() {
-                let stonk_result: &Result = &resp.json::<Stonk>().await?.chart.result[0];
-                stonk_to_image(stonk_result, ticker)
+    fn typing(&self) -> bool {
+        true
+    }
+
+    /// Accepts `[ticker] [range]`, where the range defaults to one day.
+    fn parse(&self, args: &str) -> Option<StonkArgs> {
+        let lower = args.to_lowercase();
+        let (ticker, range) = split_word(&lower);
+        if ticker.is_empty() {
+            return None;
+        }
+        let range = if range.is_empty() {
+            Range::Day
+        } else {
+            range.parse().ok()?
+        };
+        Some(StonkArgs {
+            ticker: ticker.to_string(),
+            range,
+        })
+    }
+
+    fn options(&self) -> Vec<CreateCommandOption> {
+        let mut range = CreateCommandOption::new(
+            CommandOptionType::String,
+            "range",
+            Locale::En.text("option-stonk-range", &[]),
+        );
+        for r in Range::ALL {
+            range = range.add_string_choice(r.as_str(), r.as_str());
+        }
+        vec![
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "ticker",
+                Locale::En.text("option-stonk-ticker", &[]),
+            )
+            .required(true),
+            range,
+        ]
+    }
+
+    async fn run(&self, inv: &Invocation<'_>, args: StonkArgs) -> Vec<Reply> {
+        let StonkArgs { ticker, range } = args;
+        let upstream = inv.shared::<Upstream>().await;
+        let reply = async {
+            let (stonk, chart, timestamp) = stonk(&upstream, &ticker, range).await?;
+            let title = if range == Range::Day {
+                format!("puppy stonk {ticker}")
             } else {
-                Err(anyhow!("Request failed with status: {}", resp.status()))
+                format!("puppy stonk {ticker} {}", range.as_str())
+            };
+            let mut embed = CreateEmbed::new()
+                .title(title)
+                .description(stonk)
+                .image(format!("attachment://{CHART_FILENAME}"));
+            if let Ok(timestamp) = Timestamp::from_unix_timestamp(timestamp) {
+                embed = embed.timestamp(timestamp);
             }
+            let refresh = Component::button(
+                "stonk",
+                format!("{ticker} {}", range.as_str()),
+                inv.locale.text("button-refresh", &[]),
+            );
+            anyhow::Ok(Reply::embed(embed).attachment(chart).component(refresh))
+        };
+        match reply.await {
+            Ok(reply) => vec![reply],
+            Err(why) => vec![error::reply(inv.locale, self.name(), &why)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_only_tickers() {
+        for ticker in ["tsla", "brk-b", "^gspc", "eurusd=x", "es=f", "7203.t"] {
+            assert!(is_ticker(ticker), "{}", ticker);
         }
-        Err(e) => Err(anyhow!("Request failed cuz: {}", e)),
+        for ticker in ["", "..", "../v7/quote", "tsla?x=1", "a/b", "#x", "tsla%2f"] {
+            assert!(!is_ticker(ticker), "{}", ticker);
+        }
+    }
+
+    #[test]
+    fn parses_ticker_and_range() {
+        let cases = [
+            ("tsla", Some(("tsla", Range::Day))),
+            ("AAPL 5d", Some(("aapl", Range::FiveDays))),
+            ("^gspc   MAX", Some(("^gspc", Range::Max))),
+            ("brk-b 6mo", Some(("brk-b", Range::SixMonths))),
+            ("tsla 2w", None),
+            ("", None),
+        ];
+        for (args, expected) in cases {
+            let parsed = StonkCommand.parse(args);
+            let parsed = parsed
+                .as_ref()
+                .map(|args| (args.ticker.as_str(), args.range));
+            assert_eq!(parsed, expected, "{}", args);
+        }
+    }
+
+    #[test]
+    fn plots_a_single_data_point() {
+        let result: Result = serde_json::from_value(serde_json::json!({
+            "meta": {
+                "currency": "USD",
+                "regularMarketPrice": 101.0,
+                "chartPreviousClose": 100.0
+            },
+            "timestamp": [1760000000],
+            "indicators": { "quote": [{ "close": [101.0] }] }
+        }))
+        .unwrap();
+        let (svg, latest) = plot_svg(&result, false).unwrap();
+        assert_eq!(latest, 1760000000);
+        assert!(svg.contains("points=\"0,"), "{}", svg);
+        assert!(!svg.contains("NaN"), "{}", svg);
     }
 }
@@ -1,4 +1,16 @@
+use crate::commands::{split_word, string_option, Command, Invocation, Reply};
+use crate::components::Component;
+use crate::cooldown::{Limit, Scope};
+use crate::error::{self, UserError};
+use crate::locale::Locale;
+use crate::retry::Retry;
+use crate::upstream::{Service, Upstream};
 use serde::Deserialize;
+use serenity::all::{CommandOptionType, ResolvedOption, ResolvedValue};
+use serenity::async_trait;
+use serenity::builder::CreateCommandOption;
+use std::time::Duration;
+
 #[derive(Deserialize, Debug)]
 pub struct Location {
     lat: f64,
@@ -17,20 +29,49 @@ pub struct GeocodeLocation {
 
 #[derive(Deserialize, Debug)]
 pub struct Geocode {
+    status: String,
     results: Vec<GeocodeLocation>,
 }
 
-pub async fn geocode(address: String, apikey: &str) -> Result<Location, reqwest::Error> {
-    let encoded: String = url::form_urlencoded::Serializer::new(String::new())
-        .append_pair("address", &address)
-        .append_pair("key", apikey)
-        .finish();
-    let geocode_url = format!(
-        "https://maps.googleapis.com/maps/api/geocode/json?{}",
-        encoded
-    );
-    let geocode_response: Geocode = reqwest::get(&geocode_url).await?.json().await?;
-    let location = &geocode_response.results.first().unwrap().geometry.location;
+/// Places don't move, so geocodes are reused for a long time.
+const GEOCODE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+/// OpenWeather updates current conditions and forecasts about every ten
+/// minutes.
+const WEATHER_TTL: Duration = Duration::from_secs(10 * 60);
+/// Stations report at least hourly, sometimes more often when it changes.
+const METAR_TTL: Duration = Duration::from_secs(5 * 60);
+
+pub async fn geocode(
+    upstream: &Upstream,
+    address: String,
+    apikey: &str,
+) -> anyhow::Result<Location> {
+    let geocode_response: Geocode = upstream
+        .get(Service::GoogleMaps, "/maps/api/geocode/json")
+        .query("address", &address)
+        .secret_query("key", apikey)
+        .cache(GEOCODE_TTL)
+        .retry(Retry::DEFAULT)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()?;
+    let location = match geocode_response.results.first() {
+        Some(result) => &result.geometry.location,
+        None if geocode_response.status == "ZERO_RESULTS" => {
+            return Err(UserError::NotFound {
+                what: "place",
+                name: address,
+            }
+            .into())
+        }
+        None => {
+            return Err(
+                anyhow::anyhow!("Geocoding failed: {}", geocode_response.status)
+                    .context(UserError::UpstreamDown(Service::GoogleMaps.label())),
+            )
+        }
+    };
     Ok(Location {
         lat: location.lat,
         lng: location.lng,
@@ -55,35 +96,117 @@ pub struct Weather {
     weather: Vec<WeatherWeather>,
 }
 
-pub async fn weather(location: &Location, apikey: &str) -> Result<Weather, reqwest::Error> {
-    let forecast_url = format!(
-        "https://api.openweathermap.org/data/2.5/weather?lat={}&lon={}&appid={}&lang=en",
-        location.lat, location.lng, apikey
-    );
-    println!("{}", forecast_url);
-    let weather: Weather = reqwest::get(&forecast_url).await?.json().await?;
+pub async fn weather(
+    upstream: &Upstream,
+    location: &Location,
+    apikey: &str,
+    locale: Locale,
+) -> anyhow::Result<Weather> {
+    let weather: Weather = upstream
+        .get(Service::OpenWeather, "/data/2.5/weather")
+        .query("lat", location.lat)
+        .query("lon", location.lng)
+        .secret_query("appid", apikey)
+        .query("lang", locale.code())
+        .cache(WEATHER_TTL)
+        .retry(Retry::DEFAULT)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()?;
+    if weather.weather.is_empty() {
+        return Err(anyhow::anyhow!("No weather conditions in response")
+            .context(UserError::UpstreamDown(Service::OpenWeather.label())));
+    }
     Ok(weather)
 }
 
+#[derive(Deserialize, Debug)]
+pub struct ForecastEntry {
+    /// Unix time the entry is for.
+    dt: i64,
+    main: WeatherMain,
+    weather: Vec<WeatherWeather>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Forecast {
+    list: Vec<ForecastEntry>,
+}
+
+/// Entries are three hours apart, so this covers the next day.
+const FORECAST_ENTRIES: usize = 8;
+
+pub async fn forecast(
+    upstream: &Upstream,
+    location: &Location,
+    apikey: &str,
+    locale: Locale,
+) -> anyhow::Result<Forecast> {
+    let forecast: Forecast = upstream
+        .get(Service::OpenWeather, "/data/2.5/forecast")
+        .query("lat", location.lat)
+        .query("lon", location.lng)
+        .secret_query("appid", apikey)
+        .query("lang", locale.code())
+        .query("cnt", FORECAST_ENTRIES)
+        .cache(WEATHER_TTL)
+        .retry(Retry::DEFAULT)
+        .send()
+        .await?
+        .error_for_status()?
+        .json()?;
+    if forecast.list.is_empty() {
+        return Err(anyhow::anyhow!("No forecast entries in response")
+            .context(UserError::UpstreamDown(Service::OpenWeather.label())));
+    }
+    Ok(forecast)
+}
+
+/// One line per entry, with times that Discord shows in the reader's own
+/// timezone.
+pub fn forecast_string(address: String, units: &str, forecast: Forecast, locale: Locale) -> String {
+    let uni = convert_unit_to_symbol(units);
+    let lines: Vec<String> = forecast
+        .list
+        .iter()
+        .map(|entry| {
+            let temp = convert_kelvin_to_unit(entry.main.temp, units);
+            let (emo, description) = match entry.weather.first() {
+                Some(weather) => (emoji(&weather.icon), weather.description.as_str()),
+                None => (String::new(), ""),
+            };
+            format!("<t:{}:t> {emo} {temp:.1} {uni} {description}", entry.dt)
+        })
+        .collect();
+    locale.text(
+        "weather-forecast",
+        &[("address", &address), ("forecast", &lines.join("\n"))],
+    )
+}
+
 pub fn weather_string(
     address: String,
     location: &Location,
     units: &str,
     weather: Weather,
+    locale: Locale,
 ) -> String {
     let emo = emoji(&weather.weather[0].icon);
     let uni = convert_unit_to_symbol(units);
     let temp = convert_kelvin_to_unit(weather.main.temp, units);
-    format!(
-        "weather in {} ({:.6}, {:.6}): {}. Temperature {:.2} {}. Humidity {:.1}%. {}",
-        address,
-        location.lat,
-        location.lng,
-        weather.weather[0].description,
-        temp,
-        uni,
-        weather.main.humidity,
-        emo
+    locale.text(
+        "weather",
+        &[
+            ("address", &address),
+            ("lat", &format!("{:.6}", location.lat)),
+            ("lng", &format!("{:.6}", location.lng)),
+            ("description", &weather.weather[0].description),
+            ("temperature", &format!("{temp:.2}")),
+            ("unit", &uni),
+            ("humidity", &format!("{:.1}", weather.main.humidity)),
+            ("emoji", &emo),
+        ],
     )
 }
 
@@ -134,15 +257,285 @@ pub struct MetarResponse {
     sanitized: String,
 }
 
-pub async fn metar(location: &str, apikey: &str) -> Result<String, reqwest::Error> {
-    let metar_url = format!("https://avwx.rest/api/metar/{}?filter=sanitized", location);
-    println!("{}", metar_url);
-    let metar: MetarResponse = reqwest::Client::new()
-        .get(&metar_url)
+pub async fn metar(upstream: &Upstream, location: &str, apikey: &str) -> anyhow::Result<String> {
+    if !(3..=4).contains(&location.len()) || !location.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(UserError::BadInput {
+            what: "station",
+            input: location.to_string(),
+        }
+        .into());
+    }
+    let response = upstream
+        .get(Service::Avwx, &format!("/api/metar/{location}"))
+        .query("filter", "sanitized")
         .header("Authorization", format!("TOKEN {}", apikey))
+        .cache(METAR_TTL)
+        .retry(Retry::DEFAULT)
         .send()
-        .await?
-        .json()
         .await?;
+    // AVWX answers unknown stations with a 400 and a reason in the body.
+    if matches!(response.status.as_u16(), 400 | 404) {
+        return Err(
+            anyhow::anyhow!("{}", response.body).context(UserError::NotFound {
+                what: "metar",
+                name: location.to_string(),
+            }),
+        );
+    }
+    let metar: MetarResponse = response.error_for_status()?.json()?;
     Ok(format!("`{}`", metar.sanitized))
 }
+
+/// Used by both weather and metar, whose APIs have small free tiers. Buckets
+/// are keyed by command name, so each command still gets its own budget.
+const LIMITS: &[Limit] = &[
+    Limit::new(Scope::User, 3, Duration::from_secs(10)),
+    Limit::new(Scope::Guild, 10, Duration::from_secs(5)),
+];
+
+pub struct WeatherCommand {
+    pub openweather_token: String,
+    pub google_maps_token: String,
+}
+
+pub struct WeatherArgs {
+    forecast: bool,
+    units: String,
+    address: String,
+}
+
+#[async_trait]
+impl Command for WeatherCommand {
+    type Args = WeatherArgs;
+
+    fn name(&self) -> &'static str {
+        "weather"
+    }
+
+    fn aliases(&self) -> &'static [&'static str] {
+        &["w"]
+    }
+
+    fn description(&self, locale: Locale) -> String {
+        locale.text("description-weather", &[])
+    }
+
+    fn rerun_on_edit(&self) -> bool {
+        true
+    }
+
+    fn limits(&self) -> &'static [Limit] {
+        LIMITS
+    }
+
+    fn usage(&self) -> &'static str {
+        "[forecast] [unit] [place name]"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["seattle", "celsius tokyo", "forecast paris"]
+    }
+
+    fn typing(&self) -> bool {
+        true
+    }
+
+    /// Accepts `[forecast] [unit] [place name]`, where the unit defaults to
+    /// kelvin.
+    fn parse(&self, args: &str) -> Option<WeatherArgs> {
+        let lower = args.to_lowercase();
+        let (forecast, lower) = match split_word(&lower) {
+            ("forecast", rest) if !rest.is_empty() => (true, rest.to_string()),
+            _ => (false, lower),
+        };
+        let (unit, rest) = split_word(&lower);
+        let (units, address) = match unit {
+            "celsius" | "fahrenheit" | "kelvin" if !rest.is_empty() => (unit, rest),
+            _ => ("kelvin", lower.as_str()),
+        };
+        if address.is_empty() {
+            return None;
+        }
+        Some(WeatherArgs {
+            forecast,
+            units: units.to_string(),
+            address: address.to_string(),
+        })
+    }
+
+    fn options(&self) -> Vec<CreateCommandOption> {
+        vec![
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "place",
+                Locale::En.text("option-weather-place", &[]),
+            )
+            .required(true),
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "units",
+                Locale::En.text("option-weather-units", &[]),
+            )
+            .add_string_choice("kelvin", "kelvin")
+            .add_string_choice("celsius", "celsius")
+            .add_string_choice("fahrenheit", "fahrenheit"),
+            CreateCommandOption::new(
+                CommandOptionType::Boolean,
+                "forecast",
+                Locale::En.text("option-weather-forecast", &[]),
+            ),
+        ]
+    }
+
+    fn parse_options(&self, options: &[ResolvedOption]) -> Option<WeatherArgs> {
+        let address = string_option(options, "place")?.to_lowercase();
+        let units = string_option(options, "units").unwrap_or("kelvin");
+        let forecast = options.iter().any(|option| {
+            option.name == "forecast" && matches!(option.value, ResolvedValue::Boolean(true))
+        });
+        Some(WeatherArgs {
+            forecast,
+            units: units.to_string(),
+            address,
+        })
+    }
+
+    async fn run(&self, inv: &Invocation<'_>, args: WeatherArgs) -> Vec<Reply> {
+        let upstream = inv.shared::<Upstream>().await;
+        let WeatherArgs {
+            forecast: show_forecast,
+            units,
+            address,
+        } = args;
+        // Switches to the other view of the same place.
+        let toggle = if show_forecast {
+            let label = inv.locale.text("weather-show-current", &[]);
+            Component::button("weather", format!("{units} {address}"), label)
+        } else {
+            let label = inv.locale.text("weather-show-forecast", &[]);
+            Component::button("weather", format!("forecast {units} {address}"), label)
+        };
+        let report = async {
+            let location = geocode(&upstream, address.clone(), &self.google_maps_token).await?;
+            if show_forecast {
+                let forecast =
+                    forecast(&upstream, &location, &self.openweather_token, inv.locale).await?;
+                return anyhow::Ok(forecast_string(address, &units, forecast, inv.locale));
+            }
+            let weather =
+                weather(&upstream, &location, &self.openweather_token, inv.locale).await?;
+            anyhow::Ok(weather_string(
+                address, &location, &units, weather, inv.locale,
+            ))
+        };
+        match report.await {
+            Ok(report) => vec![Reply::text(report).component(toggle)],
+            Err(why) => vec![error::reply(inv.locale, self.name(), &why)],
+        }
+    }
+}
+
+pub struct MetarCommand {
+    pub avwx_token: String,
+}
+
+#[async_trait]
+impl Command for MetarCommand {
+    type Args = String;
+
+    fn name(&self) -> &'static str {
+        "metar"
+    }
+
+    fn description(&self, locale: Locale) -> String {
+        locale.text("description-metar", &[])
+    }
+
+    fn rerun_on_edit(&self) -> bool {
+        true
+    }
+
+    fn limits(&self) -> &'static [Limit] {
+        LIMITS
+    }
+
+    fn usage(&self) -> &'static str {
+        "[station]"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["KSFO"]
+    }
+
+    fn typing(&self) -> bool {
+        true
+    }
+
+    fn parse(&self, args: &str) -> Option<String> {
+        if args.is_empty() {
+            None
+        } else {
+            Some(args.to_lowercase())
+        }
+    }
+
+    fn options(&self) -> Vec<CreateCommandOption> {
+        vec![CreateCommandOption::new(
+            CommandOptionType::String,
+            "station",
+            Locale::En.text("option-metar-station", &[]),
+        )
+        .required(true)]
+    }
+
+    async fn run(&self, inv: &Invocation<'_>, station: String) -> Vec<Reply> {
+        let upstream = inv.shared::<Upstream>().await;
+        match metar(&upstream, &station, &self.avwx_token).await {
+            Ok(metar) => vec![Reply::text(metar)],
+            Err(why) => vec![error::reply(inv.locale, self.name(), &why)],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_weather_args() {
+        let command = WeatherCommand {
+            openweather_token: String::new(),
+            google_maps_token: String::new(),
+        };
+        let cases = [
+            ("Seattle", Some((false, "kelvin", "seattle"))),
+            ("celsius Tokyo", Some((false, "celsius", "tokyo"))),
+            ("forecast Paris", Some((true, "kelvin", "paris"))),
+            (
+                "forecast fahrenheit new york",
+                Some((true, "fahrenheit", "new york")),
+            ),
+            // A lone keyword is a place name, not an option.
+            ("forecast", Some((false, "kelvin", "forecast"))),
+            ("celsius", Some((false, "kelvin", "celsius"))),
+            ("forecast celsius", Some((true, "kelvin", "celsius"))),
+            ("", None),
+        ];
+        for (args, expected) in cases {
+            let parsed = command.parse(args);
+            let parsed = parsed
+                .as_ref()
+                .map(|args| (args.forecast, args.units.as_str(), args.address.as_str()));
+            assert_eq!(parsed, expected, "{}", args);
+        }
+    }
+
+    #[test]
+    fn parses_metar_station() {
+        let command = MetarCommand {
+            avwx_token: String::new(),
+        };
+        assert_eq!(command.parse("KSFO").as_deref(), Some("ksfo"));
+        assert_eq!(command.parse(""), None);
+    }
+}
@@ -1,382 +1,217 @@
+use crate::commands::{Command, Invocation, Reply};
+use crate::cooldown::{Limit, Scope};
+use crate::locale::{fill, Locale};
+use anyhow::Context as _;
+use lazy_static::lazy_static;
 use rand::distr::{weighted::WeightedIndex, Distribution};
-
-macro_rules! weighted_choice_fn {
-    ( $( ($x:expr, $y:expr) ),* ) => {
-        {
-            let weights: Vec<i64> = vec![
-                $($x,)*
-            ];
-            let items: Vec<fn() -> String> = vec![
-                $($y,)*
-            ];
-            let wc = WeightedIndex::new(&weights).unwrap();
-            let mut rng = rand::rng();
-            items[wc.sample(&mut rng)]
+use rand::prelude::IndexedRandom;
+use regex::Regex;
+use serde::Deserialize;
+use serenity::async_trait;
+use serenity::prelude::TypeMapKey;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Enough for a laugh without burying the channel in excuses.
+const LIMITS: &[Limit] = &[Limit::new(Scope::Channel, 5, Duration::from_secs(3))];
+
+/// Named rules, each a list of alternatives to pick from. `{name}` in an
+/// alternative is replaced by whatever the rule called `name` makes up.
+pub type Grammar = HashMap<String, Vec<Alternative>>;
+
+/// Either plain text, or `[weight, text]` for one that should come up more
+/// often than the others.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum Alternative {
+    Text(String),
+    Weighted(u32, String),
+}
+
+impl Alternative {
+    fn weight(&self) -> u32 {
+        match self {
+            Alternative::Text(_) => 1,
+            Alternative::Weighted(weight, _) => *weight,
         }
-    };
-}
+    }
 
-macro_rules! weighted_choice {
-    ( $( ($x:expr, $y:expr) ),* ) => {
-        {
-            let weights: Vec<i64> = vec![
-                $($x,)*
-            ];
-            let items: Vec<String> = vec![
-                $($y.to_string(),)*
-            ];
-            let wc = WeightedIndex::new(&weights).unwrap();
-            let mut rng = rand::rng();
-            items[wc.sample(&mut rng)].clone()
+    fn text(&self) -> &str {
+        match self {
+            Alternative::Text(text) | Alternative::Weighted(_, text) => text,
         }
+    }
+}
+
+/// Rules that refer to themselves eventually pick an alternative that
+/// doesn't, but beyond this depth the first alternative is always taken,
+/// so it should be the simplest.
+const MAX_DEPTH: usize = 12;
+
+/// Expands `rule` from `grammar`, or `None` if there's no such rule.
+fn expand(grammar: &Grammar, rule: &str, depth: usize) -> Option<String> {
+    let alternatives = grammar.get(rule)?;
+    let chosen = if depth < MAX_DEPTH {
+        let weights = WeightedIndex::new(alternatives.iter().map(Alternative::weight)).ok()?;
+        &alternatives[weights.sample(&mut rand::rng())]
+    } else {
+        alternatives.first()?
     };
+    Some(fill(chosen.text(), |name| expand(grammar, name, depth + 1)))
 }
 
-pub fn why() -> String {
-    weighted_choice_fn!((1, special), (4, phrase), (6, sentence))()
-}
-
-fn special() -> String {
-    weighted_choice!(
-        (1, "why not?"),
-        (1, "woof woof!"),
-        (1, "why indeed?"),
-        (
-            1,
-            "THERE IS AS YET INSUFFICIENT DATA FOR A MEANINGFUL ANSWER"
-        ),
-        (1, "life is full of mysteries"),
-        (1, "I'm not telling you"),
-        (1, "you know why")
-    )
-}
-
-fn phrase() -> String {
-    weighted_choice_fn!(
-        (1, || -> String {
-            format!("for the {} {}", nouned_verb(), prepositional_phrase())
-        }),
-        (1, || -> String { format!("because {}", sentence()) }),
-        (1, || -> String {
-            format!("so as to {} {}", present_verb_phrase(), object())
-        }),
-        (1, || -> String {
-            format!("to {} {}", present_verb_phrase(), object())
-        })
-    )()
+/// A random excuse in `locale`.
+pub fn why(locale: Locale) -> String {
+    lazy_static! {
+        static ref BARE_A: Regex = Regex::new(r"\ba ([aeiouAEIOU])").unwrap();
+    }
+    let excuse = expand(locale.grammar(), "excuse", 0).unwrap_or_default();
+    match locale {
+        // Which article fits depends on the word picked after it.
+        Locale::En => BARE_A.replace_all(&excuse, "an $1").into_owned(),
+        _ => excuse,
+    }
 }
 
-fn preposition() -> String {
-    weighted_choice!((1, "of"), (1, "from"))
-}
+pub struct WhyCommand;
 
-fn prepositional_phrase() -> String {
-    weighted_choice_fn!(
-        (1, || -> String {
-            format!("{} {} {}", preposition(), article(), noun_phrase())
-        }),
-        (1, || -> String {
-            format!("{} {}", preposition(), proper_noun())
-        }),
-        (1, || -> String {
-            format!("{} {}", preposition(), accusative_pronoun())
-        })
-    )()
-}
+#[async_trait]
+impl Command for WhyCommand {
+    type Args = ();
 
-fn sentence() -> String {
-    format!("{} {}", subject(), predicate())
-}
+    fn name(&self) -> &'static str {
+        "why"
+    }
 
-fn subject() -> String {
-    weighted_choice_fn!(
-        (1, proper_noun),
-        (1, nominative_pronoun),
-        (1, || -> String {
-            format!("{} {}", article(), noun_phrase())
-        })
-    )()
-}
+    fn description(&self, locale: Locale) -> String {
+        locale.text("description-why", &[])
+    }
 
-fn proper_noun() -> String {
-    weighted_choice!(
-        (10, "Purple Puppy"),
-        (3, "Donald Trump"),
-        (5, "Woofer"),
-        (2, "Purple Puppies' Porpoise"),
-        (1, "Blue Puppy"),
-        (1, "Red Puppy"),
-        (1, "Green Puppy"),
-        (1, "Yellow Puppy")
-    )
-}
+    fn rerun_on_edit(&self) -> bool {
+        true
+    }
 
-fn noun_phrase() -> String {
-    weighted_choice_fn!(
-        (3, noun),
-        (3, || -> String {
-            format!("{} {}", adjective_phrase(), noun_phrase())
-        }),
-        (1, || -> String {
-            format!("{} and {}", noun_phrase(), noun_phrase())
-        })
-    )()
-}
-
-fn noun() -> String {
-    weighted_choice!(
-        (1, "puppy"),
-        (1, "cat"),
-        (1, "kitten"),
-        (1, "dog"),
-        (1, "stalker"),
-        (1, "siege tank"),
-        (1, "marine"),
-        (1, "marauder"),
-        (1, "zealot"),
-        (1, "zergling"),
-        (1, "baneling"),
-        (1, "roach"),
-        (1, "queen"),
-        (1, "hydralisk"),
-        (1, "ultralisk"),
-        (1, "adept"),
-        (1, "immortal"),
-        (1, "sentry"),
-        (1, "high templar"),
-        (1, "dark templar"),
-        (1, "archon"),
-        (1, "liberator"),
-        (1, "raven"),
-        (1, "banshee"),
-        (1, "viking"),
-        (1, "battlecruiser"),
-        (1, "phoenix"),
-        (1, "void ray"),
-        (1, "carrier"),
-        (1, "tempest"),
-        (1, "oracle"),
-        (1, "mutalisk"),
-        (1, "viper"),
-        (1, "corruptor"),
-        (1, "brood lord"),
-        (1, "overlord"),
-        (1, "overseer"),
-        (1, "pupper"),
-        (1, "pawn"),
-        (1, "knight"),
-        (1, "rook"),
-        (1, "king")
-    )
-}
-
-fn nominative_pronoun() -> String {
-    weighted_choice!(
-        (1, "I"),
-        (1, "you"),
-        (1, "he"),
-        (1, "she"),
-        (1, "they"),
-        (1, "we")
-    )
-}
-
-fn accusative_pronoun() -> String {
-    weighted_choice!(
-        (1, "me"),
-        (1, "everyone"),
-        (1, "her"),
-        (1, "him"),
-        (1, "them"),
-        (1, "us")
-    )
-}
-
-fn nouned_verb() -> String {
-    weighted_choice!(
-        (1, "affection"),
-        (1, "approval"),
-        (1, "embrace"),
-        (1, "honour"),
-        (1, "love"),
-        (1, "respect"),
-        (1, "satisfaction")
-    )
-}
-
-fn adjective_phrase() -> String {
-    weighted_choice_fn!(
-        (5, adjective),
-        (1, || -> String {
-            format!("{} and {}", adjective_phrase(), adjective_phrase())
-        }),
-        (3, || -> String {
-            format!("{} {}", intensifier(), adjective())
-        })
-    )()
-}
+    fn limits(&self) -> &'static [Limit] {
+        LIMITS
+    }
 
-fn pos_intensifier() -> String {
-    weighted_choice!(
-        (1, "arbitrarily"),
-        (1, "mildly"),
-        (1, "moderately"),
-        (1, "quite"),
-        (1, "really"),
-        (1, "somewhat"),
-        (1, "very")
-    )
-}
-
-fn intensifier() -> String {
-    weighted_choice_fn!(
-        (2, pos_intensifier),
-        (1, || -> String { format!("not {}", pos_intensifier()) })
-    )()
-}
-
-fn adjective() -> String {
-    weighted_choice!(
-        (1, "purple"),
-        (1, "green"),
-        (1, "orange"),
-        (1, "red"),
-        (1, "blue"),
-        (1, "yellow"),
-        (1, "pink"),
-        (1, "ultraviolet"),
-        (1, "infrared"),
-        (1, "spotted"),
-        (1, "fluffy"),
-        (1, "adorable"),
-        (1, "terrified"),
-        (1, "excited"),
-        (1, "acceptable"),
-        (1, "catlike"),
-        (1, "doglike"),
-        (1, "playful"),
-        (1, "friendly"),
-        (1, "spiky"),
-        (1, "pointy"),
-        (1, "aerodynamic"),
-        (1, "checkered"),
-        (1, "mottled"),
-        (1, "two-dimensional"),
-        (1, "tetrahedral"),
-        (1, "triangular"),
-        (1, "aggressive"),
-        (1, "spherical"),
-        (1, "cute"),
-        (1, "differentiable"),
-        (1, "open-source"),
-        (1, "agreeable"),
-        (1, "disagreeable"),
-        (1, "tubular"),
-        (1, "toroidal"),
-        (1, "speckled"),
-        (1, "simply connected")
-    )
-}
-
-fn article() -> String {
-    weighted_choice!((1, "the"), (1, "some"), (1, "a"))
-}
-
-fn predicate() -> String {
-    weighted_choice_fn!(
-        (1, || -> String {
-            format!("{} {}", transitive_verb(), object())
-        }),
-        (1, intransitive_verb)
-    )()
-}
-
-fn present_verb() -> String {
-    weighted_choice!(
-        (1, "bless"),
-        (1, "contradict"),
-        (1, "counter"),
-        (1, "defeat"),
-        (1, "discover"),
-        (1, "encourage"),
-        (1, "enlighten"),
-        (1, "excite"),
-        (1, "fascinate"),
-        (1, "fool"),
-        (1, "impress"),
-        (1, "intimidate"),
-        (1, "neutralise"),
-        (1, "outwit"),
-        (1, "please"),
-        (1, "satisfy"),
-        (1, "uplift"),
-        (1, "vanquish"),
-        (1, "vaporise")
-    )
-}
-
-fn present_verb_phrase() -> String {
-    weighted_choice_fn!(
-        (7, present_verb),
-        (1, || -> String { format!("obtain {} from", object()) })
-    )()
-}
-
-fn transitive_verb() -> String {
-    weighted_choice!(
-        (1, "advanced upon"),
-        (1, "asked"),
-        (1, "argued with"),
-        (1, "attacked"),
-        (1, "begged"),
-        (1, "betrayed"),
-        (1, "bothered"),
-        (1, "captured"),
-        (1, "chastised"),
-        (1, "commanded"),
-        (1, "confessed to"),
-        (1, "deceived"),
-        (1, "entertained"),
-        (1, "excommunicated"),
-        (1, "fought"),
-        (1, "helped"),
-        (1, "hugged"),
-        (1, "promoted"),
-        (1, "obeyed"),
-        (1, "threatened"),
-        (1, "told")
-    )
-}
-
-fn intransitive_verb() -> String {
-    weighted_choice!(
-        (1, "demanded it be this way"),
-        (1, "evaporated"),
-        (1, "had a good feeling about it"),
-        (1, "insisted on it"),
-        (1, "knew it was a good idea"),
-        (1, "sublimated"),
-        (1, "suggested it"),
-        (1, "told me to"),
-        (1, "wanted it"),
-        (2, "exploded")
-    )
-}
-
-fn object() -> String {
-    weighted_choice_fn!(
-        (1, accusative_pronoun),
-        (1, || -> String {
-            let n = noun_phrase();
-            let a = article();
-            if a == "a" {
-                let c = n.chars().next().unwrap();
-                match c {
-                    'a' | 'e' | 'i' | 'o' | 'u' => return format!("an {}", n),
-                    _ => (),
+    fn parse(&self, args: &str) -> Option<()> {
+        if args.is_empty() {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    async fn run(&self, inv: &Invocation<'_>, _args: ()) -> Vec<Reply> {
+        vec![Reply::text(why(inv.locale))]
+    }
+}
+
+pub const NO_REASONS_PATH: &str = "assets/no_reasons.json";
+
+/// The reasons `puppy no` picks from, which `puppy admin reload reasons`
+/// can swap out while the bot runs.
+#[derive(Clone, Default)]
+pub struct NoReasons {
+    reasons: Arc<RwLock<Vec<String>>>,
+}
+
+impl TypeMapKey for NoReasons {
+    type Value = NoReasons;
+}
+
+impl NoReasons {
+    /// Reads the reasons from [`NO_REASONS_PATH`].
+    pub fn load() -> anyhow::Result<Self> {
+        let reasons = NoReasons::default();
+        reasons.reload()?;
+        Ok(reasons)
+    }
+
+    /// Re-reads [`NO_REASONS_PATH`], keeping the old reasons if it's broken,
+    /// and returns how many there are now.
+    pub fn reload(&self) -> anyhow::Result<usize> {
+        let contents = std::fs::read_to_string(NO_REASONS_PATH)
+            .with_context(|| format!("Failed to read {NO_REASONS_PATH}"))?;
+        let reasons: Vec<String> = serde_json::from_str(&contents)
+            .with_context(|| format!("Invalid JSON in {NO_REASONS_PATH}"))?;
+        anyhow::ensure!(!reasons.is_empty(), "{NO_REASONS_PATH} has no reasons");
+        let count = reasons.len();
+        *self.reasons.write().unwrap() = reasons;
+        Ok(count)
+    }
+
+    fn choose(&self) -> Option<String> {
+        self.reasons
+            .read()
+            .unwrap()
+            .choose(&mut rand::rng())
+            .cloned()
+    }
+}
+
+/// Picks a random reason from [`NoReasons`].
+pub struct NoCommand;
+
+#[async_trait]
+impl Command for NoCommand {
+    type Args = ();
+
+    fn name(&self) -> &'static str {
+        "no"
+    }
+
+    fn description(&self, locale: Locale) -> String {
+        locale.text("description-no", &[])
+    }
+
+    fn rerun_on_edit(&self) -> bool {
+        true
+    }
+
+    fn limits(&self) -> &'static [Limit] {
+        LIMITS
+    }
+
+    fn parse(&self, args: &str) -> Option<()> {
+        if args.is_empty() {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    async fn run(&self, inv: &Invocation<'_>, _args: ()) -> Vec<Reply> {
+        let reason = inv.shared::<NoReasons>().await.choose();
+        vec![Reply::text(reason.unwrap_or_else(|| why(inv.locale)))]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_locale_makes_excuses() {
+        for locale in Locale::ALL {
+            let grammar = locale.grammar();
+            assert!(grammar.contains_key("excuse"), "{}", locale);
+            for (name, alternatives) in grammar {
+                assert!(!alternatives.is_empty(), "{} {}", locale, name);
+                for alternative in alternatives {
+                    // Every rule referred to exists.
+                    let text = fill(alternative.text(), |rule| {
+                        assert!(grammar.contains_key(rule), "{} {} {}", locale, name, rule);
+                        Some(String::new())
+                    });
+                    assert!(!text.contains('{'), "{} {}", locale, name);
                 }
             }
-            format!("{} {}", a, n)
-        })
-    )()
+            for _ in 0..100 {
+                assert!(!why(locale).is_empty());
+            }
+        }
+    }
 }
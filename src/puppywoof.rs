@@ -0,0 +1,74 @@
+use crate::commands::{Command, Invocation, Reply};
+use crate::locale::Locale;
+use lazy_static::lazy_static;
+use regex::Regex;
+use serenity::async_trait;
+
+/// Echos any message that consists entirely of woofs.
+pub struct WoofCommand;
+
+#[async_trait]
+impl Command for WoofCommand {
+    type Args = String;
+
+    fn name(&self) -> &'static str {
+        "woof"
+    }
+
+    fn description(&self, locale: Locale) -> String {
+        locale.text("description-woof", &[])
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &["woof", "awoooo", "汪汪"]
+    }
+
+    fn parse(&self, content: &str) -> Option<String> {
+        lazy_static! {
+            static ref WOOF_RE: Regex = Regex::new(
+                r"^((oua+f+\s*)+|(w(a|o|0|u|🌕)+r*f\s*)+|(aw+(o|0|🌕)+\s*)+|(b(a|o)+rk\s*)+|(汪\s*)+|(ワン\s*)+|(わん\s*)+|(гав\s*)+|(uowhf\s*)+|(arflee+bloo+\s*)+)+(!|！)*$"
+            )
+            .unwrap();
+        }
+        if WOOF_RE.is_match(&content.to_lowercase()) {
+            Some(content.to_string())
+        } else {
+            None
+        }
+    }
+
+    async fn run(&self, _inv: &Invocation<'_>, woof: String) -> Vec<Reply> {
+        vec![Reply::text(woof)]
+    }
+}
+
+pub struct HowCommand;
+
+#[async_trait]
+impl Command for HowCommand {
+    type Args = ();
+
+    fn name(&self) -> &'static str {
+        "how"
+    }
+
+    fn description(&self, locale: Locale) -> String {
+        locale.text("description-how", &[])
+    }
+
+    fn rerun_on_edit(&self) -> bool {
+        true
+    }
+
+    fn parse(&self, args: &str) -> Option<()> {
+        if args.is_empty() {
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    async fn run(&self, _inv: &Invocation<'_>, _args: ()) -> Vec<Reply> {
+        vec![Reply::text("https://github.com/dllu/discord-woofer-rust")]
+    }
+}
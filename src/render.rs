@@ -0,0 +1,55 @@
+use anyhow::anyhow;
+use lazy_static::lazy_static;
+use resvg::usvg::fontdb::Database;
+use resvg::{tiny_skia, usvg};
+use serenity::builder::CreateAttachment;
+
+lazy_static! {
+    /// Loading the system fonts takes a while, so it's only done once.
+    static ref FONTS: Database = {
+        let mut fonts = Database::new();
+        fonts.load_system_fonts();
+        fonts.set_monospace_family("DejaVu Sans Mono");
+        tracing::debug!(faces = fonts.len(), "loaded fonts");
+        fonts
+    };
+}
+
+/// Renders `svg` to PNG bytes.
+pub fn png(svg: &str) -> anyhow::Result<Vec<u8>> {
+    let tree = usvg::Tree::from_str(svg, &usvg::Options::default(), &FONTS)?;
+    let size = tree.size().to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+        .ok_or_else(|| anyhow!("couldn't allocate pixmap"))?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::identity(),
+        &mut pixmap.as_mut(),
+    );
+    Ok(pixmap.encode_png()?)
+}
+
+/// Renders `svg` off the async runtime, as an attachment that embeds can
+/// show with `attachment://{filename}`.
+pub async fn attachment(svg: String, filename: &str) -> anyhow::Result<CreateAttachment> {
+    let png = tokio::task::spawn_blocking(move || png(&svg)).await??;
+    Ok(CreateAttachment::bytes(png, filename))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_png() {
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg" width="30" height="20">
+            <rect width="30" height="20" fill="#e6dabc"/></svg>"##;
+        let png = png(svg).unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        let pixmap = tiny_skia::Pixmap::decode_png(&png).unwrap();
+        assert_eq!((pixmap.width(), pixmap.height()), (30, 20));
+        assert_eq!(pixmap.pixel(0, 0).unwrap().red(), 0xe6);
+
+        assert!(super::png("not svg").is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How often and how patiently to retry a request that failed in a way
+/// that might go away: timeouts, refused connections, 5xx and 429.
+#[derive(Clone, Copy, Debug)]
+pub struct Retry {
+    /// Tries in total, including the first.
+    pub attempts: u32,
+    /// Wait before the first retry, doubled for each one after it.
+    pub base_delay: Duration,
+    /// Longest wait between tries. A `Retry-After` longer than this isn't
+    /// worth keeping the user waiting for, so the response is returned.
+    pub max_delay: Duration,
+    /// How long each try may take.
+    pub timeout: Duration,
+}
+
+impl Retry {
+    #[allow(dead_code)]
+    pub const DEFAULT: Retry = Retry {
+        attempts: 3,
+        base_delay: Duration::from_millis(500),
+        max_delay: Duration::from_secs(10),
+        timeout: Duration::from_secs(10),
+    };
+
+    /// How long to wait after try number `attempt` (counting from 1), or
+    /// `None` if that was the last one.
+    pub fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if attempt >= self.attempts {
+            return None;
+        }
+        match retry_after {
+            Some(wait) if wait > self.max_delay => None,
+            Some(wait) => Some(wait),
+            None => Some(self.backoff(attempt)),
+        }
+    }
+
+    /// Exponential backoff, with the upper half jittered so that everyone
+    /// who failed together doesn't retry together.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(self.max_delay);
+        ceiling / 2 + ceiling.mul_f64(random_fraction() / 2.0)
+    }
+}
+
+/// A number in `[0, 1)`. Randomly keyed hashers are good enough for jitter.
+fn random_fraction() -> f64 {
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Stops sending requests to an upstream that keeps failing, so users hear
+/// that it's down right away instead of after every retry has timed out.
+pub struct Breaker {
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Failures in a row.
+    failures: u32,
+    /// Requests are refused until then.
+    open_until: Option<Instant>,
+}
+
+impl Default for Breaker {
+    fn default() -> Self {
+        Breaker::new(5, Duration::from_secs(30))
+    }
+}
+
+impl Breaker {
+    /// Opens after `threshold` failures in a row, for `cooldown`.
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Breaker {
+            threshold,
+            cooldown,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Whether a request may be sent. After the cooldown, one request is let
+    /// through to find out whether the upstream is back.
+    pub fn allow(&self) -> bool {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        match state.open_until {
+            Some(until) if until > now => false,
+            Some(_) => {
+                state.open_until = Some(now + self.cooldown);
+                true
+            }
+            None => true,
+        }
+    }
+
+    pub fn record(&self, failed: bool) {
+        let mut state = self.state.lock().unwrap();
+        if !failed {
+            *state = State::default();
+            return;
+        }
+        state.failures += 1;
+        if state.failures >= self.threshold {
+            state.open_until = Some(Instant::now() + self.cooldown);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backs_off_with_jitter() {
+        let retry = Retry {
+            attempts: 10,
+            ..Retry::DEFAULT
+        };
+        for _ in 0..100 {
+            let first = retry.delay(1, None).unwrap();
+            assert!((Duration::from_millis(250)..=Duration::from_millis(500)).contains(&first));
+            let third = retry.delay(3, None).unwrap();
+            assert!((Duration::from_secs(1)..=Duration::from_secs(2)).contains(&third));
+            let capped = retry.delay(8, None).unwrap();
+            assert!((Duration::from_secs(5)..=Duration::from_secs(10)).contains(&capped));
+        }
+        assert_eq!(
+            retry.delay(1, Some(Duration::from_secs(3))),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(retry.delay(1, Some(Duration::from_secs(60))), None);
+        assert_eq!(retry.delay(10, None), None);
+    }
+
+    #[test]
+    fn opens_after_repeated_failures() {
+        let breaker = Breaker::new(2, Duration::from_millis(20));
+        breaker.record(true);
+        assert!(breaker.allow());
+        breaker.record(true);
+        assert!(!breaker.allow());
+
+        // One request tries again after the cooldown, and a failure reopens.
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+        breaker.record(true);
+        assert!(!breaker.allow());
+
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(breaker.allow());
+        breaker.record(false);
+        assert!(breaker.allow());
+        assert!(breaker.allow());
+    }
+}
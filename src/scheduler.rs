@@ -0,0 +1,593 @@
+use crate::shutdown::Tracker;
+use crate::store::{Record, Store};
+use crate::transport::Transport;
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Timelike, Utc};
+use serde::{Deserialize, Serialize};
+use serenity::async_trait;
+use serenity::prelude::{RwLock, TypeMap, TypeMapKey};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// The longest the scheduler sleeps before looking at the clock again, so
+/// that it notices if the system clock jumps.
+const MAX_SLEEP: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// How long a one-shot job that failed waits before it's tried again.
+const RETRY_DELAY: Duration = Duration::minutes(5);
+
+/// How many times a one-shot job is tried before it's dropped.
+const MAX_ATTEMPTS: u32 = 3;
+
+/// What a job needs to talk to Discord and the rest of the bot.
+#[derive(Clone)]
+pub struct JobContext {
+    // Builds whose jobs don't post anything never read it.
+    #[allow(dead_code)]
+    pub transport: Arc<dyn Transport>,
+    pub data: Arc<RwLock<TypeMap>>,
+}
+
+/// Runs the jobs of one kind, e.g. `remind`. The payload is whatever the
+/// module passed when scheduling the job.
+#[async_trait]
+pub trait JobHandler: Send + Sync {
+    async fn run(&self, ctx: &JobContext, job: &Job) -> Result<()>;
+}
+
+/// A scheduled job as it is persisted.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Job {
+    pub id: u64,
+    /// Which [`JobHandler`] runs the job.
+    pub kind: String,
+    pub payload: serde_json::Value,
+    /// Cron expression of a recurring job, or `None` if it runs once.
+    pub cron: Option<String>,
+    pub next_run: DateTime<Utc>,
+    /// How many times a one-shot job has failed so far.
+    #[serde(default)]
+    pub failures: u32,
+}
+
+/// Every job, saved as a single record.
+#[derive(Serialize, Deserialize, Default)]
+struct Jobs {
+    next_id: u64,
+    jobs: Vec<Job>,
+}
+
+impl Record for Jobs {
+    const KIND: &'static str = "jobs";
+    const VERSION: u32 = 1;
+}
+
+const JOBS_KEY: &str = "all";
+
+/// Runs jobs at set times, in the background. Modules register a
+/// [`JobHandler`] for each kind of job at startup, and the jobs themselves
+/// are kept in the [`Store`] so that they survive restarts. Jobs that were
+/// due while the bot was down run once as soon as it's back. A job is only
+/// removed or moved to its next run once it has finished, so one that was
+/// cut short by a crash runs again.
+#[derive(Clone)]
+pub struct Scheduler {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    store: Store,
+    handlers: Mutex<HashMap<&'static str, Arc<dyn JobHandler>>>,
+    jobs: Mutex<Jobs>,
+    /// Ids of the jobs running now, which aren't started again meanwhile.
+    running: Mutex<HashSet<u64>>,
+    tracker: Tracker,
+    changed: Notify,
+    started: AtomicBool,
+}
+
+impl TypeMapKey for Scheduler {
+    type Value = Scheduler;
+}
+
+impl Scheduler {
+    /// Loads the jobs saved in `store`.
+    pub fn new(store: Store) -> Self {
+        let jobs = match store.get::<Jobs>(JOBS_KEY) {
+            Ok(jobs) => jobs.unwrap_or_default(),
+            Err(why) => {
+                tracing::error!("Error loading scheduled jobs: {why:?}");
+                Jobs::default()
+            }
+        };
+        Scheduler {
+            inner: Arc::new(Inner {
+                store,
+                handlers: Mutex::default(),
+                jobs: Mutex::new(jobs),
+                running: Mutex::default(),
+                tracker: Tracker::default(),
+                changed: Notify::new(),
+                started: AtomicBool::new(false),
+            }),
+        }
+    }
+
+    pub fn register(&self, kind: &'static str, handler: impl JobHandler + 'static) {
+        let mut handlers = self.inner.handlers.lock().unwrap();
+        handlers.insert(kind, Arc::new(handler));
+        self.inner.changed.notify_one();
+    }
+
+    /// Runs a `kind` job once at `at`.
+    #[allow(dead_code)]
+    pub fn schedule_once(
+        &self,
+        kind: &str,
+        at: DateTime<Utc>,
+        payload: impl Serialize,
+    ) -> Result<u64> {
+        self.add(kind, None, at, payload)
+    }
+
+    /// Runs a `kind` job whenever the cron expression matches, in UTC.
+    pub fn schedule_cron(&self, kind: &str, cron: &str, payload: impl Serialize) -> Result<u64> {
+        let next_run = Cron::parse(cron)?
+            .next_after(Utc::now())
+            .ok_or_else(|| anyhow!("{cron} never matches"))?;
+        self.add(kind, Some(cron.to_string()), next_run, payload)
+    }
+
+    fn add(
+        &self,
+        kind: &str,
+        cron: Option<String>,
+        next_run: DateTime<Utc>,
+        payload: impl Serialize,
+    ) -> Result<u64> {
+        let payload = serde_json::to_value(payload)?;
+        let id = self.update(|jobs| {
+            jobs.next_id += 1;
+            jobs.jobs.push(Job {
+                id: jobs.next_id,
+                kind: kind.to_string(),
+                payload,
+                cron,
+                next_run,
+                failures: 0,
+            });
+            jobs.next_id
+        })?;
+        self.inner.changed.notify_one();
+        Ok(id)
+    }
+
+    /// Removes the job, returning whether there was one.
+    #[allow(dead_code)]
+    pub fn cancel(&self, id: u64) -> Result<bool> {
+        self.update(|jobs| {
+            let before = jobs.jobs.len();
+            jobs.jobs.retain(|job| job.id != id);
+            jobs.jobs.len() != before
+        })
+    }
+
+    /// The jobs of `kind` that haven't run yet, soonest first.
+    pub fn jobs(&self, kind: &str) -> Vec<Job> {
+        let jobs = self.inner.jobs.lock().unwrap();
+        let mut jobs: Vec<Job> = jobs
+            .jobs
+            .iter()
+            .filter(|job| job.kind == kind)
+            .cloned()
+            .collect();
+        jobs.sort_by_key(|job| job.next_run);
+        jobs
+    }
+
+    /// Changes the jobs and saves them. Nothing changes if they can't be
+    /// saved.
+    fn update<T>(&self, change: impl FnOnce(&mut Jobs) -> T) -> Result<T> {
+        let mut jobs = self.inner.jobs.lock().unwrap();
+        let mut changed = Jobs {
+            next_id: jobs.next_id,
+            jobs: jobs.jobs.clone(),
+        };
+        let result = change(&mut changed);
+        self.inner.store.put(JOBS_KEY, &changed)?;
+        *jobs = changed;
+        Ok(result)
+    }
+
+    /// Starts running jobs in the background. Later calls do nothing, since
+    /// `ready` fires again whenever the bot reconnects.
+    pub fn start(&self, ctx: JobContext) {
+        if self.inner.started.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let scheduler = self.clone();
+        tokio::spawn(async move { scheduler.run(ctx).await });
+    }
+
+    /// Stops starting jobs and waits up to `timeout` for the running ones
+    /// to finish. Returns how many were still running when it gave up.
+    pub async fn stop(&self, timeout: std::time::Duration) -> usize {
+        let unfinished = self.inner.tracker.stop(timeout).await;
+        self.inner.changed.notify_one();
+        unfinished
+    }
+
+    async fn run(&self, ctx: JobContext) {
+        loop {
+            let changed = self.inner.changed.notified();
+            for job in self.due(Utc::now()) {
+                let handler = self
+                    .inner
+                    .handlers
+                    .lock()
+                    .unwrap()
+                    .get(job.kind.as_str())
+                    .cloned();
+                let (handler, active) = match (handler, self.inner.tracker.begin()) {
+                    (Some(handler), Some(active)) => (handler, active),
+                    (_, None) => return,
+                    (None, _) => continue,
+                };
+                self.inner.running.lock().unwrap().insert(job.id);
+                let scheduler = self.clone();
+                let ctx = ctx.clone();
+                tokio::spawn(async move {
+                    let _active = active;
+                    tracing::info!(job = job.id, kind = %job.kind, "running job");
+                    let result = handler.run(&ctx, &job).await;
+                    if let Err(why) = &result {
+                        tracing::error!(job = job.id, kind = %job.kind, "Error running job: {why:?}");
+                    }
+                    scheduler.finish(&job, result.is_ok(), Utc::now());
+                });
+            }
+            let sleep = self
+                .next_run()
+                .and_then(|next| (next - Utc::now()).to_std().ok())
+                .map_or(MAX_SLEEP, |until| until.min(MAX_SLEEP));
+            tokio::select! {
+                _ = tokio::time::sleep(sleep) => {}
+                _ = changed => {}
+            }
+        }
+    }
+
+    /// The jobs that are due and not already running. Jobs without a
+    /// handler wait until one is registered.
+    fn due(&self, now: DateTime<Utc>) -> Vec<Job> {
+        let handlers = self.inner.handlers.lock().unwrap();
+        let running = self.inner.running.lock().unwrap();
+        let jobs = self.inner.jobs.lock().unwrap();
+        jobs.jobs
+            .iter()
+            .filter(|job| job.next_run <= now && !running.contains(&job.id))
+            .filter(|job| handlers.contains_key(job.kind.as_str()))
+            .cloned()
+            .collect()
+    }
+
+    /// Removes `job` now that it has run, or moves it to its next run. A
+    /// one-shot job that failed is tried again a few times first.
+    fn finish(&self, job: &Job, succeeded: bool, now: DateTime<Utc>) {
+        let res = self.update(|jobs| {
+            // It may have been cancelled while it ran.
+            let index = match jobs.jobs.iter().position(|j| j.id == job.id) {
+                Some(index) => index,
+                None => return,
+            };
+            let next_run = match job.cron.as_deref() {
+                Some(cron) => match Cron::parse(cron) {
+                    Ok(cron) => cron.next_after(now),
+                    Err(why) => {
+                        tracing::error!(job = job.id, "Dropping job with bad cron: {why:?}");
+                        None
+                    }
+                },
+                None if succeeded => None,
+                None if job.failures + 1 >= MAX_ATTEMPTS => {
+                    tracing::error!(job = job.id, "Giving up on job");
+                    None
+                }
+                None => {
+                    jobs.jobs[index].failures += 1;
+                    Some(now + RETRY_DELAY)
+                }
+            };
+            match next_run {
+                Some(next_run) => jobs.jobs[index].next_run = next_run,
+                None => {
+                    jobs.jobs.remove(index);
+                }
+            }
+        });
+        match res {
+            Ok(()) => {
+                self.inner.running.lock().unwrap().remove(&job.id);
+            }
+            // It stays marked as running, so that it doesn't run again
+            // until the bot restarts.
+            Err(why) => tracing::error!(job = job.id, "Error saving scheduled jobs: {why:?}"),
+        }
+        self.inner.changed.notify_one();
+    }
+
+    /// When the next job with a handler is due.
+    fn next_run(&self) -> Option<DateTime<Utc>> {
+        let handlers = self.inner.handlers.lock().unwrap();
+        let jobs = self.inner.jobs.lock().unwrap();
+        jobs.jobs
+            .iter()
+            .filter(|job| handlers.contains_key(job.kind.as_str()))
+            .map(|job| job.next_run)
+            .min()
+    }
+}
+
+/// A cron expression: minute, hour, day of month, month and day of week,
+/// e.g. `30 9 * * 1-5` for 9:30 on weekdays. Each field is `*`, a number,
+/// a range like `1-5`, a step like `*/15` or `1-30/2`, or a list of those
+/// separated by commas. `@hourly`, `@daily`, `@weekly` and `@monthly` are
+/// shorthands.
+#[derive(Debug, PartialEq)]
+pub struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// As in cron, when both days of the month and of the week are given,
+    /// a day matching either is enough.
+    either_day: bool,
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Result<Cron> {
+        let expr = match expr.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            expr => expr,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            bail!("{expr} should have 5 fields");
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // Both 0 and 7 are Sunday.
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Cron {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            either_day: fields[2] != "*" && fields[4] != "*",
+        })
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        if self.either_day {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+
+    /// The first minute after `after` that matches, within the next few
+    /// years.
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let after = after.naive_utc();
+        let mut t =
+            after.date().and_hms_opt(after.hour(), after.minute(), 0)? + Duration::minutes(1);
+        let limit = t + Duration::days(5 * 366);
+        while t < limit {
+            let date = t.date();
+            if self.months & (1 << date.month()) == 0 {
+                let (year, month) = if date.month() == 12 {
+                    (date.year() + 1, 1)
+                } else {
+                    (date.year(), date.month() + 1)
+                };
+                t = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.matches_day(date) {
+                t = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if self.hours & (1 << t.hour()) == 0 {
+                t = date.and_hms_opt(t.hour(), 0, 0)? + Duration::hours(1);
+            } else if self.minutes & (1 << t.minute()) == 0 {
+                t += Duration::minutes(1);
+            } else {
+                return Some(t.and_utc());
+            }
+        }
+        None
+    }
+}
+
+/// Parses one cron field into a bit set of the values it allows.
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>()?),
+            None => (part, 1),
+        };
+        let (start, end) = match range {
+            "*" => (min, max),
+            range => match range.split_once('-') {
+                Some((start, end)) => (start.parse()?, end.parse()?),
+                None => {
+                    let value = range.parse()?;
+                    // `5/10` means from 5 to the end in steps of 10.
+                    (value, if part.contains('/') { max } else { value })
+                }
+            },
+        };
+        if step == 0 || start < min || end > max || start > end {
+            bail!("{part} is out of range {min}-{max}");
+        }
+        for value in (start..=end).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap()
+    }
+
+    #[test]
+    fn finds_next_cron_match() {
+        let weekdays = Cron::parse("30 9 * * 1-5").unwrap();
+        // 2026-10-16 is a Friday.
+        assert_eq!(
+            weekdays.next_after(at(2026, 10, 16, 9, 30)),
+            Some(at(2026, 10, 19, 9, 30))
+        );
+        assert_eq!(
+            weekdays.next_after(at(2026, 10, 16, 9, 29)),
+            Some(at(2026, 10, 16, 9, 30))
+        );
+
+        let quarterly = Cron::parse("*/15 * * * *").unwrap();
+        assert_eq!(
+            quarterly.next_after(at(2026, 12, 31, 23, 50)),
+            Some(at(2027, 1, 1, 0, 0))
+        );
+
+        let leap = Cron::parse("@daily").unwrap();
+        assert_eq!(leap, Cron::parse("0 0 * * *").unwrap());
+        assert_eq!(
+            Cron::parse("0 12 29 2 *")
+                .unwrap()
+                .next_after(at(2026, 3, 1, 0, 0)),
+            Some(at(2028, 2, 29, 12, 0))
+        );
+
+        assert!(Cron::parse("60 * * * *").is_err());
+        assert!(Cron::parse("* * *").is_err());
+    }
+
+    struct Recorder(tokio::sync::mpsc::UnboundedSender<serde_json::Value>);
+
+    #[async_trait]
+    impl JobHandler for Recorder {
+        async fn run(&self, _ctx: &JobContext, job: &Job) -> Result<()> {
+            let _ = self.0.send(job.payload.clone());
+            Ok(())
+        }
+    }
+
+    /// Fails once `release` is notified.
+    struct Stuck {
+        started: tokio::sync::mpsc::UnboundedSender<u64>,
+        release: Arc<Notify>,
+    }
+
+    #[async_trait]
+    impl JobHandler for Stuck {
+        async fn run(&self, _ctx: &JobContext, job: &Job) -> Result<()> {
+            let _ = self.started.send(job.id);
+            self.release.notified().await;
+            bail!("stuck")
+        }
+    }
+
+    fn context() -> JobContext {
+        JobContext {
+            transport: Arc::new(crate::transport::fake::FakeTransport::default()),
+            data: Arc::new(RwLock::new(TypeMap::new())),
+        }
+    }
+
+    /// Waits for the jobs of `kind` to be `ids`, which happens just after
+    /// the ones that ran finish.
+    async fn wait_for_jobs(scheduler: &Scheduler, kind: &str, ids: &[u64]) {
+        for _ in 0..500 {
+            let mut left: Vec<u64> = scheduler.jobs(kind).iter().map(|job| job.id).collect();
+            left.sort();
+            if left == ids {
+                return;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("jobs of {} never became {:?}", kind, ids);
+    }
+
+    #[tokio::test]
+    async fn runs_and_persists_jobs() {
+        let store = Store::memory();
+        let scheduler = Scheduler::new(store.clone());
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        scheduler.register("test", Recorder(tx));
+
+        let later = scheduler
+            .add("test", None, Utc::now() + Duration::hours(1), "later")
+            .unwrap();
+        let daily = scheduler.schedule_cron("test", "@daily", "daily").unwrap();
+        scheduler.add("test", None, Utc::now(), "now").unwrap();
+
+        scheduler.start(context());
+        let ran = tokio::time::timeout(std::time::Duration::from_secs(5), rx.recv()).await;
+        assert_eq!(ran.unwrap(), Some(serde_json::json!("now")));
+
+        // Only the jobs that haven't run yet are left, also after a restart.
+        wait_for_jobs(&scheduler, "test", &[later, daily]).await;
+        wait_for_jobs(&Scheduler::new(store), "test", &[later, daily]).await;
+    }
+
+    #[tokio::test]
+    async fn keeps_jobs_until_they_finish() {
+        let store = Store::memory();
+        let scheduler = Scheduler::new(store.clone());
+        let (started, mut running) = tokio::sync::mpsc::unbounded_channel();
+        let release = Arc::new(Notify::new());
+        let stuck = Stuck {
+            started,
+            release: release.clone(),
+        };
+        scheduler.register("test", stuck);
+        let now = Utc::now();
+        let id = scheduler.add("test", None, now, ()).unwrap();
+
+        scheduler.start(context());
+        let ran = tokio::time::timeout(std::time::Duration::from_secs(5), running.recv()).await;
+        assert_eq!(ran.unwrap(), Some(id));
+        // A crash now would run it again after a restart.
+        assert_eq!(Scheduler::new(store.clone()).jobs("test").len(), 1);
+
+        // Shutdown waits for it.
+        assert_eq!(
+            scheduler.stop(std::time::Duration::from_millis(10)).await,
+            1
+        );
+        release.notify_one();
+        assert_eq!(scheduler.stop(std::time::Duration::from_secs(5)).await, 0);
+
+        // It failed, so it's tried again later.
+        let job = Scheduler::new(store).jobs("test").remove(0);
+        assert_eq!(job.failures, 1);
+        assert!(
+            job.next_run > now + Duration::minutes(4),
+            "{}",
+            job.next_run
+        );
+    }
+}
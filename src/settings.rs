@@ -0,0 +1,416 @@
+use crate::commands::{split_word, Command, Invocation, Reply};
+use crate::locale::Locale;
+use crate::store::{Record, Store};
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, CommandOptionType, GuildId, RoleId};
+use serenity::async_trait;
+use serenity::builder::{CreateCommandOption, CreateEmbed};
+use serenity::prelude::TypeMapKey;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+use tokio::sync::{Mutex, RwLock};
+
+pub const DEFAULT_PREFIX: &str = "puppy";
+
+/// Commands that can't be disabled or restricted to channels, so that
+/// admins can't lock themselves out.
+const UNRESTRICTABLE: [&str; 2] = ["admin", "settings"];
+
+/// How the bot behaves in one guild, as configured by its admins.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct GuildSettings {
+    /// Replaces `puppy` in text commands.
+    pub prefix: Option<String>,
+    pub disabled: BTreeSet<String>,
+    /// Commands that may only run in the listed channels.
+    pub channels: BTreeMap<String, BTreeSet<ChannelId>>,
+    /// Members with any of these roles count as admins of the bot.
+    pub admin_roles: BTreeSet<RoleId>,
+    /// The language replies default to, unless a user picked their own.
+    pub locale: Option<Locale>,
+}
+
+impl Record for GuildSettings {
+    const KIND: &'static str = "guild_settings";
+    const VERSION: u32 = 1;
+}
+
+/// Why a command can't run in a channel.
+pub enum Denied {
+    Disabled,
+    OnlyIn(Vec<ChannelId>),
+}
+
+impl GuildSettings {
+    pub fn prefix(&self) -> &str {
+        self.prefix.as_deref().unwrap_or(DEFAULT_PREFIX)
+    }
+
+    pub fn allows(&self, command: &str, channel: ChannelId) -> Result<(), Denied> {
+        match self.restriction(command) {
+            Some(Denied::OnlyIn(channels)) if channels.contains(&channel) => Ok(()),
+            Some(denied) => Err(denied),
+            None => Ok(()),
+        }
+    }
+
+    /// How `command` is restricted in this guild, regardless of channel.
+    pub fn restriction(&self, command: &str) -> Option<Denied> {
+        if self.disabled.contains(command) {
+            Some(Denied::Disabled)
+        } else {
+            let channels = self.channels.get(command)?;
+            Some(Denied::OnlyIn(channels.iter().copied().collect()))
+        }
+    }
+}
+
+/// Per-guild settings, cached in memory and persisted in the [`Store`].
+#[derive(Clone)]
+pub struct Settings {
+    store: Store,
+    cache: Arc<RwLock<HashMap<GuildId, GuildSettings>>>,
+    /// Held while a guild's settings are being changed, so that
+    /// concurrent changes don't undo each other.
+    updating: Arc<std::sync::Mutex<HashMap<GuildId, Arc<Mutex<()>>>>>,
+}
+
+impl TypeMapKey for Settings {
+    type Value = Settings;
+}
+
+impl Settings {
+    pub fn new(store: Store) -> Self {
+        Settings {
+            store,
+            cache: Arc::default(),
+            updating: Arc::default(),
+        }
+    }
+
+    /// Settings of `guild`, or the defaults outside of guilds.
+    pub async fn get(&self, guild: Option<GuildId>) -> GuildSettings {
+        let guild = match guild {
+            Some(guild) => guild,
+            None => return GuildSettings::default(),
+        };
+        if let Some(settings) = self.cache.read().await.get(&guild) {
+            return settings.clone();
+        }
+        let settings = match self.store.get::<GuildSettings>(&guild.to_string()) {
+            Ok(settings) => settings.unwrap_or_default(),
+            Err(why) => {
+                tracing::error!(%guild, "Error loading settings: {why:?}");
+                GuildSettings::default()
+            }
+        };
+        self.cache.write().await.insert(guild, settings.clone());
+        settings
+    }
+
+    /// Changes the settings of `guild` and saves them.
+    pub async fn update(
+        &self,
+        guild: GuildId,
+        change: impl FnOnce(&mut GuildSettings),
+    ) -> anyhow::Result<GuildSettings> {
+        let lock = self
+            .updating
+            .lock()
+            .unwrap()
+            .entry(guild)
+            .or_default()
+            .clone();
+        let _updating = lock.lock().await;
+        let mut settings = self.get(Some(guild)).await;
+        change(&mut settings);
+        self.store.put(&guild.to_string(), &settings)?;
+        self.cache.write().await.insert(guild, settings.clone());
+        Ok(settings)
+    }
+}
+
+/// Lets guild admins change the prefix and restrict commands.
+pub struct SettingsCommand;
+
+pub enum SettingsArgs {
+    Show,
+    Prefix(String),
+    Enable(String),
+    Disable(String),
+    Channels(String, Vec<ChannelId>),
+    Admins(Vec<RoleId>),
+    Locale(String),
+}
+
+/// Parses `<#123>` or a bare channel id.
+fn parse_channel(s: &str) -> Option<ChannelId> {
+    let id = s
+        .strip_prefix("<#")
+        .and_then(|s| s.strip_suffix('>'))
+        .unwrap_or(s);
+    id.parse().ok().filter(|&id| id != 0).map(ChannelId::new)
+}
+
+/// Parses `<@&123>` or a bare role id.
+fn parse_role(s: &str) -> Option<RoleId> {
+    let id = s
+        .strip_prefix("<@&")
+        .and_then(|s| s.strip_suffix('>'))
+        .unwrap_or(s);
+    id.parse().ok().filter(|&id| id != 0).map(RoleId::new)
+}
+
+fn channel_list(channels: &BTreeSet<ChannelId>) -> String {
+    let mentions: Vec<String> = channels.iter().map(|c| format!("<#{c}>")).collect();
+    mentions.join(", ")
+}
+
+#[async_trait]
+impl Command for SettingsCommand {
+    type Args = SettingsArgs;
+
+    fn name(&self) -> &'static str {
+        "settings"
+    }
+
+    fn description(&self, locale: Locale) -> String {
+        locale.text("description-settings", &[])
+    }
+
+    fn usage(&self) -> &'static str {
+        "[prefix|enable|disable|channels|admins|locale] [value]"
+    }
+
+    fn examples(&self) -> &'static [&'static str] {
+        &[
+            "",
+            "prefix !",
+            "disable gpt",
+            "channels gpt",
+            "admins",
+            "locale de",
+        ]
+    }
+
+    /// Accepts nothing, `prefix <prefix>`, `enable <command>`,
+    /// `disable <command>`, `channels <command> [#channel...]` or
+    /// `admins [@role...]` or `locale <language>`.
+    fn parse(&self, args: &str) -> Option<SettingsArgs> {
+        let (action, rest) = split_word(args);
+        let (command, rest) = split_word(rest);
+        let command = command.to_lowercase();
+        match action.to_lowercase().as_str() {
+            "" | "show" => Some(SettingsArgs::Show),
+            "prefix" if !command.is_empty() && rest.is_empty() && command.len() <= 16 => {
+                Some(SettingsArgs::Prefix(command))
+            }
+            "enable" if !command.is_empty() && rest.is_empty() => {
+                Some(SettingsArgs::Enable(command))
+            }
+            "disable" if !command.is_empty() && rest.is_empty() => {
+                Some(SettingsArgs::Disable(command))
+            }
+            "channels" if !command.is_empty() => {
+                let channels = rest
+                    .split_whitespace()
+                    .map(parse_channel)
+                    .collect::<Option<_>>()?;
+                Some(SettingsArgs::Channels(command, channels))
+            }
+            "locale" if !command.is_empty() && rest.is_empty() => {
+                Some(SettingsArgs::Locale(command))
+            }
+            "admins" => {
+                let roles = split_word(args)
+                    .1
+                    .split_whitespace()
+                    .map(parse_role)
+                    .collect::<Option<_>>()?;
+                Some(SettingsArgs::Admins(roles))
+            }
+            _ => None,
+        }
+    }
+
+    fn options(&self) -> Vec<CreateCommandOption> {
+        let mut action = CreateCommandOption::new(
+            CommandOptionType::String,
+            "action",
+            Locale::En.text("option-settings-action", &[]),
+        );
+        for choice in [
+            "show", "prefix", "enable", "disable", "channels", "admins", "locale",
+        ] {
+            action = action.add_string_choice(choice, choice);
+        }
+        vec![
+            action,
+            CreateCommandOption::new(
+                CommandOptionType::String,
+                "value",
+                Locale::En.text("option-settings-value", &[]),
+            ),
+        ]
+    }
+
+    async fn run(&self, inv: &Invocation<'_>, args: SettingsArgs) -> Vec<Reply> {
+        let guild = match inv.guild_id {
+            Some(guild) => guild,
+            None => return vec![Reply::text(inv.locale.error("settings-guild-only", &[]))],
+        };
+        let settings = inv.shared::<Settings>().await;
+        if let SettingsArgs::Show = args {
+            return vec![show(inv.locale, &settings.get(Some(guild)).await)];
+        }
+        if !inv.is_admin().await {
+            return vec![Reply::text(inv.locale.error("settings-admin-only", &[]))];
+        }
+
+        let command = match &args {
+            SettingsArgs::Enable(name)
+            | SettingsArgs::Disable(name)
+            | SettingsArgs::Channels(name, _) => match inv.registry.canonical_name(name) {
+                Some(command) if UNRESTRICTABLE.contains(&command) => {
+                    return vec![Reply::text(
+                        inv.locale
+                            .error("settings-unrestrictable", &[("command", &command)]),
+                    )]
+                }
+                Some(command) => Some(command),
+                None => {
+                    return vec![Reply::text(
+                        inv.locale.error("no-such-command", &[("command", name)]),
+                    )]
+                }
+            },
+            _ => None,
+        };
+        let locale = match &args {
+            SettingsArgs::Locale(code) => match Locale::parse(code) {
+                Some(locale) => Some(locale),
+                None => {
+                    return vec![Reply::text(inv.locale.error(
+                        "locale-unknown",
+                        &[("locale", code), ("locales", &Locale::list())],
+                    ))];
+                }
+            },
+            _ => None,
+        };
+
+        let updated = settings
+            .update(guild, |s| match args {
+                SettingsArgs::Show => {}
+                SettingsArgs::Prefix(prefix) => {
+                    s.prefix = Some(prefix).filter(|p| p != DEFAULT_PREFIX);
+                }
+                SettingsArgs::Enable(_) => {
+                    s.disabled.remove(command.unwrap());
+                }
+                SettingsArgs::Disable(_) => {
+                    s.disabled.insert(command.unwrap().to_string());
+                }
+                SettingsArgs::Channels(_, channels) => {
+                    let command = command.unwrap().to_string();
+                    if channels.is_empty() {
+                        s.channels.remove(&command);
+                    } else {
+                        s.channels.insert(command, channels.into_iter().collect());
+                    }
+                }
+                SettingsArgs::Admins(roles) => {
+                    s.admin_roles = roles.into_iter().collect();
+                }
+                SettingsArgs::Locale(_) => {
+                    s.locale = locale.filter(|&l| l != Locale::default());
+                }
+            })
+            .await;
+        match updated {
+            Ok(updated) => vec![show(locale.unwrap_or(inv.locale), &updated)],
+            Err(why) => {
+                tracing::error!(%guild, "Error saving settings: {why:?}");
+                vec![Reply::text(inv.locale.error("settings-save-failed", &[]))]
+            }
+        }
+    }
+}
+
+fn show(locale: Locale, settings: &GuildSettings) -> Reply {
+    let disabled: Vec<&str> = settings.disabled.iter().map(String::as_str).collect();
+    let channels: Vec<String> = settings
+        .channels
+        .iter()
+        .map(|(command, channels)| format!("`{command}`: {}", channel_list(channels)))
+        .collect();
+    let roles: Vec<String> = settings
+        .admin_roles
+        .iter()
+        .map(|role| format!("<@&{role}>"))
+        .collect();
+    let or_none = |s: String| {
+        if s.is_empty() {
+            locale.text("none", &[])
+        } else {
+            s
+        }
+    };
+    let field = |key| locale.text(key, &[]);
+    Reply::embed(
+        CreateEmbed::new()
+            .title("puppy settings")
+            .field(
+                field("settings-prefix"),
+                format!("`{}`", settings.prefix()),
+                false,
+            )
+            .field(
+                field("settings-disabled"),
+                or_none(disabled.join(", ")),
+                false,
+            )
+            .field(
+                field("settings-channels"),
+                or_none(channels.join("\n")),
+                false,
+            )
+            .field(
+                field("settings-admin-roles"),
+                or_none(roles.join(", ")),
+                false,
+            )
+            .field(
+                field("settings-locale"),
+                format!("`{}`", settings.locale.unwrap_or_default()),
+                false,
+            ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn keeps_concurrent_changes() {
+        let settings = Settings::new(Store::memory());
+        let guild = GuildId::new(1000);
+        let changes = (0..50).map(|i| {
+            let settings = settings.clone();
+            tokio::spawn(async move {
+                settings
+                    .update(guild, |s| {
+                        s.disabled.insert(format!("command{i}"));
+                    })
+                    .await
+                    .unwrap();
+            })
+        });
+        for change in changes.collect::<Vec<_>>() {
+            change.await.unwrap();
+        }
+        assert_eq!(settings.get(Some(guild)).await.disabled.len(), 50);
+    }
+}
@@ -0,0 +1,101 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// How long shutdown waits for commands and jobs that are still running.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Counts the events being handled, and stops new ones once shutdown has
+/// begun.
+#[derive(Clone, Default)]
+pub struct Tracker {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    stopping: AtomicBool,
+    active: AtomicUsize,
+    idle: Notify,
+}
+
+/// Marks an event as being handled until dropped.
+pub struct Active {
+    inner: Arc<Inner>,
+}
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        if self.inner.active.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.inner.idle.notify_waiters();
+        }
+    }
+}
+
+impl Tracker {
+    /// Starts handling an event, or returns `None` if we're shutting down.
+    pub fn begin(&self) -> Option<Active> {
+        self.inner.active.fetch_add(1, Ordering::SeqCst);
+        let active = Active {
+            inner: self.inner.clone(),
+        };
+        if self.inner.stopping.load(Ordering::SeqCst) {
+            return None;
+        }
+        Some(active)
+    }
+
+    /// Refuses new events and waits up to `timeout` for the current ones to
+    /// finish. Returns how many were still running when it gave up.
+    pub async fn stop(&self, timeout: Duration) -> usize {
+        self.inner.stopping.store(true, Ordering::SeqCst);
+        let drained = async {
+            loop {
+                let idle = self.inner.idle.notified();
+                if self.inner.active.load(Ordering::SeqCst) == 0 {
+                    return;
+                }
+                idle.await;
+            }
+        };
+        let _ = tokio::time::timeout(timeout, drained).await;
+        self.inner.active.load(Ordering::SeqCst)
+    }
+}
+
+/// Resolves on SIGTERM or Ctrl-C.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+        tokio::select! {
+            _ = terminate.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_for_active_events() {
+        let tracker = Tracker::default();
+        let active = tracker.begin().unwrap();
+        assert_eq!(tracker.stop(Duration::from_millis(10)).await, 1);
+        assert!(tracker.begin().is_none());
+
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            drop(active);
+        });
+        assert_eq!(tracker.stop(Duration::from_secs(5)).await, 0);
+    }
+}
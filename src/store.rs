@@ -0,0 +1,275 @@
+use anyhow::Context as _;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serenity::prelude::TypeMapKey;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+/// A type that modules persist through the [`Store`].
+pub trait Record: Serialize + DeserializeOwned {
+    /// Namespace the records live under, e.g. `chess`.
+    const KIND: &'static str;
+
+    /// Bump whenever the serialized format changes incompatibly.
+    const VERSION: u32;
+
+    /// Upgrades a record written with an older `version`. Records that
+    /// can't be migrated are dropped.
+    fn migrate(version: u32, data: serde_json::Value) -> Option<Self> {
+        let _ = (version, data);
+        None
+    }
+}
+
+/// Raw byte storage behind a [`Store`]. Methods may block, e.g. on disk
+/// I/O; the store keeps that from stalling other tasks.
+pub trait Backend: Send + Sync {
+    fn load(&self, kind: &str, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
+    fn save(&self, kind: &str, key: &str, data: &[u8]) -> anyhow::Result<()>;
+    #[allow(dead_code)]
+    fn delete(&self, kind: &str, key: &str) -> anyhow::Result<()>;
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    version: u32,
+    data: T,
+}
+
+/// Keyed, versioned records that survive restarts.
+#[derive(Clone)]
+pub struct Store {
+    backend: Arc<dyn Backend>,
+}
+
+impl TypeMapKey for Store {
+    type Value = Store;
+}
+
+impl Store {
+    pub fn new(backend: impl Backend + 'static) -> Self {
+        Store {
+            backend: Arc::new(backend),
+        }
+    }
+
+    /// One JSON file per record under `dir/<kind>/<key>.json`.
+    pub fn json(dir: impl Into<PathBuf>) -> Self {
+        Store::new(JsonDir { dir: dir.into() })
+    }
+
+    /// Records that only last as long as the process.
+    pub fn memory() -> Self {
+        Store::new(Memory::default())
+    }
+
+    pub fn get<R: Record>(&self, key: &str) -> anyhow::Result<Option<R>> {
+        let data = match blocking(|| self.backend.load(R::KIND, key))? {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        let envelope: Envelope<serde_json::Value> = serde_json::from_slice(&data)
+            .with_context(|| format!("Corrupt {} record {key}", R::KIND))?;
+        if envelope.version == R::VERSION {
+            Ok(Some(serde_json::from_value(envelope.data)?))
+        } else {
+            Ok(R::migrate(envelope.version, envelope.data))
+        }
+    }
+
+    pub fn put<R: Record>(&self, key: &str, record: &R) -> anyhow::Result<()> {
+        let envelope = Envelope {
+            version: R::VERSION,
+            data: record,
+        };
+        let data = serde_json::to_vec_pretty(&envelope)?;
+        blocking(|| self.backend.save(R::KIND, key, &data))
+    }
+
+    #[allow(dead_code)]
+    pub fn delete<R: Record>(&self, key: &str) -> anyhow::Result<()> {
+        blocking(|| self.backend.delete(R::KIND, key))
+    }
+}
+
+struct JsonDir {
+    dir: PathBuf,
+}
+
+impl JsonDir {
+    fn path(&self, kind: &str, key: &str) -> PathBuf {
+        self.dir
+            .join(kind)
+            .join(format!("{}.json", encode_key(key)))
+    }
+}
+
+impl Backend for JsonDir {
+    fn load(&self, kind: &str, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.path(kind, key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(why) if why.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(why) => Err(why.into()),
+        }
+    }
+
+    fn save(&self, kind: &str, key: &str, data: &[u8]) -> anyhow::Result<()> {
+        let path = self.path(kind, key);
+        std::fs::create_dir_all(self.dir.join(kind))?;
+        // Write then rename so that a crash never leaves a truncated record.
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    fn delete(&self, kind: &str, key: &str) -> anyhow::Result<()> {
+        match std::fs::remove_file(self.path(kind, key)) {
+            Err(why) if why.kind() != std::io::ErrorKind::NotFound => Err(why.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Memory {
+    records: Mutex<HashMap<(String, String), Vec<u8>>>,
+}
+
+impl Backend for Memory {
+    fn load(&self, kind: &str, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let records = self.records.lock().unwrap();
+        Ok(records.get(&(kind.to_string(), key.to_string())).cloned())
+    }
+
+    fn save(&self, kind: &str, key: &str, data: &[u8]) -> anyhow::Result<()> {
+        let mut records = self.records.lock().unwrap();
+        records.insert((kind.to_string(), key.to_string()), data.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, kind: &str, key: &str) -> anyhow::Result<()> {
+        let mut records = self.records.lock().unwrap();
+        records.remove(&(kind.to_string(), key.to_string()));
+        Ok(())
+    }
+}
+
+/// Runs `f` in place, first handing this worker's other tasks to another
+/// thread if there is one. Outside of a multi-threaded runtime, e.g. at
+/// startup or in tests, `f` just runs.
+fn blocking<T>(f: impl FnOnce() -> T) -> T {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(f)
+        }
+        _ => f(),
+    }
+}
+
+/// Percent-encodes anything that isn't safe in a file name.
+fn encode_key(key: &str) -> String {
+    let mut encoded = String::new();
+    for byte in key.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Pup {
+        name: String,
+        good: bool,
+    }
+
+    impl Record for Pup {
+        const KIND: &'static str = "pup";
+        const VERSION: u32 = 2;
+
+        /// Version 1 was just the name.
+        fn migrate(version: u32, data: serde_json::Value) -> Option<Self> {
+            match version {
+                1 => Some(Pup {
+                    name: serde_json::from_value(data).ok()?,
+                    good: true,
+                }),
+                _ => None,
+            }
+        }
+    }
+
+    fn pup(name: &str) -> Pup {
+        Pup {
+            name: name.to_string(),
+            good: true,
+        }
+    }
+
+    fn save_raw(store: &Store, key: &str, version: u32, data: serde_json::Value) {
+        let data = serde_json::json!({ "version": version, "data": data });
+        store
+            .backend
+            .save(Pup::KIND, key, data.to_string().as_bytes())
+            .unwrap();
+    }
+
+    #[test]
+    fn migrates_old_versions() {
+        let store = Store::memory();
+        save_raw(&store, "rex", 1, serde_json::json!("Rex"));
+        assert_eq!(store.get::<Pup>("rex").unwrap(), Some(pup("Rex")));
+    }
+
+    #[test]
+    fn drops_unknown_versions() {
+        let store = Store::memory();
+        save_raw(&store, "future", 3, serde_json::json!({ "name": "Rex" }));
+        assert_eq!(store.get::<Pup>("future").unwrap(), None);
+
+        store.backend.save(Pup::KIND, "corrupt", b"{").unwrap();
+        assert!(store.get::<Pup>("corrupt").is_err());
+    }
+
+    #[test]
+    fn escapes_keys() {
+        assert_eq!(encode_key("1234-abc_DEF"), "1234-abc_DEF");
+        assert_eq!(encode_key("../etc/passwd"), "%2E%2E%2Fetc%2Fpasswd");
+        assert_eq!(encode_key("a b%"), "a%20b%25");
+        assert_eq!(encode_key("ü"), "%C3%BC");
+    }
+
+    // Multi-threaded, so that disk I/O goes through `block_in_place`.
+    #[tokio::test(flavor = "multi_thread")]
+    async fn round_trips_json_files() {
+        let dir = std::env::temp_dir().join(format!("woofer-store-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = Store::json(&dir);
+
+        assert_eq!(store.get::<Pup>("a/b").unwrap(), None);
+        store.put("a/b", &pup("Rex")).unwrap();
+        assert!(dir.join("pup").join("a%2Fb.json").exists());
+        // Another store over the same directory, as after a restart.
+        let reopened = Store::json(&dir);
+        assert_eq!(reopened.get::<Pup>("a/b").unwrap(), Some(pup("Rex")));
+
+        store.put("a/b", &pup("Fido")).unwrap();
+        assert_eq!(reopened.get::<Pup>("a/b").unwrap(), Some(pup("Fido")));
+        {
+            store.delete::<Pup>("a/b").unwrap();
+            assert_eq!(reopened.get::<Pup>("a/b").unwrap(), None);
+            // Deleting what's already gone is fine.
+            store.delete::<Pup>("a/b").unwrap();
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
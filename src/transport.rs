@@ -0,0 +1,455 @@
+use crate::commands::{Reply, Source};
+use serenity::all::{
+    ChannelId, CommandInteraction, ComponentInteraction, GuildId, MessageId, Permissions, UserId,
+};
+use serenity::async_trait;
+use serenity::builder::GetMessages;
+use serenity::cache::Cache;
+use serenity::http::{Http, Typing};
+use serenity::model::channel::Message;
+use serenity::model::channel::{Channel, MessageReference};
+use serenity::prelude::*;
+use std::sync::Arc;
+
+/// Everything the command handlers need from Discord, so that they can be
+/// driven by a fake in tests instead of a live gateway.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// The bot's own user id.
+    fn current_user_id(&self) -> UserId;
+
+    /// Up to `limit` messages in `channel`, newest first, optionally only
+    /// those sent before `before`.
+    #[allow(dead_code)]
+    async fn history(
+        &self,
+        channel: ChannelId,
+        before: Option<MessageId>,
+        limit: u8,
+    ) -> serenity::Result<Vec<Message>>;
+
+    /// Sends the replies to a command back to wherever it came from, and
+    /// returns the ids of the messages that made it.
+    async fn send(&self, source: &Source<'_>, replies: Vec<Reply>) -> Vec<MessageId>;
+
+    /// Replaces the content of one of the bot's messages with `reply`.
+    async fn edit(
+        &self,
+        channel: ChannelId,
+        message: MessageId,
+        reply: Reply,
+    ) -> serenity::Result<()>;
+
+    async fn delete(&self, channel: ChannelId, message: MessageId) -> serenity::Result<()>;
+
+    /// Sends `reply` to `channel` outside of any command, e.g. from a
+    /// scheduled job. Inline replies quote `reply_to`, or are sent as plain
+    /// messages if it was deleted.
+    #[allow(dead_code)]
+    async fn post(
+        &self,
+        channel: ChannelId,
+        reply_to: Option<MessageId>,
+        reply: Reply,
+    ) -> serenity::Result<MessageId>;
+
+    /// Shows the typing indicator in `channel` until the guard is dropped.
+    fn start_typing(&self, channel: ChannelId) -> Option<Typing>;
+
+    /// Acknowledges a slash command whose reply will take a while.
+    async fn defer(&self, command: &CommandInteraction);
+
+    /// Acknowledges a click on a component, so that replies can edit the
+    /// message it's on.
+    async fn acknowledge(&self, component: &ComponentInteraction);
+
+    /// Guild-wide permissions of `user`, if they can be determined.
+    async fn permissions(&self, guild: GuildId, user: UserId) -> Option<Permissions>;
+
+    /// The guild `channel` belongs to and what `user` may do in it, if it's
+    /// a guild channel and both can be determined.
+    #[allow(dead_code)]
+    async fn channel_permissions(
+        &self,
+        channel: ChannelId,
+        user: UserId,
+    ) -> Option<(GuildId, Permissions)>;
+}
+
+pub struct SerenityTransport {
+    http: Arc<Http>,
+    cache: Arc<Cache>,
+}
+
+impl SerenityTransport {
+    pub fn new(ctx: &Context) -> Self {
+        SerenityTransport {
+            http: ctx.http.clone(),
+            cache: ctx.cache.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for SerenityTransport {
+    fn current_user_id(&self) -> UserId {
+        self.cache.current_user().id
+    }
+
+    async fn history(
+        &self,
+        channel: ChannelId,
+        before: Option<MessageId>,
+        limit: u8,
+    ) -> serenity::Result<Vec<Message>> {
+        let mut builder = GetMessages::new().limit(limit);
+        if let Some(before) = before {
+            builder = builder.before(before);
+        }
+        channel.messages(&self.http, builder).await
+    }
+
+    async fn send(&self, source: &Source<'_>, replies: Vec<Reply>) -> Vec<MessageId> {
+        let mut ids = Vec::new();
+        match source {
+            Source::Message(msg) => {
+                for reply in replies {
+                    match msg
+                        .channel_id
+                        .send_message(&self.http, reply.into_message(Some((*msg).into())))
+                        .await
+                    {
+                        Ok(sent) => ids.push(sent.id),
+                        Err(why) => tracing::error!("Error sending message: {why:?}"),
+                    }
+                }
+            }
+            Source::Slash(command) => {
+                // The first reply replaces the deferred "thinking" response.
+                for (i, reply) in replies.into_iter().enumerate() {
+                    let res = if i == 0 {
+                        command.edit_response(&self.http, reply.into_edit()).await
+                    } else {
+                        command
+                            .create_followup(&self.http, reply.into_followup())
+                            .await
+                    };
+                    match res {
+                        Ok(sent) => ids.push(sent.id),
+                        Err(why) => {
+                            tracing::error!("Error sending interaction response: {why:?}")
+                        }
+                    }
+                }
+            }
+            Source::Component(component) => {
+                // The first public reply replaces the message the component
+                // is on.
+                let mut updated = false;
+                for reply in replies {
+                    let res = if reply.ephemeral {
+                        let followup = reply.into_followup().ephemeral(true);
+                        component.create_followup(&self.http, followup).await
+                    } else if !updated {
+                        updated = true;
+                        component.edit_response(&self.http, reply.into_edit()).await
+                    } else {
+                        component
+                            .create_followup(&self.http, reply.into_followup())
+                            .await
+                    };
+                    match res {
+                        Ok(sent) => ids.push(sent.id),
+                        Err(why) => {
+                            tracing::error!("Error sending interaction response: {why:?}")
+                        }
+                    }
+                }
+            }
+        }
+        ids
+    }
+
+    async fn edit(
+        &self,
+        channel: ChannelId,
+        message: MessageId,
+        reply: Reply,
+    ) -> serenity::Result<()> {
+        channel
+            .edit_message(&self.http, message, reply.into_edit_message())
+            .await
+            .map(|_| ())
+    }
+
+    async fn delete(&self, channel: ChannelId, message: MessageId) -> serenity::Result<()> {
+        channel.delete_message(&self.http, message).await
+    }
+
+    async fn post(
+        &self,
+        channel: ChannelId,
+        reply_to: Option<MessageId>,
+        reply: Reply,
+    ) -> serenity::Result<MessageId> {
+        let reference = reply_to.map(|message| {
+            let mut reference = MessageReference::from((channel, message));
+            reference.fail_if_not_exists = Some(false);
+            reference
+        });
+        let sent = channel
+            .send_message(&self.http, reply.into_message(reference))
+            .await?;
+        Ok(sent.id)
+    }
+
+    fn start_typing(&self, channel: ChannelId) -> Option<Typing> {
+        Some(channel.start_typing(&self.http))
+    }
+
+    async fn defer(&self, command: &CommandInteraction) {
+        if let Err(why) = command.defer(&self.http).await {
+            tracing::error!("Error deferring interaction: {why:?}");
+        }
+    }
+
+    async fn acknowledge(&self, component: &ComponentInteraction) {
+        if let Err(why) = component.defer(&self.http).await {
+            tracing::error!("Error acknowledging component: {why:?}");
+        }
+    }
+
+    async fn permissions(&self, guild: GuildId, user: UserId) -> Option<Permissions> {
+        let member = match guild.member((&self.cache, self.http.as_ref()), user).await {
+            Ok(member) => member,
+            Err(why) => {
+                tracing::warn!(%guild, %user, "Error fetching member: {why:?}");
+                return None;
+            }
+        };
+        let guild = guild.to_guild_cached(&self.cache)?;
+        Some(guild.member_permissions(&member))
+    }
+
+    async fn channel_permissions(
+        &self,
+        channel: ChannelId,
+        user: UserId,
+    ) -> Option<(GuildId, Permissions)> {
+        let channel = match channel.to_channel((&self.cache, self.http.as_ref())).await {
+            Ok(Channel::Guild(channel)) => channel,
+            Ok(_) => return None,
+            Err(why) => {
+                tracing::warn!(%channel, "Error fetching channel: {why:?}");
+                return None;
+            }
+        };
+        let guild = channel.guild_id;
+        let member = match guild.member((&self.cache, self.http.as_ref()), user).await {
+            Ok(member) => member,
+            Err(why) => {
+                tracing::warn!(%guild, %user, "Error fetching member: {why:?}");
+                return None;
+            }
+        };
+        let permissions = guild
+            .to_guild_cached(&self.cache)?
+            .user_permissions_in(&channel, &member);
+        Some((guild, permissions))
+    }
+}
+
+/// An in-memory [`Transport`] that records what the bot sends.
+#[cfg(test)]
+pub mod fake {
+    use super::*;
+    use serenity::all::User;
+    use serenity::model::channel::Message;
+    use serenity::model::Timestamp;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Mutex;
+
+    pub const BOT_ID: u64 = 1;
+    pub const CHANNEL_ID: u64 = 100;
+    pub const GUILD_ID: u64 = 1000;
+    /// A channel of some other guild.
+    pub const OTHER_GUILD_CHANNEL_ID: u64 = 200;
+    /// A channel of the test guild where only admins can send messages.
+    pub const READ_ONLY_CHANNEL_ID: u64 = 300;
+    /// Id of the first message the bot sends.
+    pub const FIRST_SENT_ID: u64 = 1_000_000;
+
+    #[derive(Default)]
+    pub struct FakeTransport {
+        /// Messages already in the channel, oldest first.
+        pub history: Mutex<Vec<Message>>,
+        pub sent: Mutex<Vec<Reply>>,
+        /// Ids handed out to sent messages so far.
+        pub sent_count: AtomicU64,
+        pub edited: Mutex<Vec<(MessageId, Reply)>>,
+        pub deleted: Mutex<Vec<MessageId>>,
+        /// Messages sent outside of commands, with the message they quote.
+        pub posted: Mutex<Vec<(ChannelId, Option<MessageId>, Reply)>>,
+        /// Users with the administrator permission.
+        pub admins: Vec<u64>,
+    }
+
+    impl FakeTransport {
+        pub fn take_sent(&self) -> Vec<Reply> {
+            std::mem::take(&mut *self.sent.lock().unwrap())
+        }
+
+        pub fn take_edited(&self) -> Vec<(MessageId, Reply)> {
+            std::mem::take(&mut *self.edited.lock().unwrap())
+        }
+    }
+
+    #[async_trait]
+    impl Transport for FakeTransport {
+        fn current_user_id(&self) -> UserId {
+            UserId::new(BOT_ID)
+        }
+
+        async fn history(
+            &self,
+            channel: ChannelId,
+            before: Option<MessageId>,
+            limit: u8,
+        ) -> serenity::Result<Vec<Message>> {
+            let history = self.history.lock().unwrap();
+            Ok(history
+                .iter()
+                .rev()
+                .filter(|m| m.channel_id == channel)
+                .filter(|m| before.is_none_or(|before| m.id < before))
+                .take(limit as usize)
+                .cloned()
+                .collect())
+        }
+
+        async fn send(&self, _source: &Source<'_>, replies: Vec<Reply>) -> Vec<MessageId> {
+            let count = replies.len() as u64;
+            let first = self.sent_count.fetch_add(count, Ordering::SeqCst) + FIRST_SENT_ID;
+            self.sent.lock().unwrap().extend(replies);
+            (first..first + count).map(MessageId::new).collect()
+        }
+
+        async fn edit(
+            &self,
+            _channel: ChannelId,
+            message: MessageId,
+            reply: Reply,
+        ) -> serenity::Result<()> {
+            self.edited.lock().unwrap().push((message, reply));
+            Ok(())
+        }
+
+        async fn delete(&self, _channel: ChannelId, message: MessageId) -> serenity::Result<()> {
+            self.deleted.lock().unwrap().push(message);
+            Ok(())
+        }
+
+        async fn post(
+            &self,
+            channel: ChannelId,
+            reply_to: Option<MessageId>,
+            reply: Reply,
+        ) -> serenity::Result<MessageId> {
+            let id = self.sent_count.fetch_add(1, Ordering::SeqCst) + FIRST_SENT_ID;
+            self.posted.lock().unwrap().push((channel, reply_to, reply));
+            Ok(MessageId::new(id))
+        }
+
+        fn start_typing(&self, _channel: ChannelId) -> Option<Typing> {
+            None
+        }
+
+        async fn defer(&self, _command: &CommandInteraction) {}
+
+        async fn acknowledge(&self, _component: &ComponentInteraction) {}
+
+        async fn permissions(&self, _guild: GuildId, user: UserId) -> Option<Permissions> {
+            if self.admins.contains(&user.get()) {
+                Some(Permissions::ADMINISTRATOR)
+            } else {
+                Some(Permissions::empty())
+            }
+        }
+
+        /// Every other channel is in the test guild, and anyone can send
+        /// messages there.
+        async fn channel_permissions(
+            &self,
+            channel: ChannelId,
+            user: UserId,
+        ) -> Option<(GuildId, Permissions)> {
+            let guild = match channel.get() {
+                OTHER_GUILD_CHANNEL_ID => GUILD_ID + 1,
+                _ => GUILD_ID,
+            };
+            let permissions = if self.admins.contains(&user.get()) {
+                Permissions::all()
+            } else if channel.get() == READ_ONLY_CHANNEL_ID {
+                Permissions::VIEW_CHANNEL
+            } else {
+                Permissions::VIEW_CHANNEL | Permissions::SEND_MESSAGES
+            };
+            Some((GuildId::new(guild), permissions))
+        }
+    }
+
+    pub fn user(id: u64, name: &str) -> User {
+        let mut user = User::default();
+        user.id = UserId::new(id);
+        user.name = name.to_string();
+        user
+    }
+
+    /// A message sent just now in the test channel of the test guild.
+    pub fn message(id: u64, author: User, content: &str) -> Message {
+        let mut msg = Message::default();
+        msg.id = MessageId::new(id);
+        msg.channel_id = ChannelId::new(CHANNEL_ID);
+        msg.guild_id = Some(GuildId::new(GUILD_ID));
+        msg.author = author;
+        msg.content = content.to_string();
+        msg.timestamp = Timestamp::now();
+        msg
+    }
+
+    /// A click on a component with `custom_id` in the test channel, choosing
+    /// `values` if it's a select menu.
+    #[allow(dead_code)]
+    pub fn component(author: User, custom_id: &str, values: &[&str]) -> ComponentInteraction {
+        let data = if values.is_empty() {
+            serde_json::json!({ "custom_id": custom_id, "component_type": 2 })
+        } else {
+            serde_json::json!({ "custom_id": custom_id, "component_type": 3, "values": values })
+        };
+        let interaction = serde_json::json!({
+            "id": "1",
+            "application_id": BOT_ID.to_string(),
+            "type": 3,
+            "data": data,
+            "guild_id": GUILD_ID.to_string(),
+            "channel_id": CHANNEL_ID.to_string(),
+            "user": author,
+            "token": "token",
+            "version": 1,
+            "message": message(FIRST_SENT_ID, user(BOT_ID, "puppy"), ""),
+            "locale": "en-US",
+            "entitlements": [],
+            "authorizing_integration_owners": {},
+            "attachment_size_limit": 8000000,
+        });
+        serde_json::from_value(interaction).unwrap()
+    }
+
+    /// Gives the author of `msg` the listed roles in the test guild.
+    pub fn with_roles(mut msg: Message, roles: &[u64]) -> Message {
+        let roles: Vec<String> = roles.iter().map(u64::to_string).collect();
+        let member = serde_json::json!({ "roles": roles });
+        msg.member = Some(Box::new(serde_json::from_value(member).unwrap()));
+        msg
+    }
+}
@@ -0,0 +1,637 @@
+use crate::cache::Cache;
+use crate::error::UserError;
+use crate::metrics::METRICS;
+use crate::retry::{Breaker, Retry};
+use anyhow::{anyhow, Context as _};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serenity::prelude::TypeMapKey;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The external APIs the bot talks to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Service {
+    Yahoo,
+    GoogleMaps,
+    OpenWeather,
+    Avwx,
+    OpenRouter,
+}
+
+impl Service {
+    pub const ALL: [Service; 5] = [
+        Service::Yahoo,
+        Service::GoogleMaps,
+        Service::OpenWeather,
+        Service::Avwx,
+        Service::OpenRouter,
+    ];
+
+    /// Name used in the config file and fixture paths.
+    pub fn name(self) -> &'static str {
+        match self {
+            Service::Yahoo => "yahoo",
+            Service::GoogleMaps => "google_maps",
+            Service::OpenWeather => "openweather",
+            Service::Avwx => "avwx",
+            Service::OpenRouter => "openrouter",
+        }
+    }
+
+    /// Name shown to users when the service is down.
+    pub fn label(self) -> &'static str {
+        match self {
+            Service::Yahoo => "Yahoo Finance",
+            Service::GoogleMaps => "Google Maps",
+            Service::OpenWeather => "OpenWeather",
+            Service::Avwx => "AVWX",
+            Service::OpenRouter => "OpenRouter",
+        }
+    }
+
+    fn default_base_url(self) -> &'static str {
+        match self {
+            Service::Yahoo => "https://query1.finance.yahoo.com",
+            Service::GoogleMaps => "https://maps.googleapis.com",
+            Service::OpenWeather => "https://api.openweathermap.org",
+            Service::Avwx => "https://avwx.rest",
+            Service::OpenRouter => "https://openrouter.ai",
+        }
+    }
+}
+
+/// How many responses [`Request::cache`] keeps across all services.
+const CACHE_CAPACITY: usize = 256;
+
+/// Whether requests go to the network, to the network and then to disk, or
+/// only to previously recorded fixtures.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Live,
+    Record,
+    Replay,
+}
+
+/// Shared HTTP client for every external API, with per-service base URLs
+/// so that the bot can be pointed at a mock server.
+#[derive(Clone)]
+pub struct Upstream {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    client: reqwest::Client,
+    base_urls: HashMap<Service, String>,
+    mode: Mode,
+    fixtures: PathBuf,
+    cache: Cache<Response>,
+    breakers: HashMap<Service, Breaker>,
+}
+
+impl TypeMapKey for Upstream {
+    type Value = Upstream;
+}
+
+impl Upstream {
+    pub fn new(
+        base_urls: &HashMap<String, String>,
+        mode: Mode,
+        fixtures: impl Into<PathBuf>,
+    ) -> anyhow::Result<Self> {
+        let mut urls = HashMap::new();
+        for service in Service::ALL {
+            let url = base_urls
+                .get(service.name())
+                .map(String::as_str)
+                .unwrap_or(service.default_base_url());
+            urls.insert(service, url.trim_end_matches('/').to_string());
+        }
+        if let Some(unknown) = base_urls
+            .keys()
+            .find(|name| !Service::ALL.iter().any(|s| s.name() == name.as_str()))
+        {
+            return Err(anyhow!("Unknown service {unknown} in base_urls"));
+        }
+
+        Ok(Upstream {
+            inner: Arc::new(Inner {
+                client: reqwest::Client::new(),
+                base_urls: urls,
+                mode,
+                fixtures: fixtures.into(),
+                cache: Cache::new(CACHE_CAPACITY),
+                breakers: Service::ALL
+                    .iter()
+                    .map(|&service| (service, Breaker::default()))
+                    .collect(),
+            }),
+        })
+    }
+
+    // Without any HTTP commands, nothing sends requests.
+    #[allow(dead_code)]
+    pub fn get(&self, service: Service, path: &str) -> Request {
+        self.request(Method::GET, service, path)
+    }
+
+    #[allow(dead_code)]
+    pub fn post(&self, service: Service, path: &str) -> Request {
+        self.request(Method::POST, service, path)
+    }
+
+    fn request(&self, method: Method, service: Service, path: &str) -> Request {
+        let url = format!("{}{}", self.inner.base_urls[&service], path);
+        Request {
+            upstream: self.clone(),
+            service,
+            builder: self.inner.client.request(method.clone(), url),
+            fixture_key: format!("{method} {path}"),
+            query: Vec::new(),
+            ttl: None,
+            retry: None,
+        }
+    }
+}
+
+pub struct Request {
+    upstream: Upstream,
+    service: Service,
+    builder: reqwest::RequestBuilder,
+    /// Identifies the request among the recorded fixtures. Secret query
+    /// parameters are left out so that fixtures don't contain API keys.
+    fixture_key: String,
+    query: Vec<(String, String)>,
+    ttl: Option<Duration>,
+    retry: Option<Retry>,
+}
+
+// Each command only needs some of these.
+#[allow(dead_code)]
+impl Request {
+    pub fn query(mut self, name: &str, value: impl ToString) -> Self {
+        let value = value.to_string();
+        self.fixture_key.push_str(&format!(" {name}={value}"));
+        self.query.push((name.to_string(), value));
+        self
+    }
+
+    /// A query parameter that is sent but never written to fixtures.
+    pub fn secret_query(mut self, name: &str, value: impl ToString) -> Self {
+        self.query.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn header(mut self, name: &str, value: impl AsRef<str>) -> Self {
+        self.builder = self.builder.header(name, value.as_ref());
+        self
+    }
+
+    /// Sends `body` as JSON. Requests with different bodies are different
+    /// fixtures, and never share a cached response.
+    pub fn json(mut self, body: &impl Serialize) -> Self {
+        let bytes = serde_json::to_vec(body).unwrap_or_default();
+        self.fixture_key
+            .push_str(&format!(" body={:016x}", fnv1a(&bytes)));
+        self.builder = self.builder.json(body);
+        self
+    }
+
+    /// Reuses a successful response to the same request for `ttl`, and
+    /// sends identical requests that are made at the same time only once.
+    /// Secret query parameters aren't part of what makes requests identical.
+    pub fn cache(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Retries transient failures according to `policy`, and gives up
+    /// right away while the service's circuit breaker is open.
+    pub fn retry(mut self, policy: Retry) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    pub async fn send(self) -> anyhow::Result<Response> {
+        let ttl = match self.ttl {
+            Some(ttl) => ttl,
+            None => return self.send_uncached().await,
+        };
+        let (upstream, service) = (self.upstream.clone(), self.service);
+        let key = format!("{} {}", service.name(), self.fixture_key);
+        let mut fetched = false;
+        let fetch = || {
+            fetched = true;
+            self.send_uncached()
+        };
+        let response = upstream
+            .inner
+            .cache
+            .get_or_fetch(&key, ttl, fetch, |r| r.status.is_success())
+            .await;
+        if !fetched {
+            METRICS.upstream_cache_hit(service.name());
+        }
+        response
+    }
+
+    async fn send_uncached(self) -> anyhow::Result<Response> {
+        let inner = &self.upstream.inner;
+        let path = inner
+            .fixtures
+            .join(self.service.name())
+            .join(format!("{}.json", fixture_name(&self.fixture_key)));
+
+        if inner.mode == Mode::Replay {
+            let fixture = std::fs::read(&path)
+                .with_context(|| format!("No fixture for {}", self.fixture_key))
+                .context(UserError::UpstreamDown(self.service.label()))?;
+            let fixture: Fixture = serde_json::from_slice(&fixture)?;
+            return fixture.into_response(self.service);
+        }
+
+        let builder = self.builder.query(&self.query);
+        let response = match self.retry {
+            Some(policy) => {
+                let breaker = &inner.breakers[&self.service];
+                fetch_with_retries(builder, self.service, policy, breaker).await
+            }
+            None => fetch(builder, self.service).await.map_err(Into::into),
+        };
+        let response = response.context(UserError::UpstreamDown(self.service.label()))?;
+
+        if inner.mode == Mode::Record {
+            let fixture = Fixture::from_response(&self.fixture_key, &response);
+            std::fs::create_dir_all(inner.fixtures.join(self.service.name()))?;
+            std::fs::write(&path, serde_json::to_vec_pretty(&fixture)?)?;
+        }
+        Ok(response)
+    }
+}
+
+async fn fetch_with_retries(
+    builder: reqwest::RequestBuilder,
+    service: Service,
+    policy: Retry,
+    breaker: &Breaker,
+) -> anyhow::Result<Response> {
+    let mut attempt = 1;
+    loop {
+        if !breaker.allow() {
+            return Err(anyhow!("Circuit breaker for {} is open", service.label()));
+        }
+        let request = builder
+            .try_clone()
+            .ok_or_else(|| anyhow!("Request body can't be retried"))?;
+        let result = fetch(request.timeout(policy.timeout), service).await;
+        let (retryable, retry_after) = match &result {
+            Ok(response) => (
+                response.status.is_server_error()
+                    || response.status == StatusCode::TOO_MANY_REQUESTS,
+                response.retry_after(),
+            ),
+            Err(why) => (why.is_timeout() || why.is_connect(), None),
+        };
+        // Only transient failures count against the service. Any other
+        // response means it's up, and other errors are the request's fault.
+        if result.is_ok() || retryable {
+            breaker.record(retryable);
+        }
+        let delay = if retryable {
+            policy.delay(attempt, retry_after)
+        } else {
+            None
+        };
+        let delay = match delay {
+            Some(delay) => delay,
+            None => return Ok(result?),
+        };
+        tracing::warn!(
+            service = service.name(),
+            attempt,
+            ?delay,
+            "Retrying: {}",
+            match &result {
+                Ok(response) => response.status.to_string(),
+                Err(why) => why.to_string(),
+            }
+        );
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
+
+async fn fetch(builder: reqwest::RequestBuilder, service: Service) -> reqwest::Result<Response> {
+    let start = Instant::now();
+    let response = async {
+        let response = builder.send().await?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let body = response.text().await?;
+        Ok(Response {
+            service,
+            status,
+            headers,
+            body,
+        })
+    }
+    .await;
+    let status = response.as_ref().ok().map(|r: &Response| r.status.as_u16());
+    METRICS.upstream_request(service.name(), status, start.elapsed());
+    response
+}
+
+#[derive(Clone)]
+pub struct Response {
+    pub service: Service,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+#[allow(dead_code)]
+impl Response {
+    /// Parses the body. A body that doesn't parse means the API changed or
+    /// is misbehaving, so users are told it's down.
+    pub fn json<T: DeserializeOwned>(&self) -> anyhow::Result<T> {
+        serde_json::from_str(&self.body)
+            .with_context(|| format!("Unexpected response: {}", self.body))
+            .context(UserError::UpstreamDown(self.service.label()))
+    }
+
+    /// How long the API asked us to wait before trying again.
+    pub fn retry_after(&self) -> Option<Duration> {
+        let secs = self.headers.get("retry-after")?.to_str().ok()?;
+        secs.parse().ok().map(Duration::from_secs)
+    }
+
+    /// Turns unsuccessful statuses into errors. Callers that expect
+    /// particular statuses, like 404 for unknown places, check those first.
+    pub fn error_for_status(self) -> anyhow::Result<Self> {
+        if self.status.is_success() {
+            return Ok(self);
+        }
+        let err = anyhow!(
+            "{} returned {}: {}",
+            self.service.label(),
+            self.status,
+            self.body
+        );
+        let user_error = if self.status == StatusCode::TOO_MANY_REQUESTS {
+            UserError::RateLimited(self.retry_after())
+        } else {
+            UserError::UpstreamDown(self.service.label())
+        };
+        Err(err.context(user_error))
+    }
+}
+
+/// A recorded response, stored as `<fixtures>/<service>/<request>.json`.
+#[derive(Serialize, Deserialize)]
+struct Fixture {
+    request: String,
+    status: u16,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    body: String,
+}
+
+impl Fixture {
+    fn from_response(request: &str, response: &Response) -> Self {
+        Fixture {
+            request: request.to_string(),
+            status: response.status.as_u16(),
+            headers: response
+                .headers
+                .iter()
+                .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.into())))
+                .collect(),
+            body: response.body.clone(),
+        }
+    }
+
+    fn into_response(self, service: Service) -> anyhow::Result<Response> {
+        let mut headers = HeaderMap::new();
+        for (name, value) in self.headers {
+            headers.insert(
+                HeaderName::from_bytes(name.as_bytes())?,
+                HeaderValue::from_str(&value)?,
+            );
+        }
+        Ok(Response {
+            service,
+            status: StatusCode::from_u16(self.status)?,
+            headers,
+            body: self.body,
+        })
+    }
+}
+
+/// Turns a request description into a readable file name.
+fn fixture_name(key: &str) -> String {
+    let mut name: String = key
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    // The readable part is only a hint, and may be cut short or clash; the
+    // hash of the whole key tells fixtures apart.
+    name.truncate(100);
+    format!("{name}-{:016x}", fnv1a(key.as_bytes()))
+}
+
+/// 64-bit FNV-1a, which unlike the std hashers is the same in every build,
+/// so fixture names stay valid.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+// The tests need weather's secret query parameters.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    /// Serves `body` to a single request on a local port and returns the
+    /// base URL along with a handle to the request line it received.
+    fn mock_server(body: &'static str) -> (String, std::thread::JoinHandle<String>) {
+        let (url, handle) = mock_responses(vec![("200 OK", body)]);
+        (
+            url,
+            std::thread::spawn(move || handle.join().unwrap().remove(0)),
+        )
+    }
+
+    /// Answers one request with each of `responses` in turn, and returns the
+    /// request lines it received.
+    fn mock_responses(
+        responses: Vec<(&'static str, &'static str)>,
+    ) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = [0; 4096];
+                let n = stream.read(&mut request).unwrap();
+                write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .unwrap();
+                let request = String::from_utf8_lossy(&request[..n]).into_owned();
+                requests.push(request.lines().next().unwrap().to_string());
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn records_and_replays_fixtures() {
+        let fixtures = std::env::temp_dir().join(format!("woofer-fixtures-{}", std::process::id()));
+        let (url, server) = mock_server(r#"{"sanitized":"KSEA 181853Z"}"#);
+        let base_urls = HashMap::from([("avwx".to_string(), url)]);
+
+        let recorder = Upstream::new(&base_urls, Mode::Record, &fixtures).unwrap();
+        let request = |upstream: &Upstream| {
+            upstream
+                .get(Service::Avwx, "/api/metar/KSEA")
+                .query("filter", "sanitized")
+                .secret_query("token", "hunter2")
+        };
+        let recorded = request(&recorder).send().await.unwrap();
+        assert_eq!(
+            server.join().unwrap(),
+            "GET /api/metar/KSEA?filter=sanitized&token=hunter2 HTTP/1.1"
+        );
+
+        // The mock server is gone, so this can only come from disk.
+        let replayer = Upstream::new(&base_urls, Mode::Replay, &fixtures).unwrap();
+        let replayed = request(&replayer).send().await.unwrap();
+        assert_eq!(replayed.status, StatusCode::OK);
+        assert_eq!(replayed.body, recorded.body);
+        assert_eq!(replayed.headers["content-type"], "application/json");
+
+        let saved = std::fs::read_dir(fixtures.join("avwx")).unwrap();
+        for entry in saved {
+            let contents = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+            assert!(!contents.contains("hunter2"));
+        }
+        assert!(request(&replayer).query("extra", 1).send().await.is_err());
+        std::fs::remove_dir_all(fixtures).unwrap();
+    }
+
+    #[test]
+    fn names_fixtures_by_the_whole_request() {
+        let long = format!("GET /{}", "a".repeat(200));
+        let longer = format!("{long}b");
+        assert_ne!(fixture_name(&long), fixture_name(&longer));
+        assert!(fixture_name(&longer).len() < 120);
+        assert_ne!(
+            fixture_name("POST /api body=1"),
+            fixture_name("POST /api body=2")
+        );
+        assert_eq!(
+            fixture_name("GET /api/metar/KSEA filter=sanitized"),
+            "GET__api_metar_KSEA_filter_sanitized-73a7a30866cab841"
+        );
+    }
+
+    #[tokio::test]
+    async fn caches_responses() {
+        // The mock server only answers once, so the rest must be cached.
+        let (url, server) = mock_server(r#"{"sanitized":"KSEA 181853Z"}"#);
+        let base_urls = HashMap::from([("avwx".to_string(), url)]);
+        let upstream = Upstream::new(&base_urls, Mode::Live, "fixtures").unwrap();
+        let request = || {
+            upstream
+                .get(Service::Avwx, "/api/metar/KSEA")
+                .cache(Duration::from_secs(60))
+        };
+        let (first, second) = tokio::join!(request().send(), request().send());
+        assert_eq!(first.unwrap().body, second.unwrap().body);
+        assert!(request().send().await.is_ok());
+        server.join().unwrap();
+
+        // Without a TTL it goes to the network, which is gone now.
+        let uncached = upstream.get(Service::Avwx, "/api/metar/KSEA").send();
+        assert!(uncached.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn keeps_query_values_apart_by_case() {
+        let (url, server) = mock_responses(vec![("200 OK", "upper"), ("200 OK", "lower")]);
+        let base_urls = HashMap::from([("yahoo".to_string(), url)]);
+        let upstream = Upstream::new(&base_urls, Mode::Live, "fixtures").unwrap();
+        let request = |ticker| {
+            upstream
+                .get(Service::Yahoo, "/quote")
+                .query("symbol", ticker)
+                .cache(Duration::from_secs(60))
+        };
+        assert_eq!(request("BRK").send().await.unwrap().body, "upper");
+        assert_eq!(request("brk").send().await.unwrap().body, "lower");
+        let requests = server.join().unwrap();
+        assert_eq!(requests[1], "GET /quote?symbol=brk HTTP/1.1");
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures() {
+        let (url, server) = mock_responses(vec![
+            ("503 Service Unavailable", "{}"),
+            ("429 Too Many Requests", "{}"),
+            ("200 OK", r#"{"sanitized":"KSEA 181853Z"}"#),
+            ("404 Not Found", "{}"),
+        ]);
+        let base_urls = HashMap::from([("avwx".to_string(), url)]);
+        let upstream = Upstream::new(&base_urls, Mode::Live, "fixtures").unwrap();
+        let policy = Retry {
+            base_delay: Duration::from_millis(1),
+            ..Retry::DEFAULT
+        };
+        let request = || upstream.get(Service::Avwx, "/api/metar/KSEA").retry(policy);
+
+        let response = request().send().await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        // Client errors aren't worth retrying.
+        let response = request().send().await.unwrap();
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        assert_eq!(server.join().unwrap().len(), 4);
+
+        // Nothing is listening anymore, so the breaker opens eventually and
+        // then refuses without trying.
+        for _ in 0..2 {
+            assert!(request().send().await.is_err());
+        }
+        let err = request().send().await.err().unwrap();
+        assert!(format!("{err:?}").contains("Circuit breaker for AVWX is open"));
+    }
+
+    #[tokio::test]
+    async fn ignores_bad_requests_in_breaker() {
+        let base_urls = HashMap::from([("avwx".to_string(), "ftp://localhost".to_string())]);
+        let upstream = Upstream::new(&base_urls, Mode::Live, "fixtures").unwrap();
+        for _ in 0..10 {
+            let request = upstream.get(Service::Avwx, "/api/metar/KSEA");
+            let err = request.retry(Retry::DEFAULT).send().await.err().unwrap();
+            assert!(!format!("{err:?}").contains("Circuit breaker"), "{:?}", err);
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_services() {
+        let base_urls = HashMap::from([("yahooo".to_string(), "http://x".to_string())]);
+        assert!(Upstream::new(&base_urls, Mode::Live, "fixtures").is_err());
+    }
+}
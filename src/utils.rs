@@ -1,17 +1,19 @@
 pub fn author_name_from_msg(msg: &serenity::all::Message) -> String {
-    let mut author_name: String = msg
-        .author
-        .global_name
-        .clone()
-        .unwrap_or(msg.author.name.clone());
+    let nick = msg
+        .member
+        .as_ref()
+        .and_then(|member| member.nick.as_deref());
+    author_name(&msg.author, nick)
+}
+
+pub fn author_name(user: &serenity::all::User, nick: Option<&str>) -> String {
+    let mut author_name: String = user.global_name.clone().unwrap_or(user.name.clone());
 
-    if let Some(member) = &msg.member {
-        if let Some(nick) = &member.nick {
-            author_name = nick.to_string();
-        }
+    if let Some(nick) = nick {
+        author_name = nick.to_string();
     }
 
-    if author_name == "Purple Puppy" && msg.author.name != "purplepuppy" {
+    if author_name == "Purple Puppy" && user.name != "purplepuppy" {
         return "Fake Deformed Purple Puppy".to_string();
     }
     author_name